@@ -0,0 +1,162 @@
+/*
+ * Audit - Keep a trace of the commands run by the clients, to know who did what
+ * Read-only commands are only recorded when configured to, since clients following jobs would flood the log
+ * Entries older than the retention period are removed every PRUNE_INTERVAL seconds
+ */
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+use serde_json::value::Value;
+
+use common::{Context, Result, ErrorKind};
+use common::structs::{Audit, AuditFilter};
+use database;
+use handler;
+
+/*
+ * Delay between two removals of the old entries, in seconds
+ */
+const PRUNE_INTERVAL: u64 = 3600;
+
+/*
+ * Fields whose name contains one of these are redacted from the recorded arguments
+ */
+const SENSITIVE: &'static [&'static str] = &["password", "passphrase", "secret", "token", "key", "credential"];
+
+const REDACTED: &'static str = "<redacted>";
+
+fn redact_value(value: &mut Value) {
+    match *value {
+        Value::Object(ref mut map) => {
+            for (k, v) in map.iter_mut() {
+                let k = k.to_lowercase();
+
+                if SENSITIVE.iter().any(|s| k.contains(s)) {
+                    *v = Value::String(REDACTED.to_string());
+                }
+                else {
+                    redact_value(v);
+                }
+            }
+        },
+        Value::Array(ref mut values) => {
+            for v in values.iter_mut() {
+                redact_value(v);
+            }
+        },
+        _ => {}
+    };
+}
+
+/*
+ * Remove the secrets from a command argument
+ * Arguments that are not JSON documents are names, and are kept as they are
+ */
+pub fn redact(arg: &str) -> String {
+    match serde_json::from_str::<Value>(arg) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            value.to_string()
+        },
+        Err(_) => arg.to_string()
+    }
+}
+
+fn is_read(cmd: &str) -> bool {
+    cmd.starts_with("list") || cmd.starts_with("get") || cmd.starts_with("status") || cmd == "statsvm" || cmd == "metrics"
+}
+
+fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(_) => 0
+    }
+}
+
+/*
+ * Append a command to the audit log
+ * Failing to do so does not fail the command, it has already run anyway
+ */
+pub fn record(ctx: &Context, client: &str, identity: &str, cmd: &str, arg: &str, job: &str, duration: Duration, res: &Result<String>) {
+    let denied = match *res {
+        Err(ref e) => e.kind() == ErrorKind::PermissionDenied,
+        Ok(_) => false
+    };
+
+    if is_read(cmd) && !ctx.conf.audit.reads && !denied {
+        return;
+    }
+
+    let (kind, object) = match handler::target(cmd, arg) {
+        Some((kind, name)) => (kind.to_string(), name),
+        None => (String::new(), String::new())
+    };
+
+    let entry = Audit {
+        node: ctx.conf.global.node,
+        time: now(),
+        client: client.to_string(),
+        identity: identity.to_string(),
+        command: cmd.to_string(),
+        kind: kind,
+        object: object,
+        argument: redact(arg),
+        job: job.to_string(),
+        success: res.is_ok(),
+        error: res.as_ref().err().map(|e| e.to_string()).unwrap_or(String::new()),
+        code: res.as_ref().err().map(|e| e.kind().code().to_string()).unwrap_or(String::new()),
+        duration: (duration.as_secs() * 1000 + (duration.subsec_nanos() / 1000000) as u64) as i64
+    };
+
+    if let Err(e) = database::audit::create(ctx, &entry) {
+        error!("audit", "failed to record command: {}", e; client = client, command = cmd);
+    }
+}
+
+/*
+ * Handle a 'listaudit' command, filters without a limit get the configured one
+ */
+pub fn list(ctx: &Context, obj: &str) -> Result<String> {
+    let mut filter = try!(AuditFilter::from_json(obj));
+    if filter.limit == 0 {
+        filter.limit = ctx.conf.audit.limit;
+    }
+
+    let entries = try!(database::audit::list(ctx, &filter));
+    let s = try!(serde_json::to_string(&entries));
+
+    Ok(s)
+}
+
+/*
+ * Remove the entries older than the retention period
+ */
+pub fn prune(ctx: &Context) -> Result<()> {
+    database::audit::prune(ctx, now() - ctx.conf.audit.retention as i64)
+}
+
+/*
+ * Remove the old entries periodically, unless they are kept forever
+ */
+pub fn run(ctx: Arc<Context>) {
+    if ctx.conf.audit.retention == 0 {
+        return;
+    }
+
+    loop {
+        if let Err(e) = prune(ctx.as_ref()) {
+            warn!("audit", "failed to remove old entries: {}", e);
+        }
+
+        thread::sleep(Duration::from_secs(PRUNE_INTERVAL));
+    }
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use serde_json;
+use serde_json::value::Value;
+
+use common::{Context, Error};
+use common::structs::AuditFilter;
+use database;
+
+use super::{redact, record, prune};
+use super::list as listaudit;
+
+fn context(reads: bool) -> Context {
+    Context::test(format!(r#"
+        backend = []
+
+        [global]
+        node = 1
+
+        [database]
+        type = "memory"
+
+        [audit]
+        reads = {}
+    "#, reads).as_str())
+}
+
+fn list(ctx: &Context, filter: &str) -> Vec<(String, String)> {
+    let filter = AuditFilter::from_json(filter).unwrap();
+    database::audit::list(ctx, &filter).unwrap().into_iter().map(|e| (e.command, e.object)).collect()
+}
+
+#[test]
+fn redact_secrets() {
+    let arg = r#"{"name": "test", "parameters": {"root_password": "hunter2", "size": "10G"}, "keys": [{"SSH_Key": "ssh-rsa"}]}"#;
+    let redacted: Value = serde_json::from_str(redact(arg).as_str()).unwrap();
+
+    assert_eq!(redacted["name"].as_str(), Some("test"));
+    assert_eq!(redacted["parameters"]["root_password"].as_str(), Some("<redacted>"));
+    assert_eq!(redacted["parameters"]["size"].as_str(), Some("10G"));
+    assert_eq!(redacted["keys"].as_str(), Some("<redacted>"));
+
+    // Names are kept as they are
+    assert_eq!(redact("test").as_str(), "test");
+    assert_eq!(redact("").as_str(), "");
+}
+
+#[test]
+fn record_commands() {
+    let ctx = context(false);
+    let ok = Ok(String::new());
+    let d = Duration::from_millis(1500);
+
+    record(&ctx, "test", "admin", "delvm", "web", "", d, &ok);
+    record(&ctx, "test", "admin", "createnet", r#"{"name": "lan"}"#, "", d, &Err(Error::already_exists("Network already exists")));
+
+    // Reads are only recorded when they are denied
+    record(&ctx, "test", "viewer", "listvm", "", "", d, &ok);
+    record(&ctx, "test", "viewer", "getvm", "web", "", d, &Err(Error::permission_denied("Denied")));
+
+    let entries = database::audit::list(&ctx, &AuditFilter::default()).unwrap();
+    assert_eq!(entries.len(), 3);
+
+    assert_eq!(entries[0].kind.as_str(), "vm");
+    assert_eq!(entries[0].object.as_str(), "web");
+    assert_eq!(entries[0].identity.as_str(), "admin");
+    assert_eq!(entries[0].duration, 1500);
+    assert!(entries[0].success);
+
+    assert_eq!(entries[1].object.as_str(), "lan");
+    assert_eq!(entries[1].code.as_str(), "already_exists");
+    assert!(!entries[1].success);
+
+    assert_eq!(entries[2].command.as_str(), "getvm");
+    assert_eq!(entries[2].code.as_str(), "permission_denied");
+
+    // Unless configured to
+    let ctx = context(true);
+    record(&ctx, "test", "viewer", "listvm", "", "", d, &ok);
+    assert_eq!(list(&ctx, "").len(), 1);
+}
+
+#[test]
+fn filters() {
+    let ctx = context(false);
+    let ok = Ok(String::new());
+    let d = Duration::from_millis(0);
+
+    record(&ctx, "test", "admin", "startvm", "web", "", d, &ok);
+    record(&ctx, "test", "admin", "stopvm", "web", "", d, &ok);
+    record(&ctx, "test", "admin", "delvm", "db", "", d, &ok);
+    record(&ctx, "test", "admin", "delnet", "web", "", d, &ok);
+
+    assert_eq!(list(&ctx, r#"{"object": "web"}"#).len(), 3);
+    assert_eq!(list(&ctx, r#"{"kind": "vm", "object": "web"}"#).len(), 2);
+    assert_eq!(list(&ctx, r#"{"command": "delvm"}"#), vec![(String::from("delvm"), String::from("db"))]);
+
+    // The latest entries are kept, in chronological order
+    assert_eq!(list(&ctx, r#"{"limit": 2}"#), vec![(String::from("delvm"), String::from("db")), (String::from("delnet"), String::from("web"))]);
+
+    assert_eq!(list(&ctx, r#"{"since": 1}"#).len(), 4);
+    assert_eq!(list(&ctx, r#"{"until": 1}"#).len(), 0);
+
+    assert!(AuditFilter::from_json("not json").is_err());
+}
+
+#[test]
+fn default_limit() {
+    let ctx = context(false);
+    let ok = Ok(String::new());
+
+    for i in 0..105 {
+        record(&ctx, "test", "admin", "delvm", format!("vm{}", i).as_str(), "", Duration::from_millis(0), &ok);
+    }
+
+    let entries: Value = serde_json::from_str(listaudit(&ctx, "").unwrap().as_str()).unwrap();
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), 100);
+    assert_eq!(entries[99]["object"].as_str(), Some("vm104"));
+
+    let entries: Value = serde_json::from_str(listaudit(&ctx, r#"{"limit": 103}"#).unwrap().as_str()).unwrap();
+    assert_eq!(entries.as_array().unwrap().len(), 103);
+}
+
+#[test]
+fn retention() {
+    let ctx = context(false);
+    let ok = Ok(String::new());
+
+    record(&ctx, "test", "admin", "startvm", "web", "", Duration::from_millis(0), &ok);
+    record(&ctx, "test", "admin", "stopvm", "web", "", Duration::from_millis(0), &ok);
+
+    // Recent entries are kept
+    prune(&ctx).unwrap();
+    assert_eq!(list(&ctx, "").len(), 2);
+
+    database::audit::prune(&ctx, i64::max_value()).unwrap();
+    assert_eq!(list(&ctx, "").len(), 0);
+}
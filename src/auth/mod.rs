@@ -0,0 +1,254 @@
+/*
+ * Authentication - Identify the clients of the control interfaces
+ *
+ * HTTP clients send one of the configured token secrets as a bearer token:
+ *
+ *     Authorization: Bearer <secret>
+ *
+ * UDP clients never send the secret, they wrap the command in a signed envelope instead:
+ *
+ *     auth <token name> <unix timestamp> <nonce> <signature> <command> <argument>
+ *
+ * The signature is the hex-encoded HMAC-SHA256 of "#<id> <token name> <unix timestamp> <nonce> <command> <argument>",
+ * keyed with the token secret, where <id> is the identifier of the request (empty without one).
+ * Envelopes more than MAX_SKEW seconds old are rejected, and so are the nonces already seen
+ * in that window, so that a captured envelope can not be replayed.
+ *
+ * Each token is given a role, which defines the commands it may run. Roles are either
+ * one of the built-in ones below, or custom roles defined in the configuration file.
+ *
+ * Clients of the Unix socket are not authenticated here, they are checked with their peer
+ * credentials and given the role configured for the socket.
+ */
+
+pub mod signature;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crypto::util::fixed_time_eq;
+use uuid::{Uuid, UuidVersion};
+
+use common::{Context, Result, Error};
+use config;
+
+use self::signature::{hmac, signed_data, seal};
+
+/*
+ * Maximum age of a signed envelope, in seconds
+ */
+const MAX_SKEW: u64 = 30;
+
+/*
+ * Maximum length of a nonce
+ */
+const MAX_NONCE: usize = 64;
+
+/*
+ * Built-in roles, the audit log is only listed by administrators
+ */
+const READ_ONLY: &'static [&'static str] = &["listimg", "listvm", "listnet", "listsnap", "listjob", "get*", "status*", "statsvm", "subscribe", "metrics"];
+const OPERATOR: &'static [&'static str] = &["listimg", "listvm", "listnet", "listsnap", "listjob", "get*", "status*", "statsvm", "subscribe", "metrics", "startvm", "stopvm", "createsnap", "restoresnap", "delsnap", "canceljob"];
+const ADMIN: &'static [&'static str] = &["*"];
+
+/*
+ * Authenticated client
+ */
+pub struct Identity {
+    pub name: String, // Name of the token used, or "anonymous" when authentication is disabled
+    pub role: String,
+    commands: Vec<String> // Commands allowed by the role
+}
+
+impl Identity {
+    /*
+     * Identity used when authentication is disabled, allowed to run any command
+     */
+    pub fn anonymous() -> Identity {
+        Identity {
+            name: String::from("anonymous"),
+            role: String::from("admin"),
+            commands: vec![String::from("*")]
+        }
+    }
+
+    fn new(auth: &config::Auth, token: &config::Token) -> Identity {
+        Identity {
+            name: token.name.clone(),
+            role: token.role.clone(),
+            commands: role_commands(Some(auth), token.role.as_str())
+        }
+    }
+
+    /*
+     * Identity of a client authenticated by other means, given a role from the configuration
+     */
+    pub fn with_role(ctx: &Context, name: &str, role: &str) -> Identity {
+        Identity {
+            name: name.to_string(),
+            role: role.to_string(),
+            commands: role_commands(ctx.conf.auth.as_ref(), role)
+        }
+    }
+
+    /*
+     * Check if the identity is allowed to run a command
+     */
+    pub fn allows(&self, cmd: &str) -> bool {
+        self.commands.iter().any(|c| {
+            if c.ends_with('*') {
+                cmd.starts_with(&c[..c.len() - 1])
+            }
+            else {
+                c.as_str() == cmd
+            }
+        })
+    }
+}
+
+/*
+ * Return the commands allowed by a role, looking for custom roles first
+ */
+fn role_commands(auth: Option<&config::Auth>, role: &str) -> Vec<String> {
+    if let Some(r) = auth.and_then(|a| a.get_role(role)) {
+        return r.commands.clone();
+    }
+
+    let builtin: &[&str] = match role {
+        "read-only" => READ_ONLY,
+        "operator" => OPERATOR,
+        "admin" => ADMIN,
+        _ => &[] // Unknown roles are rejected when the configuration is parsed
+    };
+
+    builtin.iter().map(|c| c.to_string()).collect()
+}
+
+fn now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0
+    }
+}
+
+/*
+ * Nonces of the envelopes accepted recently, by token, with their timestamp
+ */
+pub struct Nonces {
+    seen: Mutex<HashMap<(String, String), u64>>
+}
+
+impl Nonces {
+    pub fn new() -> Nonces {
+        Nonces {
+            seen: Mutex::new(HashMap::new())
+        }
+    }
+
+    /*
+     * Record a nonce, return false if it was already seen
+     * Nonces are forgotten once their envelope would be rejected as expired anyway
+     */
+    fn check(&self, name: &str, nonce: &str, timestamp: u64, now: u64) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, t| *t + MAX_SKEW >= now);
+
+        let key = (name.to_string(), nonce.to_string());
+        if seen.contains_key(&key) {
+            return false;
+        }
+
+        seen.insert(key, timestamp);
+        true
+    }
+}
+
+fn is_valid_nonce(nonce: &str) -> bool {
+    nonce.len() > 0 && nonce.len() <= MAX_NONCE && nonce.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/*
+ * Authenticate an HTTP client from its Authorization header
+ */
+pub fn bearer(ctx: &Context, header: Option<&str>) -> Result<Identity> {
+    let auth = match ctx.conf.auth {
+        Some(ref auth) => auth,
+        None => return Ok(Identity::anonymous())
+    };
+
+    let header = try!(header.ok_or(Error::unauthorized("Missing Authorization header")));
+    let secret = match header.find(' ') {
+        Some(i) if header[..i].eq_ignore_ascii_case("bearer") => header[i + 1..].trim(),
+        _ => return Err(Error::unauthorized("Invalid Authorization header, expected a bearer token"))
+    };
+
+    for token in &auth.token {
+        if fixed_time_eq(token.secret.as_bytes(), secret.as_bytes()) {
+            return Ok(Identity::new(auth, token));
+        }
+    }
+
+    Err(Error::unauthorized("Invalid token"))
+}
+
+/*
+ * Authenticate a UDP client from a signed envelope, return its identity and the wrapped command
+ * 'id' is the identifier of the request carrying the envelope, if any
+ */
+pub fn envelope(ctx: &Context, nonces: &Nonces, id: Option<&str>, data: &str) -> Result<(Identity, String)> {
+    let auth = match ctx.conf.auth {
+        Some(ref auth) => auth,
+        None => return Ok((Identity::anonymous(), data.to_string()))
+    };
+
+    let data = data.trim_left();
+    let mut parts = data.splitn(6, ' ');
+
+    if parts.next() != Some("auth") {
+        return Err(Error::unauthorized("Authentication required"));
+    }
+
+    let name = try!(parts.next().ok_or(Error::unauthorized("Invalid envelope: missing token name")));
+    let timestamp = try!(parts.next().ok_or(Error::unauthorized("Invalid envelope: missing timestamp")));
+    let nonce = try!(parts.next().ok_or(Error::unauthorized("Invalid envelope: missing nonce")));
+    let signature = try!(parts.next().ok_or(Error::unauthorized("Invalid envelope: missing signature")));
+    let payload = parts.next().unwrap_or("");
+
+    let token = try!(auth.get_token(name).ok_or(Error::unauthorized("Invalid token")));
+
+    let expected = hmac(token.secret.as_str(), signed_data(id, name, timestamp, nonce, payload).as_bytes());
+    if !fixed_time_eq(expected.as_bytes(), signature.as_bytes()) {
+        return Err(Error::unauthorized("Invalid signature"));
+    }
+
+    let timestamp = try!(timestamp.parse::<u64>().ok().ok_or(Error::unauthorized("Invalid envelope: invalid timestamp")));
+    let now = now();
+
+    if timestamp + MAX_SKEW < now || timestamp > now + MAX_SKEW {
+        return Err(Error::unauthorized("Expired envelope"));
+    }
+
+    if !is_valid_nonce(nonce) {
+        return Err(Error::unauthorized("Invalid envelope: invalid nonce"));
+    }
+    if !nonces.check(name, nonce, timestamp, now) {
+        return Err(Error::unauthorized("Replayed envelope"));
+    }
+
+    Ok((Identity::new(auth, token), payload.to_string()))
+}
+
+/*
+ * Wrap a command in a signed envelope, for the request with the specified identifier
+ */
+pub fn sign(token: &config::Token, id: Option<&str>, payload: &str) -> String {
+    let nonce = Uuid::new(UuidVersion::Random).unwrap().simple().to_string();
+    seal(token.name.as_str(), token.secret.as_str(), id, now(), nonce.as_str(), payload)
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
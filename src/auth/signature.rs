@@ -0,0 +1,37 @@
+/*
+ * Signature - Signed envelopes, see the auth module for the format
+ *
+ * The daemon verifies the envelopes that olvmctl signs with the same functions.
+ * This module only depends on the standard library and rust-crypto, so that clients can include it.
+ */
+
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+
+/*
+ * Hex-encoded HMAC-SHA256 of some data
+ */
+pub fn hmac(secret: &str, data: &[u8]) -> String {
+    let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+    mac.input(data);
+
+    mac.result().code().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/*
+ * Data covered by the signature of an envelope
+ */
+pub fn signed_data(id: Option<&str>, name: &str, timestamp: &str, nonce: &str, payload: &str) -> String {
+    format!("#{} {} {} {} {}", id.unwrap_or(""), name, timestamp, nonce, payload)
+}
+
+/*
+ * Wrap a command in an envelope signed with a token, for the request with the specified identifier
+ */
+pub fn seal(name: &str, secret: &str, id: Option<&str>, timestamp: u64, nonce: &str, payload: &str) -> String {
+    let timestamp = timestamp.to_string();
+    let signature = hmac(secret, signed_data(id, name, timestamp.as_str(), nonce, payload).as_bytes());
+
+    format!("auth {} {} {} {} {}", name, timestamp, nonce, signature, payload)
+}
@@ -0,0 +1,273 @@
+use common::{Context, ErrorKind};
+use config;
+use handler;
+
+use super::{Identity, Nonces, bearer, envelope, sign, now};
+use super::signature::{hmac, seal};
+
+fn context(auth: bool) -> Context {
+    let mut conf = String::from(r#"
+        backend = []
+
+        [global]
+        node = 1
+
+        [database]
+        type = "memory"
+    "#);
+
+    if auth {
+        conf.push_str(r#"
+            [auth]
+            remote = "cluster"
+
+            [[auth.token]]
+            name = "dashboard"
+            secret = "s3cret"
+            role = "operator"
+
+            [[auth.token]]
+            name = "cluster"
+            secret = "other"
+            role = "admin"
+
+            [[auth.token]]
+            name = "viewer"
+            secret = "viewer"
+
+            [[auth.token]]
+            name = "monitor"
+            secret = "monitor"
+            role = "monitoring"
+
+            [[auth.role]]
+            name = "monitoring"
+            commands = ["status", "statusvm"]
+        "#);
+    }
+
+    Context::test(conf.as_str())
+}
+
+#[test]
+fn hmac_sha256() {
+    // RFC 4231, test case 2
+    assert_eq!(hmac("Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+}
+
+#[test]
+fn disabled() {
+    let ctx = context(false);
+
+    assert_eq!(bearer(&ctx, None).unwrap().name.as_str(), "anonymous");
+
+    let (identity, payload) = envelope(&ctx, &Nonces::new(), None, "listvm").unwrap();
+    assert_eq!(identity.name.as_str(), "anonymous");
+    assert_eq!(payload.as_str(), "listvm");
+}
+
+#[test]
+fn bearer_token() {
+    let ctx = context(true);
+
+    assert_eq!(bearer(&ctx, Some("Bearer s3cret")).unwrap().name.as_str(), "dashboard");
+    assert_eq!(bearer(&ctx, Some("bearer other")).unwrap().name.as_str(), "cluster");
+
+    assert_eq!(bearer(&ctx, None).err().unwrap().kind(), ErrorKind::Unauthorized);
+    assert_eq!(bearer(&ctx, Some("Bearer wrong")).err().unwrap().kind(), ErrorKind::Unauthorized);
+    assert_eq!(bearer(&ctx, Some("Basic s3cret")).err().unwrap().kind(), ErrorKind::Unauthorized);
+}
+
+#[test]
+fn signed_envelope() {
+    let ctx = context(true);
+    let token = ctx.conf.auth.as_ref().unwrap().get_remote_token().unwrap();
+
+    let nonces = Nonces::new();
+
+    let (identity, payload) = envelope(&ctx, &nonces, Some("abc"), sign(token, Some("abc"), "getvm test").as_str()).unwrap();
+    assert_eq!(identity.name.as_str(), "cluster");
+    assert_eq!(payload.as_str(), "getvm test");
+
+    // Requests without an identifier
+    let (_, payload) = envelope(&ctx, &nonces, None, sign(token, None, "listvm").as_str()).unwrap();
+    assert_eq!(payload.as_str(), "listvm");
+}
+
+#[test]
+fn client_envelope() {
+    let ctx = context(true);
+    let nonces = Nonces::new();
+
+    // Signed by olvmctl with the shared module, the format is fixed
+    assert_eq!(seal("cli", "s3cret", Some("abc"), 1500000000, "0123456789abcdef", "getvm test").as_str(),
+        "auth cli 1500000000 0123456789abcdef 39e5ad43386d278d52c3dcae65c966ff8dcfc89a763f8453099c7918675167c3 getvm test");
+
+    let signed = seal("dashboard", "s3cret", Some("abc"), now(), "0123456789abcdef", "getvm test");
+    let (identity, payload) = envelope(&ctx, &nonces, Some("abc"), signed.as_str()).unwrap();
+    assert_eq!(identity.name.as_str(), "dashboard");
+    assert_eq!(payload.as_str(), "getvm test");
+}
+
+#[test]
+fn invalid_envelope() {
+    let ctx = context(true);
+
+    let nonces = Nonces::new();
+    let token = ctx.conf.auth.as_ref().unwrap().get_token("dashboard").unwrap();
+
+    // Unsigned command
+    assert_eq!(envelope(&ctx, &nonces, None, "listvm").err().unwrap().kind(), ErrorKind::Unauthorized);
+
+    // Tampered command
+    let tampered = sign(token, Some("abc"), "getvm test").replace("getvm test", "delvm test");
+    assert_eq!(envelope(&ctx, &nonces, Some("abc"), tampered.as_str()).err().unwrap().kind(), ErrorKind::Unauthorized);
+
+    // Envelope moved to another request
+    let signed = sign(token, Some("abc"), "getvm test");
+    assert_eq!(envelope(&ctx, &nonces, Some("def"), signed.as_str()).err().unwrap().kind(), ErrorKind::Unauthorized);
+    assert_eq!(envelope(&ctx, &nonces, None, signed.as_str()).err().unwrap().kind(), ErrorKind::Unauthorized);
+
+    // Unknown token
+    let forged = format!("auth nobody {} n1 {} listvm", now(), hmac("s3cret", format!("# nobody {} n1 listvm", now()).as_bytes()));
+    assert_eq!(envelope(&ctx, &nonces, None, forged.as_str()).err().unwrap().kind(), ErrorKind::Unauthorized);
+
+    // Expired envelope
+    let old = now() - 3600;
+    let expired = format!("auth dashboard {} n2 {} listvm", old, hmac("s3cret", format!("# dashboard {} n2 listvm", old).as_bytes()));
+    assert_eq!(envelope(&ctx, &nonces, None, expired.as_str()).err().unwrap().kind(), ErrorKind::Unauthorized);
+}
+
+#[test]
+fn replayed_envelope() {
+    let ctx = context(true);
+    let nonces = Nonces::new();
+    let token = ctx.conf.auth.as_ref().unwrap().get_token("dashboard").unwrap();
+
+    let signed = sign(token, Some("abc"), "stopvm test");
+    envelope(&ctx, &nonces, Some("abc"), signed.as_str()).unwrap();
+
+    let e = envelope(&ctx, &nonces, Some("abc"), signed.as_str()).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unauthorized);
+
+    // Same command, new envelope
+    envelope(&ctx, &nonces, Some("abc"), sign(token, Some("abc"), "stopvm test").as_str()).unwrap();
+
+    // Nonces are only kept while their envelope is valid
+    assert!(nonces.check("dashboard", "n1", now() - 3600, now()));
+    assert!(!nonces.check("dashboard", "n1", now() - 3600, now()));
+    assert!(nonces.check("dashboard", "n1", now(), now() + 3600));
+}
+
+#[test]
+fn roles() {
+    let ctx = context(true);
+
+    let admin = bearer(&ctx, Some("Bearer other")).unwrap();
+    assert!(admin.allows("delvm"));
+    assert!(admin.allows("migratevm"));
+
+    let operator = bearer(&ctx, Some("Bearer s3cret")).unwrap();
+    assert!(operator.allows("listvm"));
+    assert!(operator.allows("startvm"));
+    assert!(operator.allows("createsnap"));
+    assert!(!operator.allows("delvm"));
+    assert!(!operator.allows("createnet"));
+
+    // Tokens without a role are read-only
+    let viewer = bearer(&ctx, Some("Bearer viewer")).unwrap();
+    assert_eq!(viewer.role.as_str(), "read-only");
+    assert!(viewer.allows("getvm"));
+    assert!(viewer.allows("status"));
+    assert!(viewer.allows("statusvm"));
+    assert!(viewer.allows("statsvm"));
+    assert!(viewer.allows("listjob"));
+    assert!(!viewer.allows("stopvm"));
+
+    // The audit log is reserved to administrators
+    assert!(!viewer.allows("listaudit"));
+    assert!(!operator.allows("listaudit"));
+    assert!(admin.allows("listaudit"));
+
+    let monitor = bearer(&ctx, Some("Bearer monitor")).unwrap();
+    assert!(monitor.allows("status"));
+    assert!(!monitor.allows("listvm"));
+}
+
+#[test]
+fn unknown_role() {
+    let conf = r#"
+        backend = []
+
+        [global]
+        node = 1
+
+        [database]
+        type = "memory"
+    "#;
+
+    // Custom roles can be given to tokens and local clients
+    let custom = format!("{}{}", conf, r#"
+        [unix]
+        path = "/tmp/olvm.sock"
+        role = "monitoring"
+
+        [auth]
+        [[auth.token]]
+        name = "monitor"
+        secret = "monitor"
+        role = "monitoring"
+
+        [[auth.role]]
+        name = "monitoring"
+        commands = ["status"]
+    "#);
+    assert!(config::parse(custom.as_str()).is_ok());
+
+    let token = format!("{}{}", conf, r#"
+        [auth]
+        [[auth.token]]
+        name = "dashboard"
+        secret = "s3cret"
+        role = "operater"
+    "#);
+    assert!(config::parse(token.as_str()).is_err());
+
+    let unix = format!("{}{}", conf, r#"
+        [unix]
+        path = "/tmp/olvm.sock"
+        role = "root"
+    "#);
+    assert!(config::parse(unix.as_str()).is_err());
+}
+
+#[test]
+fn with_role() {
+    let ctx = context(true);
+
+    let local = Identity::with_role(&ctx, "uid 1000", "operator");
+    assert!(local.allows("startvm"));
+    assert!(!local.allows("delvm"));
+
+    // Custom roles apply too
+    let local = Identity::with_role(&ctx, "uid 1000", "monitoring");
+    assert!(local.allows("statusvm"));
+    assert!(!local.allows("listvm"));
+
+    // Built-in roles are available without an [auth] section
+    let local = Identity::with_role(&context(false), "uid 0", "admin");
+    assert!(local.allows("delvm"));
+}
+
+#[test]
+fn handle_denied() {
+    let ctx = context(true);
+    let viewer = bearer(&ctx, Some("Bearer viewer")).unwrap();
+
+    let e = handler::handle(&ctx, "test", &viewer, "delvm", "test").err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+    // Allowed commands still run
+    assert_eq!(handler::handle(&ctx, "test", &viewer, "listvm", "").unwrap().as_str(), "[]");
+}
@@ -7,10 +7,10 @@ use common::structs::Image;
 use database;
 
 pub fn script_create(ctx: &Context, img: &mut Image) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(img.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(img.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.image.create {
-        let params = try!(super::script(path, try!(img.to_json()).as_str()));
+        let params = try!(super::script(ctx, backend.name.as_str(), "image_create", path, try!(img.to_json()).as_str()));
         try!(database::image::params(ctx, img, params));
     }
 
@@ -18,10 +18,10 @@ pub fn script_create(ctx: &Context, img: &mut Image) -> Result<()> {
 }
 
 pub fn script_delete(ctx: &Context, img: &Image) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(img.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(img.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.image.delete {
-        try!(super::script(path, try!(img.to_json()).as_str()));
+        try!(super::script(ctx, backend.name.as_str(), "image_delete", path, try!(img.to_json()).as_str()));
     }
 
     Ok(())
@@ -5,37 +5,43 @@
 pub mod image;
 pub mod vm;
 
-use std::process::Command;
 use std::collections::HashMap;
+use std::time::Instant;
 
-use common::{Result, Error};
+use common::{Context, Result, Error};
 
 /*
- * Execute a backend script
+ * Execute a backend script, 'name' identifies it in the metrics
  */
-pub fn script(path: &str, obj: &str) -> Result<HashMap<String, String>> {
+pub fn script(ctx: &Context, backend: &str, name: &str, path: &str, obj: &str) -> Result<HashMap<String, String>> {
     // Execute the script
-    let out = match Command::new(path).arg(obj).output() {
+    let started = Instant::now();
+    let res = ctx.runner.exec(path, &[obj]);
+
+    ctx.metrics.script(backend, name, started.elapsed(), match res {
+        Ok(ref out) => out.success,
+        Err(_) => false
+    });
+
+    let out = match res {
         Ok(out) => out,
-        Err(e) => return Err(Error::new(format!("script: exec: {}", e)))
+        Err(e) => return Err(Error::backend(format!("script: {}", e)))
     };
 
     // If the script did not exit with status 0, print stderr
-    if !out.status.success() {
-        let stderr = try!(String::from_utf8(out.stderr).ok().ok_or(Error::new("script: failed to read stderr")));
-        return Err(Error::new(format!("script: failed: {}", stderr)));
+    if !out.success {
+        return Err(Error::backend(format!("script: failed: {}", out.stderr)));
     }
 
     // Read stdout to retreive output parameters sent by the script
-    let stdout = try!(String::from_utf8(out.stdout).ok().ok_or(Error::new("script: failed to read stdout")));
-    let lines = stdout.lines();
+    let lines = out.stdout.lines();
     let mut params = HashMap::new();
 
     for line in lines {
         // Each line should be composed of a key and a value, separated by whitespace(s)
         let mut parts = line.split_whitespace();
-        let key = try!(parts.next().ok_or(Error::new("script: invalid output")));
-        let value = try!(parts.next().ok_or(Error::new("script: invalid output")));
+        let key = try!(parts.next().ok_or(Error::backend("script: invalid output")));
+        let value = try!(parts.next().ok_or(Error::backend("script: invalid output")));
 
         params.insert(key.to_string(), value.to_string());
     }
@@ -31,10 +31,10 @@ fn json(ctx: &Context, vm: &VM) -> Result<Value> {
 }
 
 pub fn script_create(ctx: &Context, vm: &mut VM) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.vm.create {
-        let params = try!(super::script(path, try!(json(ctx, vm)).to_string().as_str()));
+        let params = try!(super::script(ctx, backend.name.as_str(), "vm_create", path, try!(json(ctx, vm)).to_string().as_str()));
         try!(database::vm::params(ctx, vm, params));
     }
 
@@ -42,10 +42,10 @@ pub fn script_create(ctx: &Context, vm: &mut VM) -> Result<()> {
 }
 
 pub fn script_start(ctx: &Context, vm: &mut VM) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.vm.start {
-        let params = try!(super::script(path, try!(json(ctx, vm)).to_string().as_str()));
+        let params = try!(super::script(ctx, backend.name.as_str(), "vm_start", path, try!(json(ctx, vm)).to_string().as_str()));
         try!(database::vm::params(ctx, vm, params));
     }
 
@@ -53,10 +53,10 @@ pub fn script_start(ctx: &Context, vm: &mut VM) -> Result<()> {
 }
 
 pub fn script_stop(ctx: &Context, vm: &mut VM) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.vm.stop {
-        let params = try!(super::script(path, try!(json(ctx, vm)).to_string().as_str()));
+        let params = try!(super::script(ctx, backend.name.as_str(), "vm_stop", path, try!(json(ctx, vm)).to_string().as_str()));
         try!(database::vm::params(ctx, vm, params));
     }
 
@@ -64,27 +64,27 @@ pub fn script_stop(ctx: &Context, vm: &mut VM) -> Result<()> {
 }
 
 pub fn script_delete(ctx: &Context, vm: &VM) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.vm.delete {
-        try!(super::script(path, try!(json(ctx, vm)).to_string().as_str()));
+        try!(super::script(ctx, backend.name.as_str(), "vm_delete", path, try!(json(ctx, vm)).to_string().as_str()));
     }
 
     Ok(())
 }
 
 pub fn script_status(ctx: &Context, vm: &mut VM) -> Result<HashMap<String, String>> {
-    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.vm.status {
-        return Ok(try!(super::script(path, try!(json(ctx, vm)).to_string().as_str())));
+        return Ok(try!(super::script(ctx, backend.name.as_str(), "vm_status", path, try!(json(ctx, vm)).to_string().as_str())));
     }
 
     Ok(HashMap::new())
 }
 
 pub fn script_snapshot_create(ctx: &Context, vm: &VM, name: &str) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.vm.snapshot_create {
         let vm_json = try!(json(ctx, vm));
@@ -93,14 +93,14 @@ pub fn script_snapshot_create(ctx: &Context, vm: &VM, name: &str) -> Result<()>
             "vm": vm_json
         }).to_string();
 
-        try!(super::script(path, json.as_str()));
+        try!(super::script(ctx, backend.name.as_str(), "snapshot_create", path, json.as_str()));
     }
 
     Ok(())
 }
 
 pub fn script_snapshot_restore(ctx: &Context, vm: &VM, name: &str) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.vm.snapshot_restore {
         let vm_json = try!(json(ctx, vm));
@@ -109,14 +109,14 @@ pub fn script_snapshot_restore(ctx: &Context, vm: &VM, name: &str) -> Result<()>
             "vm": vm_json
         }).to_string();
 
-        try!(super::script(path, json.as_str()));
+        try!(super::script(ctx, backend.name.as_str(), "snapshot_restore", path, json.as_str()));
     }
 
     Ok(())
 }
 
 pub fn script_snapshot_delete(ctx: &Context, vm: &VM, name: &str) -> Result<()> {
-    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::new("Invalid or unknown backend")));
+    let backend = try!(ctx.conf.get_backend(vm.backend.as_str()).ok_or(Error::invalid_argument("Invalid or unknown backend")));
 
     if let Some(ref path) = backend.vm.snapshot_delete {
         let vm_json = try!(json(ctx, vm));
@@ -125,7 +125,7 @@ pub fn script_snapshot_delete(ctx: &Context, vm: &VM, name: &str) -> Result<()>
             "vm": vm_json
         }).to_string();
 
-        try!(super::script(path, json.as_str()));
+        try!(super::script(ctx, backend.name.as_str(), "snapshot_delete", path, json.as_str()));
     }
 
     Ok(())
@@ -0,0 +1,276 @@
+/*
+ * Command - Translate the command line into a daemon command
+ */
+
+use serde_json::{self, Map};
+use serde_json::value::Value;
+
+/*
+ * A command, as understood by the daemon's handlers
+ */
+pub struct Request {
+    pub command: String,
+    pub argument: String
+}
+
+impl Request {
+    fn new<S: Into<String>>(command: &str, argument: S) -> Request {
+        Request {
+            command: command.to_string(),
+            argument: argument.into()
+        }
+    }
+}
+
+/*
+ * Positional arguments and --flag value pairs following the action
+ */
+struct Args {
+    positional: Vec<String>,
+    flags: Vec<(String, String)>
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, String> {
+        let mut positional = Vec::new();
+        let mut flags = Vec::new();
+
+        let mut i = 0;
+        while i < args.len() {
+            if args[i].starts_with("--") {
+                let value = try!(args.get(i + 1).ok_or(format!("missing value for {}", args[i])));
+                flags.push((args[i][2..].to_string(), value.clone()));
+                i += 2;
+            }
+            else {
+                positional.push(args[i].clone());
+                i += 1;
+            }
+        }
+
+        Ok(Args {
+            positional: positional,
+            flags: flags
+        })
+    }
+
+    /*
+     * Return the n-th positional argument
+     */
+    fn arg(&self, n: usize, name: &str) -> Result<&str, String> {
+        match self.positional.get(n) {
+            Some(a) => Ok(a.as_str()),
+            None => Err(format!("missing <{}> argument", name))
+        }
+    }
+
+    /*
+     * Return the last value of a flag
+     */
+    fn flag(&self, name: &str) -> Option<&str> {
+        self.flags.iter().rev().find(|f| f.0.as_str() == name).map(|f| f.1.as_str())
+    }
+
+    /*
+     * Return all the values of a repeatable flag
+     */
+    fn flags(&self, name: &str) -> Vec<&str> {
+        self.flags.iter().filter(|f| f.0.as_str() == name).map(|f| f.1.as_str()).collect()
+    }
+
+    /*
+     * Make sure only known flags were given
+     */
+    fn check(&self, known: &[&str]) -> Result<(), String> {
+        for f in &self.flags {
+            if !known.contains(&f.0.as_str()) {
+                return Err(format!("unknown flag --{}", f.0));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/*
+ * Build a JSON object from the flags, or take it as-is from --data
+ */
+fn object(args: &Args, name: &str, known: &[&str], build: &Fn(&Args, &mut Map<String, Value>) -> Result<(), String>) -> Result<String, String> {
+    let mut obj = match args.flag("data") {
+        Some(data) => match serde_json::from_str::<Value>(data) {
+            Ok(Value::Object(obj)) => obj,
+            _ => return Err(String::from("--data must be a JSON object"))
+        },
+        None => Map::new()
+    };
+
+    let mut all = known.to_vec();
+    all.push("data");
+    try!(args.check(&all));
+
+    obj.insert(String::from("name"), Value::String(name.to_string()));
+    try!(build(args, &mut obj));
+
+    Ok(Value::Object(obj).to_string())
+}
+
+/*
+ * Copy string flags into fields of the same name
+ */
+fn strings(args: &Args, obj: &mut Map<String, Value>, keys: &[&str]) {
+    for k in keys {
+        if let Some(v) = args.flag(k) {
+            obj.insert(k.to_string(), Value::String(v.to_string()));
+        }
+    }
+}
+
+/*
+ * Parse the repeatable --param key=value flags
+ */
+fn parameters(args: &Args, obj: &mut Map<String, Value>) -> Result<(), String> {
+    let params = args.flags("param");
+    if params.len() == 0 {
+        return Ok(());
+    }
+
+    let mut map = Map::new();
+    for p in params {
+        let i = try!(p.find('=').ok_or(format!("invalid parameter '{}', expected key=value", p)));
+        map.insert(p[..i].to_string(), Value::String(p[i + 1..].to_string()));
+    }
+
+    obj.insert(String::from("parameters"), Value::Object(map));
+    Ok(())
+}
+
+fn vm(args: &Args, obj: &mut Map<String, Value>) -> Result<(), String> {
+    strings(args, obj, &["backend", "image"]);
+
+    let nets = args.flags("net");
+    if nets.len() > 0 {
+        let mut interfaces = Vec::new();
+
+        for n in nets {
+            let parts: Vec<&str> = n.splitn(3, ':').collect();
+            if parts.len() < 2 {
+                return Err(format!("invalid interface '{}', expected <network>:<ip>[:<mac>]", n));
+            }
+
+            interfaces.push(match parts.len() {
+                3 => json!({"network": parts[0], "ip": parts[1], "mac": parts[2]}),
+                _ => json!({"network": parts[0], "ip": parts[1]})
+            });
+        }
+
+        obj.insert(String::from("interfaces"), Value::Array(interfaces));
+    }
+
+    if let Some(autostart) = args.flag("autostart") {
+        match autostart {
+            "true" => obj.insert(String::from("autostart"), Value::Bool(true)),
+            "false" => obj.insert(String::from("autostart"), Value::Bool(false)),
+            _ => return Err(String::from("--autostart must be true or false"))
+        };
+    }
+
+    if let Some(restart) = args.flag("restart") {
+        let mut policy = json!({"type": restart});
+
+        for &(flag, key) in &[("max-retries", "max_retries"), ("backoff", "backoff")] {
+            if let Some(v) = args.flag(flag) {
+                let n = try!(v.parse::<u64>().map_err(|_| format!("--{} must be a number", flag)));
+                policy.as_object_mut().unwrap().insert(key.to_string(), json!(n));
+            }
+        }
+
+        obj.insert(String::from("restart_policy"), policy);
+    }
+
+    parameters(args, obj)
+}
+
+fn image(args: &Args, obj: &mut Map<String, Value>) -> Result<(), String> {
+    strings(args, obj, &["backend", "file"]);
+    parameters(args, obj)
+}
+
+fn network(args: &Args, obj: &mut Map<String, Value>) -> Result<(), String> {
+    strings(args, obj, &["cidr", "router", "interface"]);
+
+    let dns = args.flags("dns");
+    if dns.len() > 0 {
+        obj.insert(String::from("dns"), Value::Array(dns.iter().map(|d| Value::String(d.to_string())).collect()));
+    }
+
+    Ok(())
+}
+
+/*
+ * Flags accepted when creating or updating a VM
+ */
+const VM_FLAGS: &'static [&'static str] = &["backend", "image", "net", "param", "autostart", "restart", "max-retries", "backoff"];
+
+/*
+ * Build the command corresponding to the command line
+ */
+pub fn build(words: &[String]) -> Result<Request, String> {
+    let resource = words[0].as_str();
+    let action = words.get(1).map(|a| a.as_str()).unwrap_or("");
+    let rest: &[String] = if words.len() > 2 { &words[2..] } else { &[] };
+    let args = try!(Args::parse(rest));
+
+    if resource == "status" {
+        return Ok(Request::new("status", ""));
+    }
+
+    // Actions taking no flags
+    match action {
+        "create" | "update" | "migrate" => {},
+        _ => try!(args.check(&[]))
+    };
+
+    let req = match (resource, action) {
+        ("vm", "list") => Request::new("listvm", ""),
+        ("vm", "get") => Request::new("getvm", try!(args.arg(0, "name"))),
+        ("vm", "create") => Request::new("createvm", try!(object(&args, try!(args.arg(0, "name")), VM_FLAGS, &vm))),
+        ("vm", "update") => Request::new("updatevm", try!(object(&args, try!(args.arg(0, "name")), VM_FLAGS, &vm))),
+        ("vm", "delete") => Request::new("delvm", try!(args.arg(0, "name"))),
+        ("vm", "start") => Request::new("startvm", try!(args.arg(0, "name"))),
+        ("vm", "stop") => Request::new("stopvm", try!(args.arg(0, "name"))),
+        ("vm", "status") => Request::new("statusvm", try!(args.arg(0, "name"))),
+        ("vm", "stats") => Request::new("statsvm", try!(args.arg(0, "name"))),
+        ("vm", "migrate") => {
+            try!(args.check(&["to"]));
+            let dst = try!(args.flag("to").ok_or(String::from("missing --to <ip:port>")));
+
+            Request::new("migratevm", json!({"name": try!(args.arg(0, "name")), "destination": dst}).to_string())
+        },
+
+        ("img", "list") => Request::new("listimg", ""),
+        ("img", "get") => Request::new("getimg", try!(args.arg(0, "name"))),
+        ("img", "create") => Request::new("createimg", try!(object(&args, try!(args.arg(0, "name")), &["backend", "file", "param"], &image))),
+        ("img", "update") => Request::new("updateimg", try!(object(&args, try!(args.arg(0, "name")), &["backend", "file", "param"], &image))),
+        ("img", "delete") => Request::new("delimg", try!(args.arg(0, "name"))),
+
+        ("net", "list") => Request::new("listnet", ""),
+        ("net", "get") => Request::new("getnet", try!(args.arg(0, "name"))),
+        ("net", "create") => Request::new("createnet", try!(object(&args, try!(args.arg(0, "name")), &["cidr", "router", "dns", "interface"], &network))),
+        ("net", "update") => Request::new("updatenet", try!(object(&args, try!(args.arg(0, "name")), &["cidr", "router", "dns", "interface"], &network))),
+        ("net", "delete") => Request::new("delnet", try!(args.arg(0, "name"))),
+
+        ("snap", "list") => Request::new("listsnap", try!(args.arg(0, "vm"))),
+        ("snap", "create") => Request::new("createsnap", json!({"vm": try!(args.arg(0, "vm")), "name": try!(args.arg(1, "name"))}).to_string()),
+        ("snap", "restore") => Request::new("restoresnap", json!({"vm": try!(args.arg(0, "vm")), "name": try!(args.arg(1, "name"))}).to_string()),
+        ("snap", "delete") => Request::new("delsnap", json!({"vm": try!(args.arg(0, "vm")), "name": try!(args.arg(1, "name"))}).to_string()),
+
+        ("job", "list") => Request::new("listjob", ""),
+        ("job", "get") => Request::new("getjob", try!(args.arg(0, "id"))),
+        ("job", "cancel") => Request::new("canceljob", try!(args.arg(0, "id"))),
+
+        ("vm", _) | ("img", _) | ("net", _) | ("snap", _) | ("job", _) => return Err(format!("unknown action '{}' for {}", action, resource)),
+        _ => return Err(format!("unknown resource '{}'", resource))
+    };
+
+    Ok(req)
+}
@@ -0,0 +1,149 @@
+/*
+ * olvmctl - Command line client for the olvm daemon
+ *
+ * Builds the command argument from the command line, sends it over one of
+ * the daemon's interfaces (UDP, HTTP or Unix socket) and prints the result
+ */
+
+#[macro_use]
+extern crate serde_json;
+extern crate crypto;
+extern crate openssl;
+
+mod command;
+mod transport;
+mod output;
+
+// Shared with the daemon, only depends on the standard library
+// The server side of the protocol is not used here
+#[path = "../../utils/frame.rs"]
+#[allow(dead_code)]
+mod frame;
+
+// Shared with the daemon, so that both sides sign envelopes the same way
+#[path = "../../auth/signature.rs"]
+mod signature;
+
+use std::env;
+use std::process;
+
+use transport::{Transport, Token};
+
+const USAGE: &'static str = "Usage: olvmctl [options] <resource> <action> [arguments] [--flag value ...]
+
+Options:
+  -u, --udp <addr>          Send commands over UDP (default: 127.0.0.1:1997)
+  -H, --http <url>          Send commands over HTTP(S), for example http://127.0.0.1:8000
+  -U, --unix <path>         Send commands over a Unix socket, for example /run/olvm/olvm.sock
+  -t, --token <name:secret> Authenticate with a token (default: $OLVM_TOKEN)
+      --ca <file>           HTTPS: CA certificate used to verify the server
+      --cert <file>         HTTPS: client certificate
+      --key <file>          HTTPS: client private key
+  -j, --json                Print the raw JSON response
+  -n, --no-wait             Print the ID of the job started by a command instead of waiting for its end
+  -h, --help                Print this help
+
+Resources:
+  status
+  vm list | get <name> | delete <name> | start <name> | stop <name> | status <name> | stats <name>
+  vm create|update <name> --backend <backend> [--image <image>] [--net <network>:<ip>[:<mac>] ...] [--param <key>=<value> ...]
+                   [--autostart true|false] [--restart never|on-failure|always [--max-retries <n>] [--backoff <seconds>]]
+  vm migrate <name> --to <ip:port>
+  img list | get <name> | delete <name>
+  img create|update <name> --backend <backend> --file <path> [--param <key>=<value> ...]
+  net list | get <name> | delete <name>
+  net create|update <name> --cidr <cidr> [--router <ip>] [--dns <ip> ...] [--interface <iface>]
+  snap list <vm> | create <vm> <name> | restore <vm> <name> | delete <vm> <name>
+  job list | get <id> | cancel <id>
+
+Create and update commands also accept the whole object with --data <json>.";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("olvmctl: {}", msg);
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut rest = Vec::new();
+
+    let mut transport = Transport::Udp(String::from("127.0.0.1:1997"));
+    let mut token = env::var("OLVM_TOKEN").ok();
+    let mut tls = transport::TlsOptions::default();
+    let mut raw = false;
+    let mut wait = true;
+
+    // Options come before the resource
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-u" | "--udp" => transport = Transport::Udp(args.next().unwrap_or_else(|| fail("missing UDP address"))),
+            "-H" | "--http" => transport = Transport::Http(args.next().unwrap_or_else(|| fail("missing HTTP URL"))),
+            "-U" | "--unix" => transport = Transport::Unix(args.next().unwrap_or_else(|| fail("missing socket path"))),
+            "-t" | "--token" => token = Some(args.next().unwrap_or_else(|| fail("missing token"))),
+            "--ca" => tls.ca = args.next(),
+            "--cert" => tls.certificate = args.next(),
+            "--key" => tls.key = args.next(),
+            "-j" | "--json" => raw = true,
+            "-n" | "--no-wait" => wait = false,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => {
+                rest.push(arg);
+                rest.extend(args);
+                break;
+            }
+        }
+    }
+
+    if rest.len() == 0 {
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+
+    let token = match token {
+        Some(t) => match Token::parse(t.as_str()) {
+            Ok(t) => Some(t),
+            Err(e) => fail(e.as_str())
+        },
+        None => None
+    };
+
+    let req = match command::build(&rest) {
+        Ok(req) => req,
+        Err(e) => fail(e.as_str())
+    };
+
+    let res = match transport::send(&transport, token.as_ref(), &tls, &req) {
+        Ok(res) => res,
+        Err(e) => fail(e.as_str())
+    };
+
+    // Long commands run as jobs on the daemon, wait for their result
+    let res = match transport::job(res.as_str()) {
+        Some(ref id) if wait => match transport::wait(&transport, token.as_ref(), &tls, id.as_str()) {
+            Ok(res) => res,
+            Err(e) => fail(e.as_str())
+        },
+        _ => res
+    };
+
+    if raw {
+        if res.len() > 0 {
+            println!("{}", res);
+        }
+        return;
+    }
+
+    match output::print(&req, res.as_str()) {
+        Ok(_) => {},
+        Err(e) => fail(e.as_str())
+    };
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
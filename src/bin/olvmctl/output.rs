@@ -0,0 +1,110 @@
+/*
+ * Output - Print the daemon's responses in a human-readable form
+ */
+
+use serde_json;
+use serde_json::value::Value;
+
+use command::Request;
+
+/*
+ * Columns printed when listing objects: header and JSON field
+ */
+const VM_COLUMNS: &'static [(&'static str, &'static str)] = &[("NAME", "name"), ("STATE", "state"), ("BACKEND", "backend"), ("IMAGE", "image"), ("NODE", "node"), ("INTERFACES", "interfaces")];
+const IMAGE_COLUMNS: &'static [(&'static str, &'static str)] = &[("NAME", "name"), ("BACKEND", "backend"), ("NODE", "node"), ("FILE", "file")];
+const NETWORK_COLUMNS: &'static [(&'static str, &'static str)] = &[("NAME", "name"), ("CIDR", "cidr"), ("ROUTER", "router"), ("INTERFACE", "interface")];
+const SNAPSHOT_COLUMNS: &'static [(&'static str, &'static str)] = &[("NAME", "name"), ("VM", "vm")];
+const JOB_COLUMNS: &'static [(&'static str, &'static str)] = &[("ID", "id"), ("COMMAND", "command"), ("STATE", "state"), ("PROGRESS", "progress"), ("CLIENT", "client")];
+
+/*
+ * Format a field as a table cell
+ */
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(&Value::Null) => String::new(),
+        Some(&Value::String(ref s)) => s.clone(),
+        Some(&Value::Array(ref a)) => {
+            let items: Vec<String> = a.iter().map(|v| {
+                // VM interfaces are shown as network:ip
+                match (v.get("network").and_then(|n| n.as_str()), v.get("ip").and_then(|i| i.as_str())) {
+                    (Some(net), Some(ip)) => format!("{}:{}", net, ip),
+                    _ => cell(Some(v))
+                }
+            }).collect();
+
+            items.join(",")
+        },
+        Some(v) => v.to_string()
+    }
+}
+
+/*
+ * Format a list of objects as a table
+ */
+pub fn table(columns: &[(&str, &str)], rows: &[Value]) -> String {
+    let cells: Vec<Vec<String>> = rows.iter().map(|r| columns.iter().map(|c| cell(r.get(c.1))).collect()).collect();
+
+    let widths: Vec<usize> = columns.iter().enumerate().map(|(i, c)| {
+        cells.iter().map(|r| r[i].len()).fold(c.0.len(), |a, b| if b > a { b } else { a })
+    }).collect();
+
+    let mut out = String::new();
+    let headers: Vec<String> = columns.iter().map(|c| c.0.to_string()).collect();
+
+    for row in Some(&headers).into_iter().chain(cells.iter()) {
+        let line: Vec<String> = row.iter().enumerate().map(|(i, c)| format!("{:1$}", c, widths[i])).collect();
+        out.push_str(line.join("  ").trim_right());
+        out.push('\n');
+    }
+
+    out
+}
+
+/*
+ * Format a single object as key: value lines
+ */
+pub fn object(obj: &Value) -> String {
+    let mut out = String::new();
+
+    if let Some(map) = obj.as_object() {
+        let width = map.keys().map(|k| k.len()).max().unwrap_or(0);
+
+        for (k, v) in map {
+            out.push_str(format!("{:2$}  {}\n", format!("{}:", k), cell(Some(v)), width + 1).as_str());
+        }
+    }
+
+    out
+}
+
+pub fn print(req: &Request, res: &str) -> Result<(), String> {
+    // Commands without any result
+    if res.len() == 0 {
+        return Ok(());
+    }
+
+    let json: Value = match serde_json::from_str(res) {
+        Ok(json) => json,
+        Err(_) => {
+            println!("{}", res);
+            return Ok(());
+        }
+    };
+
+    let columns = match req.command.as_str() {
+        "listvm" => VM_COLUMNS,
+        "listimg" => IMAGE_COLUMNS,
+        "listnet" => NETWORK_COLUMNS,
+        "listsnap" => SNAPSHOT_COLUMNS,
+        "listjob" => JOB_COLUMNS,
+        _ => &[]
+    };
+
+    match json {
+        Value::Array(ref rows) if columns.len() > 0 => print!("{}", table(columns, rows)),
+        Value::Object(_) => print!("{}", object(&json)),
+        _ => println!("{}", json)
+    };
+
+    Ok(())
+}
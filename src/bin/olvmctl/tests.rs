@@ -0,0 +1,177 @@
+use serde_json;
+use serde_json::value::Value;
+
+use command::{self, Request};
+use transport::{self, Token};
+use output;
+
+fn build(line: &str) -> Request {
+    let words: Vec<String> = line.split(' ').map(|w| w.to_string()).collect();
+    command::build(&words).unwrap()
+}
+
+fn build_err(line: &str) -> String {
+    let words: Vec<String> = line.split(' ').map(|w| w.to_string()).collect();
+    command::build(&words).err().unwrap()
+}
+
+fn argument(req: &Request) -> Value {
+    serde_json::from_str(req.argument.as_str()).unwrap()
+}
+
+/*
+ * Command line
+ */
+#[test]
+fn build_simple() {
+    let req = build("vm list");
+    assert_eq!(req.command.as_str(), "listvm");
+    assert_eq!(req.argument.as_str(), "");
+
+    let req = build("vm start test");
+    assert_eq!(req.command.as_str(), "startvm");
+    assert_eq!(req.argument.as_str(), "test");
+
+    assert_eq!(build("status").command.as_str(), "status");
+    assert_eq!(build("net delete lan").command.as_str(), "delnet");
+}
+
+#[test]
+fn build_vm_create() {
+    let req = build("vm create test --backend kvm --image debian --net lan:10.0.0.2 --net wan:10.1.0.2:52:54:00:12:34:56 --param memory=512");
+    assert_eq!(req.command.as_str(), "createvm");
+
+    let vm = argument(&req);
+    assert_eq!(vm["name"].as_str(), Some("test"));
+    assert_eq!(vm["backend"].as_str(), Some("kvm"));
+    assert_eq!(vm["image"].as_str(), Some("debian"));
+    assert_eq!(vm["interfaces"][0]["network"].as_str(), Some("lan"));
+    assert_eq!(vm["interfaces"][0]["ip"].as_str(), Some("10.0.0.2"));
+    assert_eq!(vm["interfaces"][1]["mac"].as_str(), Some("52:54:00:12:34:56"));
+    assert_eq!(vm["parameters"]["memory"].as_str(), Some("512"));
+}
+
+#[test]
+fn build_vm_policy() {
+    let vm = argument(&build("vm update test --backend kvm --autostart true --restart on-failure --max-retries 5"));
+
+    assert_eq!(vm["autostart"].as_bool(), Some(true));
+    assert_eq!(vm["restart_policy"]["type"].as_str(), Some("on-failure"));
+    assert_eq!(vm["restart_policy"]["max_retries"].as_u64(), Some(5));
+    assert!(vm["restart_policy"].get("backoff").is_none());
+
+    assert!(build_err("vm create test --autostart yes").contains("--autostart"));
+    assert!(build_err("vm create test --restart always --backoff soon").contains("--backoff"));
+}
+
+#[test]
+fn build_data() {
+    let req = build(r#"net create lan --data {"cidr":"10.0.0.0/24"} --router 10.0.0.1"#);
+    let net = argument(&req);
+
+    assert_eq!(net["name"].as_str(), Some("lan"));
+    assert_eq!(net["cidr"].as_str(), Some("10.0.0.0/24"));
+    assert_eq!(net["router"].as_str(), Some("10.0.0.1"));
+}
+
+#[test]
+fn build_snapshot() {
+    let req = build("snap restore test snap1");
+    assert_eq!(req.command.as_str(), "restoresnap");
+
+    let snap = argument(&req);
+    assert_eq!(snap["vm"].as_str(), Some("test"));
+    assert_eq!(snap["name"].as_str(), Some("snap1"));
+
+    assert_eq!(build("snap list test").argument.as_str(), "test");
+}
+
+#[test]
+fn build_invalid() {
+    assert!(build_err("vm get").contains("<name>"));
+    assert!(build_err("vm foo").contains("unknown action"));
+    assert!(build_err("foo list").contains("unknown resource"));
+    assert!(build_err("vm create test --colour red").contains("--colour"));
+    assert!(build_err("vm create test --backend").contains("missing value"));
+    assert!(build_err("vm create test --net lan").contains("invalid interface"));
+    assert!(build_err("vm migrate test").contains("--to"));
+}
+
+/*
+ * Transport
+ */
+#[test]
+fn sign() {
+    let token = Token::parse("cli:s3cret").unwrap();
+    assert_eq!(token.name.as_str(), "cli");
+    assert_eq!(token.secret.as_str(), "s3cret");
+    assert!(Token::parse("cli").is_err());
+
+    // Same envelope as the daemon's auth::sign
+    assert_eq!(transport::sign(&token, "abc", "getvm test", 1500000000, "0123456789abcdef").as_str(),
+        "auth cli 1500000000 0123456789abcdef 39e5ad43386d278d52c3dcae65c966ff8dcfc89a763f8453099c7918675167c3 getvm test");
+}
+
+#[test]
+fn routes() {
+    let (method, path, body) = transport::route(&build("vm start test")).unwrap();
+    assert_eq!((method, path.as_str(), body.as_str()), ("POST", "/vms/test/start", ""));
+
+    let (method, path, _) = transport::route(&build("vm update test --backend kvm")).unwrap();
+    assert_eq!((method, path.as_str()), ("PUT", "/vms/test"));
+
+    let (method, path, _) = transport::route(&build("snap delete test snap1")).unwrap();
+    assert_eq!((method, path.as_str()), ("DELETE", "/vms/test/snapshots/snap1"));
+
+    let (method, path, body) = transport::route(&build("img create debian --backend kvm --file /tmp/debian.img")).unwrap();
+    assert_eq!((method, path.as_str()), ("POST", "/images"));
+    assert!(body.contains("debian.img"));
+
+    let (method, path, _) = transport::route(&build("vm stats test")).unwrap();
+    assert_eq!((method, path.as_str()), ("GET", "/vms/test/stats"));
+
+    let (method, path, _) = transport::route(&build("job cancel 42")).unwrap();
+    assert_eq!((method, path.as_str()), ("POST", "/jobs/42/cancel"));
+}
+
+#[test]
+fn jobs() {
+    assert_eq!(build("job list").command.as_str(), "listjob");
+    assert_eq!(build("job get 42").argument.as_str(), "42");
+    assert!(build_err("job cancel").contains("<id>"));
+
+    assert_eq!(transport::job(r#"{"job": "42"}"#), Some(String::from("42")));
+    assert_eq!(transport::job(r#"{"name": "test"}"#), None);
+    assert_eq!(transport::job(""), None);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(transport::error(r#"{"error": "VM not found", "code": "not_found"}"#), Some(String::from("VM not found (not_found)")));
+    assert_eq!(transport::error(r#"{"name": "test"}"#), None);
+    assert_eq!(transport::error(""), None);
+}
+
+/*
+ * Output
+ */
+#[test]
+fn table() {
+    let rows: Value = serde_json::from_str(r#"[
+        {"name": "test", "backend": "kvm", "image": "", "node": 1, "interfaces": [{"network": "lan", "ip": "10.0.0.2"}]},
+        {"name": "longername", "backend": "lxc", "node": 2, "interfaces": []}
+    ]"#).unwrap();
+
+    let out = output::table(&[("NAME", "name"), ("NODE", "node"), ("INTERFACES", "interfaces")], rows.as_array().unwrap());
+    let lines: Vec<&str> = out.lines().collect();
+
+    assert_eq!(lines[0], "NAME        NODE  INTERFACES");
+    assert_eq!(lines[1], "test        1     lan:10.0.0.2");
+    assert_eq!(lines[2], "longername  2");
+}
+
+#[test]
+fn object() {
+    let obj: Value = serde_json::from_str(r#"{"name": "lan", "dns": ["8.8.8.8", "8.8.4.4"]}"#).unwrap();
+    assert_eq!(output::object(&obj).as_str(), "dns:   8.8.8.8,8.8.4.4\nname:  lan\n");
+}
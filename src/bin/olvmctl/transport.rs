@@ -0,0 +1,343 @@
+/*
+ * Transport - Send a command to the daemon over one of its interfaces
+ */
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+use serde_json::value::Value;
+
+use openssl::rand::rand_bytes;
+use openssl::ssl::{SslConnector, SslMethod, SslFiletype};
+
+use command::Request;
+use frame;
+use signature;
+
+/*
+ * Time to wait for the daemon's response
+ */
+const TIMEOUT: u64 = 10;
+
+/*
+ * Number of times a UDP request is sent again when no response arrived
+ */
+const RETRIES: u32 = 2;
+
+/*
+ * Time between two checks of a job, in milliseconds
+ */
+const POLL_INTERVAL: u64 = 500;
+
+pub enum Transport {
+    Udp(String), // Daemon address
+    Http(String), // Base URL
+    Unix(String) // Socket path
+}
+
+/*
+ * Authentication token, given as "<name>:<secret>"
+ */
+pub struct Token {
+    pub name: String,
+    pub secret: String
+}
+
+impl Token {
+    pub fn parse(s: &str) -> Result<Token, String> {
+        match s.find(':') {
+            Some(i) => Ok(Token {
+                name: s[..i].to_string(),
+                secret: s[i + 1..].to_string()
+            }),
+            None => Err(String::from("invalid token, expected <name>:<secret>"))
+        }
+    }
+}
+
+/*
+ * Files used to setup HTTPS connections
+ */
+#[derive(Default)]
+pub struct TlsOptions {
+    pub ca: Option<String>,
+    pub certificate: Option<String>,
+    pub key: Option<String>
+}
+
+/*
+ * Wrap the command of a request in a signed envelope, see the daemon's auth module
+ */
+pub fn sign(token: &Token, id: &str, payload: &str, timestamp: u64, nonce: &str) -> String {
+    signature::seal(token.name.as_str(), token.secret.as_str(), Some(id), timestamp, nonce, payload)
+}
+
+/*
+ * Random hex-encoded nonce, so that the daemon can reject replayed envelopes
+ */
+fn nonce() -> Result<String, String> {
+    let mut buf = [0; 16];
+    try!(rand_bytes(&mut buf).map_err(|e| format!("Failed to generate a nonce: {}", e)));
+
+    Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/*
+ * Return the error message if a response is an error object
+ */
+pub fn error(res: &str) -> Option<String> {
+    let json: Value = match serde_json::from_str(res) {
+        Ok(json) => json,
+        Err(_) => return None
+    };
+
+    match (json.get("error").and_then(|e| e.as_str()), json.get("code").and_then(|c| c.as_str())) {
+        (Some(msg), Some(code)) => Some(format!("{} ({})", msg, code)),
+        _ => None
+    }
+}
+
+fn line(req: &Request) -> String {
+    format!("{} {}", req.command, req.argument).trim().to_string()
+}
+
+fn udp(addr: &str, token: Option<&Token>, req: &Request) -> Result<String, String> {
+    let (socket, dst) = try!(frame::bind(addr).map_err(|e| format!("UDP {}: {}", addr, e)));
+    let id = frame::request_id();
+
+    let data = match token {
+        Some(token) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            sign(token, id.as_str(), line(req).as_str(), now, try!(nonce()).as_str())
+        },
+        None => line(req)
+    };
+
+    let res = match frame::exchange(&socket, dst, id.as_str(), data.as_str(), Duration::from_secs(TIMEOUT), RETRIES) {
+        Ok(res) => res,
+        Err(e) => return Err(format!("UDP {}: {}", addr, e))
+    };
+
+    match String::from_utf8(res) {
+        Ok(s) => Ok(s.trim().to_string()),
+        Err(_) => Err(String::from("UDP: invalid response"))
+    }
+}
+
+fn unix(path: &str, req: &Request) -> Result<String, String> {
+    let mut socket = try!(UnixStream::connect(path).map_err(|e| format!("{}: {}", path, e)));
+    try!(socket.set_read_timeout(Some(Duration::from_secs(TIMEOUT))).map_err(|e| format!("{}: {}", path, e)));
+
+    try!(socket.write_all(format!("{}\n", line(req)).as_bytes()).map_err(|e| format!("{}: {}", path, e)));
+
+    let mut res = String::new();
+    try!(BufReader::new(socket).read_line(&mut res).map_err(|e| format!("{}: {}", path, e)));
+
+    Ok(res.trim().to_string())
+}
+
+/*
+ * Return the method, path and body of the HTTP request corresponding to a command
+ */
+pub fn route(req: &Request) -> Result<(&'static str, String, String), String> {
+    let arg = req.argument.clone();
+
+    // Name and snapshot fields of JSON arguments
+    let field = |key: &str| -> Result<String, String> {
+        let json: Value = try!(serde_json::from_str(req.argument.as_str()).map_err(|e| e.to_string()));
+        match json.get(key).and_then(|v| v.as_str()) {
+            Some(v) => Ok(v.to_string()),
+            None => Err(format!("missing `{}`", key))
+        }
+    };
+
+    let route = match req.command.as_str() {
+        "status" => ("GET", String::from("/status"), String::new()),
+
+        "listimg" => ("GET", String::from("/images"), String::new()),
+        "createimg" => ("POST", String::from("/images"), arg),
+        "getimg" => ("GET", format!("/images/{}", arg), String::new()),
+        "updateimg" => ("PUT", format!("/images/{}", try!(field("name"))), arg),
+        "delimg" => ("DELETE", format!("/images/{}", arg), String::new()),
+
+        "listvm" => ("GET", String::from("/vms"), String::new()),
+        "createvm" => ("POST", String::from("/vms"), arg),
+        "getvm" => ("GET", format!("/vms/{}", arg), String::new()),
+        "updatevm" => ("PUT", format!("/vms/{}", try!(field("name"))), arg),
+        "delvm" => ("DELETE", format!("/vms/{}", arg), String::new()),
+        "startvm" => ("POST", format!("/vms/{}/start", arg), String::new()),
+        "stopvm" => ("POST", format!("/vms/{}/stop", arg), String::new()),
+        "statusvm" => ("GET", format!("/vms/{}/status", arg), String::new()),
+        "statsvm" => ("GET", format!("/vms/{}/stats", arg), String::new()),
+        "migratevm" => ("POST", format!("/vms/{}/migrate", try!(field("name"))), arg),
+
+        "listsnap" => ("GET", format!("/vms/{}/snapshots", arg), String::new()),
+        "createsnap" => ("POST", format!("/vms/{}/snapshots", try!(field("vm"))), arg),
+        "delsnap" => ("DELETE", format!("/vms/{}/snapshots/{}", try!(field("vm")), try!(field("name"))), String::new()),
+        "restoresnap" => ("POST", format!("/vms/{}/snapshots/{}/restore", try!(field("vm")), try!(field("name"))), String::new()),
+
+        "listnet" => ("GET", String::from("/networks"), String::new()),
+        "createnet" => ("POST", String::from("/networks"), arg),
+        "getnet" => ("GET", format!("/networks/{}", arg), String::new()),
+        "updatenet" => ("PUT", format!("/networks/{}", try!(field("name"))), arg),
+        "delnet" => ("DELETE", format!("/networks/{}", arg), String::new()),
+
+        "listjob" => ("GET", String::from("/jobs"), String::new()),
+        "getjob" => ("GET", format!("/jobs/{}", arg), String::new()),
+        "canceljob" => ("POST", format!("/jobs/{}/cancel", arg), String::new()),
+
+        _ => return Err(format!("no HTTP route for '{}'", req.command))
+    };
+
+    Ok(route)
+}
+
+/*
+ * Send a request and read the response, return the status code and body
+ */
+fn exchange<S: Read + Write>(stream: &mut S, request: &str) -> Result<(u32, String), String> {
+    try!(stream.write_all(request.as_bytes()).map_err(|e| format!("HTTP: {}", e)));
+
+    let mut reader = BufReader::new(stream);
+
+    let mut status = String::new();
+    try!(reader.read_line(&mut status).map_err(|e| format!("HTTP: {}", e)));
+
+    let code = match status.split(' ').nth(1).and_then(|c| c.parse::<u32>().ok()) {
+        Some(code) => code,
+        None => return Err(format!("HTTP: invalid status line '{}'", status.trim()))
+    };
+
+    // Headers, only the body length is needed
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        try!(reader.read_line(&mut header).map_err(|e| format!("HTTP: {}", e)));
+
+        let header = header.trim();
+        if header.len() == 0 {
+            break;
+        }
+
+        if let Some(i) = header.find(':') {
+            if header[..i].eq_ignore_ascii_case("content-length") {
+                length = header[i + 1..].trim().parse::<usize>().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0; length];
+    try!(reader.read_exact(&mut body).map_err(|e| format!("HTTP: {}", e)));
+
+    match String::from_utf8(body) {
+        Ok(body) => Ok((code, body)),
+        Err(_) => Err(String::from("HTTP: invalid response body"))
+    }
+}
+
+fn http(url: &str, token: Option<&Token>, tls: &TlsOptions, req: &Request) -> Result<String, String> {
+    let (https, host) = if url.starts_with("https://") {
+        (true, &url[8..])
+    }
+    else if url.starts_with("http://") {
+        (false, &url[7..])
+    }
+    else {
+        return Err(format!("invalid URL '{}', expected http://<host>:<port>", url));
+    };
+
+    let host = host.trim_right_matches('/');
+    let (method, path, body) = try!(route(req));
+
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n", method, path, host, body.len());
+    if let Some(token) = token {
+        request.push_str(format!("Authorization: Bearer {}\r\n", token.secret).as_str());
+    }
+    request.push_str("\r\n");
+    request.push_str(body.as_str());
+
+    let stream = try!(TcpStream::connect(host).map_err(|e| format!("{}: {}", host, e)));
+    try!(stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT))).map_err(|e| format!("{}: {}", host, e)));
+
+    let (code, body) = if https {
+        let mut builder = try!(SslConnector::builder(SslMethod::tls()).map_err(|e| format!("TLS: {}", e)));
+
+        if let Some(ref ca) = tls.ca {
+            try!(builder.set_ca_file(ca.as_str()).map_err(|e| format!("TLS: {}: {}", ca, e)));
+        }
+        if let (&Some(ref cert), &Some(ref key)) = (&tls.certificate, &tls.key) {
+            try!(builder.set_certificate_chain_file(cert.as_str()).map_err(|e| format!("TLS: {}: {}", cert, e)));
+            try!(builder.set_private_key_file(key.as_str(), SslFiletype::PEM).map_err(|e| format!("TLS: {}: {}", key, e)));
+        }
+
+        let domain = match host.rfind(':') {
+            Some(i) => &host[..i],
+            None => host
+        };
+
+        let mut stream = try!(builder.build().connect(domain, stream).map_err(|e| format!("TLS: {}", e)));
+        try!(exchange(&mut stream, request.as_str()))
+    }
+    else {
+        let mut stream = stream;
+        try!(exchange(&mut stream, request.as_str()))
+    };
+
+    if code >= 400 {
+        return Err(error(body.as_str()).unwrap_or(format!("HTTP error {}", code)));
+    }
+
+    Ok(body)
+}
+
+/*
+ * Send a command and return the daemon's response
+ */
+pub fn send(transport: &Transport, token: Option<&Token>, tls: &TlsOptions, req: &Request) -> Result<String, String> {
+    let res = try!(match *transport {
+        Transport::Udp(ref addr) => udp(addr.as_str(), token, req),
+        Transport::Http(ref url) => http(url.as_str(), token, tls, req),
+        Transport::Unix(ref path) => unix(path.as_str(), req)
+    });
+
+    match error(res.as_str()) {
+        Some(e) => Err(e),
+        None => Ok(res)
+    }
+}
+
+/*
+ * Return the ID of the job started by a command, if any
+ */
+pub fn job(res: &str) -> Option<String> {
+    match serde_json::from_str::<Value>(res) {
+        Ok(json) => json.get("job").and_then(|j| j.as_str()).map(|j| j.to_string()),
+        Err(_) => None
+    }
+}
+
+/*
+ * Wait for the end of a job, and return the result of its command
+ */
+pub fn wait(transport: &Transport, token: Option<&Token>, tls: &TlsOptions, id: &str) -> Result<String, String> {
+    let req = Request {
+        command: String::from("getjob"),
+        argument: id.to_string()
+    };
+
+    loop {
+        let res = try!(send(transport, token, tls, &req));
+        let job: Value = try!(serde_json::from_str(res.as_str()).map_err(|_| format!("invalid job '{}'", id)));
+        let field = |key: &str| job.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+
+        match field("state").as_str() {
+            "done" => return Ok(field("result")),
+            "failed" | "cancelled" => return Err(format!("{} ({})", field("error"), field("code"))),
+            _ => thread::sleep(Duration::from_millis(POLL_INTERVAL))
+        };
+    }
+}
@@ -7,33 +7,187 @@ use std::io;
 
 use serde_json;
 use mongodb;
+use openssl;
 
 use config;
+use database;
+use events;
+use jobs;
+use locks;
+use metrics;
+use stats;
+use utils::exec::Runner;
+#[cfg(test)]
+use utils::exec::Process;
 
 /*
  * Global context struct
  */
 pub struct Context {
     pub conf: config::Config,
-    pub db: mongodb::db::Database
+    pub db: Box<database::Store>,
+    pub runner: Box<Runner>,
+    pub jobs: jobs::Queue,
+    pub locks: locks::Locks,
+    pub events: events::Bus,
+    pub metrics: metrics::Metrics,
+    pub stats: stats::Sampler
+}
+
+/*
+ * Contexts of the tests, with an in-memory database
+ */
+#[cfg(test)]
+impl Context {
+    pub fn test(conf: &str) -> Context {
+        Context::test_with_runner(conf, Box::new(Process))
+    }
+
+    /*
+     * Same, with a runner standing in for the backend scripts
+     */
+    pub fn test_with_runner(conf: &str, runner: Box<Runner>) -> Context {
+        Context {
+            conf: config::parse(conf).unwrap(),
+            db: Box::new(database::memory::Memory::new()),
+            runner: runner,
+            jobs: jobs::Queue::new(),
+            locks: locks::Locks::new(),
+            events: events::Bus::new(),
+            metrics: metrics::Metrics::new(),
+            stats: stats::Sampler::new()
+        }
+    }
+}
+
+/*
+ * Error kinds, each one has a stable machine-readable code returned to clients
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    Internal,
+    NotFound,
+    AlreadyExists,
+    InvalidArgument,
+    BackendFailure,
+    NetworkFailure,
+    StorageFailure,
+    Unauthorized,
+    PermissionDenied,
+    InvalidState,
+    Cancelled,
+    Busy
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ErrorKind::Internal => "internal",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::AlreadyExists => "already_exists",
+            ErrorKind::InvalidArgument => "invalid_argument",
+            ErrorKind::BackendFailure => "backend_failure",
+            ErrorKind::NetworkFailure => "network_failure",
+            ErrorKind::StorageFailure => "storage_failure",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::InvalidState => "invalid_state",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Busy => "busy"
+        }
+    }
+
+    pub fn from_code(code: &str) -> ErrorKind {
+        match code {
+            "not_found" => ErrorKind::NotFound,
+            "already_exists" => ErrorKind::AlreadyExists,
+            "invalid_argument" => ErrorKind::InvalidArgument,
+            "backend_failure" => ErrorKind::BackendFailure,
+            "network_failure" => ErrorKind::NetworkFailure,
+            "storage_failure" => ErrorKind::StorageFailure,
+            "unauthorized" => ErrorKind::Unauthorized,
+            "permission_denied" => ErrorKind::PermissionDenied,
+            "invalid_state" => ErrorKind::InvalidState,
+            "cancelled" => ErrorKind::Cancelled,
+            "busy" => ErrorKind::Busy,
+            _ => ErrorKind::Internal
+        }
+    }
 }
 
 /*
  * Error type
  */
 pub struct Error {
+    kind: ErrorKind,
     message: String
 }
 
 impl Error {
     pub fn new<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::Internal, message)
+    }
+
+    pub fn with_kind<S: Into<String>>(kind: ErrorKind, message: S) -> Error {
         Error {
+            kind: kind,
             message: message.into()
         }
     }
 
+    pub fn not_found<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::NotFound, message)
+    }
+
+    pub fn already_exists<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::AlreadyExists, message)
+    }
+
+    pub fn invalid_argument<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::InvalidArgument, message)
+    }
+
+    pub fn backend<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::BackendFailure, message)
+    }
+
+    pub fn network<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::NetworkFailure, message)
+    }
+
+    pub fn storage<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::StorageFailure, message)
+    }
+
+    pub fn unauthorized<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::Unauthorized, message)
+    }
+
+    pub fn permission_denied<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::PermissionDenied, message)
+    }
+
+    pub fn invalid_state<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::InvalidState, message)
+    }
+
+    pub fn cancelled<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::Cancelled, message)
+    }
+
+    pub fn busy<S: Into<String>>(message: S) -> Error {
+        Error::with_kind(ErrorKind::Busy, message)
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
     pub fn description_json(&self) -> String {
-        format!("{{\"error\": \"{}\"}}", self.description())
+        json!({
+            "error": self.message,
+            "code": self.kind.code()
+        }).to_string()
     }
 }
 
@@ -55,7 +209,7 @@ impl fmt::Display for Error {
 
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error: {}", self.message)
+        write!(f, "Error ({}): {}", self.kind.code(), self.message)
     }
 }
 
@@ -67,13 +221,19 @@ impl std::convert::From<io::Error> for Error {
 
 impl std::convert::From<mongodb::Error> for Error {
     fn from(e: mongodb::Error) -> Error {
-        Error::new(format!("Database error: {}", e.description()))
+        Error::storage(format!("Database error: {}", e.description()))
+    }
+}
+
+impl std::convert::From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Error {
+        Error::new(format!("TLS error: {}", e))
     }
 }
 
 impl std::convert::From<serde_json::Error> for Error {
     fn from(e: serde_json::Error) -> Error {
-        Error::new(format!("JSON error: {}", e.description()))
+        Error::invalid_argument(format!("JSON error: {}", e.description()))
     }
 }
 
@@ -14,7 +14,7 @@ fn default_i32() -> i32 {
 /*
  * Data structure to represent an image
  */
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Image {
     pub name: String,
 
@@ -33,14 +33,14 @@ impl Image {
     pub fn from_json(s: &str) -> Result<Image> {
         match serde_json::from_str(s) {
             Ok(img) => Ok(img),
-            Err(e) => Err(Error::new(format!("Failed to parse JSON into an Image structure: {}", e)))
+            Err(e) => Err(Error::invalid_argument(format!("Failed to parse JSON into an Image structure: {}", e)))
         }
     }
 
     pub fn from_bson(doc: Document) -> Result<Image> {
         match bson::from_bson::<Image>(Bson::Document(doc)) {
             Ok(img) => Ok(img),
-            Err(e) => Err(Error::new(e.description()))
+            Err(e) => Err(Error::storage(e.description()))
         }
     }
 
@@ -66,7 +66,7 @@ impl Image {
 /*
  * Data structure to represent a network interface attached to a VM
  */
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Interface {
     pub network: String, // Name of the network to connect the interface to
     pub ip: String, // Interface's IPv4 address
@@ -75,10 +75,110 @@ pub struct Interface {
     pub mac: String, // MAC address, set this to override the random default address
 }
 
+/*
+ * Lifecycle state of a VM
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    #[serde(rename = "defined")]
+    Defined, // Stored, not created by the backend yet
+    #[serde(rename = "creating")]
+    Creating,
+    #[serde(rename = "stopped")]
+    Stopped,
+    #[serde(rename = "starting")]
+    Starting,
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "stopping")]
+    Stopping,
+    #[serde(rename = "migrating")]
+    Migrating,
+    #[serde(rename = "crashed")]
+    Crashed, // The VM process died while it was running
+    #[serde(rename = "error")]
+    Error, // A backend script failed, the actual state is unknown
+    #[serde(rename = "unknown")]
+    Unknown // Stored before states existed, probed when the server starts
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            State::Defined => "defined",
+            State::Creating => "creating",
+            State::Stopped => "stopped",
+            State::Starting => "starting",
+            State::Running => "running",
+            State::Stopping => "stopping",
+            State::Migrating => "migrating",
+            State::Crashed => "crashed",
+            State::Error => "error",
+            State::Unknown => "unknown"
+        }
+    }
+}
+
+/*
+ * VMs stored before states existed were already created, and may be running
+ */
+fn default_state() -> State {
+    State::Unknown
+}
+
+/*
+ * What to do when a VM crashes
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Restart {
+    #[serde(rename = "never")]
+    Never,
+    #[serde(rename = "on-failure")]
+    OnFailure, // Restart at most 'max_retries' times
+    #[serde(rename = "always")]
+    Always
+}
+
+fn default_restart() -> Restart {
+    Restart::Never
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_backoff() -> u32 {
+    5
+}
+
+fn default_restart_policy() -> RestartPolicy {
+    RestartPolicy {
+        kind: default_restart(),
+        max_retries: default_max_retries(),
+        backoff: default_backoff()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestartPolicy {
+    #[serde(rename = "type", default = "default_restart")]
+    pub kind: Restart,
+
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    #[serde(default = "default_backoff")]
+    pub backoff: u32 // Seconds before the first restart, doubled after each attempt
+}
+
+fn default_false() -> bool {
+    false
+}
+
 /*
  * Data structure to represent a vm in database
  */
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VM {
     pub name: String,
 
@@ -93,6 +193,15 @@ pub struct VM {
     #[serde(default = "Vec::new")]
     pub interfaces: Vec<Interface>,
 
+    #[serde(default = "default_state")]
+    pub state: State, // Managed by the VM handlers, ignored when sent by clients
+
+    #[serde(default = "default_restart_policy")]
+    pub restart_policy: RestartPolicy,
+
+    #[serde(default = "default_false")]
+    pub autostart: bool, // Start the VM when the server starts
+
     #[serde(default = "HashMap::new")]
     pub parameters: HashMap<String, String>
 }
@@ -101,14 +210,14 @@ impl VM {
     pub fn from_json(s: &str) -> Result<VM> {
         match serde_json::from_str(s) {
             Ok(vm) => Ok(vm),
-            Err(e) => Err(Error::new(format!("Failed to parse JSON into a VM structure: {}", e)))
+            Err(e) => Err(Error::invalid_argument(format!("Failed to parse JSON into a VM structure: {}", e)))
         }
     }
 
     pub fn from_bson(doc: Document) -> Result<VM> {
         match bson::from_bson::<VM>(Bson::Document(doc)) {
             Ok(vm) => Ok(vm),
-            Err(e) => Err(Error::new(e.description()))
+            Err(e) => Err(Error::storage(e.description()))
         }
     }
 
@@ -134,7 +243,7 @@ impl VM {
 /*
  * Data structure to represent the recoverable saved state of a VM
  */
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Snapshot {
     #[serde(default = "String::new")]
     pub vm: String, // Name of the VM
@@ -150,14 +259,14 @@ impl Snapshot {
     pub fn from_json(s: &str) -> Result<Snapshot> {
         match serde_json::from_str(s) {
             Ok(snap) => Ok(snap),
-            Err(e) => Err(Error::new(format!("Failed to parse JSON into a Snapshot structure: {}", e)))
+            Err(e) => Err(Error::invalid_argument(format!("Failed to parse JSON into a Snapshot structure: {}", e)))
         }
     }
 
     pub fn from_bson(doc: Document) -> Result<Snapshot> {
         match bson::from_bson::<Snapshot>(Bson::Document(doc)) {
             Ok(snap) => Ok(snap),
-            Err(e) => Err(Error::new(e.description()))
+            Err(e) => Err(Error::storage(e.description()))
         }
     }
 
@@ -183,7 +292,7 @@ impl Snapshot {
 /*
  * Data structure to represent a network
  */
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Network {
     pub name: String,
 
@@ -207,14 +316,134 @@ impl Network {
     pub fn from_json(s: &str) -> Result<Network> {
         match serde_json::from_str(s) {
             Ok(net) => Ok(net),
-            Err(e) => Err(Error::new(format!("Failed to parse JSON into an Network structure: {}", e)))
+            Err(e) => Err(Error::invalid_argument(format!("Failed to parse JSON into an Network structure: {}", e)))
         }
     }
 
     pub fn from_bson(doc: Document) -> Result<Network> {
         match bson::from_bson::<Network>(Bson::Document(doc)) {
             Ok(net) => Ok(net),
-            Err(e) => Err(Error::new(e.description()))
+            Err(e) => Err(Error::storage(e.description()))
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let json = match serde_json::to_string(self) {
+            Ok(json) => json,
+            Err(e) => return Err(Error::new(e.description()))
+        };
+
+        Ok(json)
+    }
+
+    pub fn to_bson(&self) -> Result<Document> {
+        let doc = match bson::to_bson(self) {
+            Ok(bson) => try!(bson.as_document().ok_or(Error::new("Invalid document"))).clone(),
+            Err(e) => return Err(Error::new(e.description()))
+        };
+
+        Ok(doc)
+    }
+}
+
+/*
+ * Progress of a job
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    #[serde(rename = "queued")]
+    Queued,
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "done")]
+    Done,
+    #[serde(rename = "failed")]
+    Failed,
+    #[serde(rename = "cancelled")]
+    Cancelled
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled"
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match *self {
+            JobState::Done | JobState::Failed | JobState::Cancelled => true,
+            _ => false
+        }
+    }
+}
+
+fn default_u32() -> u32 {
+    0
+}
+
+fn default_i64() -> i64 {
+    0
+}
+
+/*
+ * Data structure to represent a command running in the background
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: String,
+
+    #[serde(default = "default_i32")]
+    pub node: i32,
+
+    pub command: String,
+
+    #[serde(default = "String::new")]
+    pub argument: String,
+
+    #[serde(default = "String::new")]
+    pub client: String, // Client that submitted the job
+    #[serde(default = "String::new")]
+    pub identity: String, // Token or user the client authenticated as
+
+    pub state: JobState,
+
+    #[serde(default = "default_u32")]
+    pub progress: u32, // Percentage
+
+    #[serde(default = "String::new")]
+    pub result: String, // Output of the command, once done
+
+    #[serde(default = "String::new")]
+    pub error: String, // Error message and code, once failed
+    #[serde(default = "String::new")]
+    pub code: String,
+
+    // Unix timestamps, 0 until the corresponding step is reached
+    #[serde(default = "default_i64")]
+    pub created: i64,
+    #[serde(default = "default_i64")]
+    pub started: i64,
+    #[serde(default = "default_i64")]
+    pub finished: i64
+}
+
+impl Job {
+    pub fn from_json(s: &str) -> Result<Job> {
+        match serde_json::from_str(s) {
+            Ok(job) => Ok(job),
+            Err(e) => Err(Error::invalid_argument(format!("Failed to parse JSON into a Job structure: {}", e)))
+        }
+    }
+
+    pub fn from_bson(doc: Document) -> Result<Job> {
+        match bson::from_bson::<Job>(Bson::Document(doc)) {
+            Ok(job) => Ok(job),
+            Err(e) => Err(Error::storage(e.description()))
         }
     }
 
@@ -236,3 +465,118 @@ impl Network {
         Ok(doc)
     }
 }
+
+/*
+ * Data structure to represent an entry of the audit log
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Audit {
+    #[serde(default = "default_i32")]
+    pub node: i32,
+
+    pub time: i64, // Unix timestamp of the end of the command
+
+    #[serde(default = "String::new")]
+    pub client: String, // Connection the command came from
+    #[serde(default = "String::new")]
+    pub identity: String, // Token or user that sent the command
+
+    pub command: String,
+
+    // Object targeted by the command ("vm", "image" or "network"), empty for the other commands
+    #[serde(default = "String::new")]
+    pub kind: String,
+    #[serde(default = "String::new")]
+    pub object: String,
+
+    #[serde(default = "String::new")]
+    pub argument: String, // Argument of the command, with the secrets redacted
+
+    #[serde(default = "String::new")]
+    pub job: String, // ID of the job the command ran as, if any
+
+    #[serde(default = "default_false")]
+    pub success: bool,
+
+    #[serde(default = "String::new")]
+    pub error: String, // Error message and code, on failure
+    #[serde(default = "String::new")]
+    pub code: String,
+
+    #[serde(default = "default_i64")]
+    pub duration: i64 // Milliseconds
+}
+
+impl Audit {
+    pub fn from_bson(doc: Document) -> Result<Audit> {
+        match bson::from_bson::<Audit>(Bson::Document(doc)) {
+            Ok(entry) => Ok(entry),
+            Err(e) => Err(Error::storage(e.description()))
+        }
+    }
+
+    pub fn to_bson(&self) -> Result<Document> {
+        let doc = match bson::to_bson(self) {
+            Ok(bson) => try!(bson.as_document().ok_or(Error::new("Invalid document"))).clone(),
+            Err(e) => return Err(Error::new(e.description()))
+        };
+
+        Ok(doc)
+    }
+}
+
+/*
+ * Criteria of a 'listaudit' command, unset fields match any entry
+ */
+#[derive(Deserialize, Debug, Default)]
+pub struct AuditFilter {
+    #[serde(default = "default_i64")]
+    pub since: i64, // Unix timestamps, inclusive
+    #[serde(default = "default_i64")]
+    pub until: i64,
+
+    #[serde(default = "String::new")]
+    pub kind: String,
+    #[serde(default = "String::new")]
+    pub object: String,
+    #[serde(default = "String::new")]
+    pub command: String,
+
+    #[serde(default = "default_u32")]
+    pub limit: u32 // Only return the latest entries
+}
+
+impl AuditFilter {
+    pub fn from_json(s: &str) -> Result<AuditFilter> {
+        if s.len() == 0 {
+            return Ok(AuditFilter::default());
+        }
+
+        match serde_json::from_str(s) {
+            Ok(filter) => Ok(filter),
+            Err(e) => Err(Error::invalid_argument(format!("Failed to parse JSON into an audit filter: {}", e)))
+        }
+    }
+
+    pub fn matches(&self, entry: &Audit) -> bool {
+        (self.since == 0 || entry.time >= self.since) &&
+        (self.until == 0 || entry.time <= self.until) &&
+        (self.kind.len() == 0 || entry.kind == self.kind) &&
+        (self.object.len() == 0 || entry.object == self.object) &&
+        (self.command.len() == 0 || entry.command == self.command)
+    }
+
+    /*
+     * Filter entries sorted by time, used by the storage backends unable to do it themselves
+     */
+    pub fn apply(&self, entries: Vec<Audit>) -> Vec<Audit> {
+        let mut entries: Vec<Audit> = entries.into_iter().filter(|e| self.matches(e)).collect();
+
+        if self.limit > 0 && entries.len() > self.limit as usize {
+            let skip = entries.len() - self.limit as usize;
+            entries.drain(..skip);
+        }
+
+        entries
+    }
+}
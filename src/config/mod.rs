@@ -2,6 +2,7 @@
  * Configuration loading
  */
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 
@@ -20,26 +21,396 @@ pub struct Global {
 /*
  * Database configuration
  */
+fn default_database_type() -> String {
+    String::from("mongodb")
+}
+
+fn default_database_host() -> String {
+    String::from("127.0.0.1")
+}
+
+fn default_database_port() -> u16 {
+    27017
+}
+
+fn default_database_path() -> String {
+    String::from("/var/lib/olvm/db")
+}
+
 #[derive(Deserialize)]
 pub struct Database {
+    #[serde(rename = "type", default = "default_database_type")]
+    pub kind: String, // Storage backend: "mongodb", "file" or "memory"
+
+    // MongoDB
+    #[serde(default = "default_database_host")]
     pub host: String,
-    pub port: u16
+    #[serde(default = "default_database_port")]
+    pub port: u16,
+
+    // File
+    #[serde(default = "default_database_path")]
+    pub path: String
 }
 
 /*
  * UDP interface configuration
  */
+fn default_udp_workers() -> usize {
+    8
+}
+
+fn default_udp_queue() -> usize {
+    64
+}
+
 #[derive(Deserialize)]
 pub struct UDP {
-    pub addr: String
+    pub addr: String,
+
+    #[serde(default = "default_udp_workers")]
+    pub workers: usize, // Number of commands handled at the same time
+
+    #[serde(default = "default_udp_queue")]
+    pub queue: usize // Number of commands waiting for a worker, further commands are rejected
 }
 
 /*
  * HTTP interface configuration
  */
+#[derive(Deserialize)]
+pub struct TLS {
+    pub certificate: String, // PEM certificate chain
+    pub key: String, // PEM private key
+    pub client_ca: Option<String> // When set, clients must present a certificate signed by this CA
+}
+
 #[derive(Deserialize)]
 pub struct HTTP {
-    pub addr: String
+    pub addr: String,
+    pub tls: Option<TLS>,
+
+    #[serde(default = "Vec::new")]
+    pub cors: Vec<String> // Origins allowed to make cross-origin requests, "*" allows any origin
+}
+
+/*
+ * Unix interface configuration
+ */
+fn default_unix_mode() -> String {
+    String::from("0660")
+}
+
+fn default_unix_role() -> String {
+    String::from("admin")
+}
+
+#[derive(Deserialize)]
+pub struct Unix {
+    pub path: String,
+
+    #[serde(default = "default_unix_mode")]
+    pub mode: String, // Octal permissions of the socket file
+
+    #[serde(default = "Vec::new")]
+    pub uids: Vec<u32>, // Users allowed to connect, in addition to root
+    #[serde(default = "Vec::new")]
+    pub gids: Vec<u32>, // Groups allowed to connect (primary or supplementary), only root when both lists are empty
+
+    #[serde(default = "default_unix_role")]
+    pub role: String // Role given to the local clients
+}
+
+/*
+ * Authentication configuration
+ */
+pub const BUILTIN_ROLES: &'static [&'static str] = &["read-only", "operator", "admin"];
+
+fn default_token_role() -> String {
+    String::from("read-only")
+}
+
+#[derive(Deserialize)]
+pub struct Token {
+    pub name: String,
+    pub secret: String,
+
+    #[serde(default = "default_token_role")]
+    pub role: String
+}
+
+#[derive(Deserialize)]
+pub struct Role {
+    pub name: String,
+    pub commands: Vec<String> // Allowed commands, a trailing '*' matches any suffix
+}
+
+#[derive(Deserialize)]
+pub struct Auth {
+    #[serde(default = "Vec::new")]
+    pub token: Vec<Token>,
+
+    #[serde(default = "Vec::new")]
+    pub role: Vec<Role>, // Custom roles, in addition to the built-in ones
+
+    pub remote: Option<String> // Name of the token used to sign commands sent to other nodes
+}
+
+impl Auth {
+    /*
+     * Return the token corresponding to a name
+     */
+    pub fn get_token(&self, name: &str) -> Option<&Token> {
+        for t in &self.token {
+            if t.name.as_str() == name {
+                return Some(t)
+            }
+        }
+
+        None
+    }
+
+    /*
+     * Return the role corresponding to a name
+     */
+    pub fn get_role(&self, name: &str) -> Option<&Role> {
+        for r in &self.role {
+            if r.name.as_str() == name {
+                return Some(r)
+            }
+        }
+
+        None
+    }
+
+    /*
+     * Return the token used to sign commands sent to other nodes
+     */
+    pub fn get_remote_token(&self) -> Option<&Token> {
+        match self.remote {
+            Some(ref name) => self.get_token(name.as_str()),
+            None => None
+        }
+    }
+}
+
+/*
+ * Supervisor configuration
+ */
+fn default_supervisor_interval() -> u64 {
+    10
+}
+
+fn default_supervisor() -> Supervisor {
+    Supervisor {
+        interval: default_supervisor_interval()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Supervisor {
+    #[serde(default = "default_supervisor_interval")]
+    pub interval: u64 // Seconds between two checks of the running VMs, at least 1
+}
+
+/*
+ * Resource usage sampling configuration
+ */
+fn default_stats_interval() -> u64 {
+    5
+}
+
+fn default_stats() -> Stats {
+    Stats {
+        interval: default_stats_interval()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Stats {
+    #[serde(default = "default_stats_interval")]
+    pub interval: u64 // Seconds between two samples of the running VMs, at least 1
+}
+
+/*
+ * Job workers configuration
+ */
+fn default_jobs_workers() -> usize {
+    4
+}
+
+fn default_jobs_retention() -> u64 {
+    7 * 24 * 3600
+}
+
+fn default_jobs() -> Jobs {
+    Jobs {
+        workers: default_jobs_workers(),
+        retention: default_jobs_retention()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Jobs {
+    #[serde(default = "default_jobs_workers")]
+    pub workers: usize, // Number of jobs running at the same time
+
+    #[serde(default = "default_jobs_retention")]
+    pub retention: u64 // Seconds finished jobs are kept for
+}
+
+/*
+ * Audit log configuration
+ */
+fn default_audit_reads() -> bool {
+    false
+}
+
+fn default_audit_limit() -> u32 {
+    100
+}
+
+fn default_audit_retention() -> u64 {
+    90 * 24 * 3600
+}
+
+fn default_audit() -> Audit {
+    Audit {
+        reads: default_audit_reads(),
+        limit: default_audit_limit(),
+        retention: default_audit_retention()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Audit {
+    #[serde(default = "default_audit_reads")]
+    pub reads: bool, // Also record the read-only commands (list*, get*, status*)
+
+    #[serde(default = "default_audit_limit")]
+    pub limit: u32, // Entries returned by 'listaudit' when the filter has no limit
+
+    #[serde(default = "default_audit_retention")]
+    pub retention: u64 // Seconds entries are kept for, 0 keeps them forever
+}
+
+/*
+ * Webhooks configuration
+ */
+fn default_webhooks_retries() -> u32 {
+    5
+}
+
+fn default_webhooks_backoff() -> u64 {
+    10
+}
+
+fn default_webhooks_timeout() -> u64 {
+    10
+}
+
+fn default_webhooks_queue() -> usize {
+    1000
+}
+
+#[derive(Deserialize)]
+pub struct Webhook {
+    pub name: String,
+    pub url: String, // http:// or https:// URL the events are posted to
+    pub secret: String, // Key of the HMAC-SHA256 signature of the payloads
+
+    #[serde(default = "Vec::new")]
+    pub events: Vec<String> // Types of the events to send, a trailing '*' matches any suffix, all of them when empty
+}
+
+#[derive(Deserialize)]
+pub struct Webhooks {
+    #[serde(default = "Vec::new")]
+    pub target: Vec<Webhook>,
+
+    #[serde(default = "default_webhooks_retries")]
+    pub retries: u32, // Number of retries of a failed delivery
+
+    #[serde(default = "default_webhooks_backoff")]
+    pub backoff: u64, // Seconds before the first retry, doubled after each retry
+
+    #[serde(default = "default_webhooks_timeout")]
+    pub timeout: u64, // Seconds to wait for a target to answer
+
+    #[serde(default = "default_webhooks_queue")]
+    pub queue: usize, // Events waiting to be posted, and deliveries waiting for a retry, per target
+
+    pub dead_letter: Option<String> // File the undelivered events are appended to
+}
+
+/*
+ * Logging configuration
+ */
+fn default_log_level() -> String {
+    String::from("info")
+}
+
+fn default_log_sink_type() -> String {
+    String::from("stderr")
+}
+
+fn default_log_format() -> String {
+    String::from("text")
+}
+
+fn default_log_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_log_keep() -> usize {
+    5
+}
+
+fn default_log_sinks() -> Vec<LogSink> {
+    vec![LogSink {
+        kind: default_log_sink_type(),
+        format: default_log_format(),
+        path: None,
+        max_size: default_log_max_size(),
+        keep: default_log_keep()
+    }]
+}
+
+fn default_log() -> Log {
+    Log {
+        level: default_log_level(),
+        modules: HashMap::new(),
+        sink: default_log_sinks()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogSink {
+    #[serde(rename = "type", default = "default_log_sink_type")]
+    pub kind: String, // "stderr", "file", "syslog" or "journald"
+
+    #[serde(default = "default_log_format")]
+    pub format: String, // "text" or "json", for the stderr and file sinks
+
+    pub path: Option<String>, // File to write to, or socket of the syslog and journald daemons
+
+    // File
+    #[serde(default = "default_log_max_size")]
+    pub max_size: u64, // Bytes written before the file is rotated, 0 to never rotate it
+    #[serde(default = "default_log_keep")]
+    pub keep: usize // Number of rotated files kept
+}
+
+#[derive(Deserialize)]
+pub struct Log {
+    #[serde(default = "default_log_level")]
+    pub level: String, // "error", "warn", "info" or "debug"
+
+    #[serde(default = "HashMap::new")]
+    pub modules: HashMap<String, String>, // Levels of specific modules, overriding the global one
+
+    #[serde(default = "default_log_sinks")]
+    pub sink: Vec<LogSink>
 }
 
 /*
@@ -81,6 +452,26 @@ pub struct Config {
     pub database: Database,
     pub udp: Option<UDP>,
     pub http: Option<HTTP>,
+    pub unix: Option<Unix>,
+    pub auth: Option<Auth>,
+
+    #[serde(default = "default_supervisor")]
+    pub supervisor: Supervisor,
+
+    #[serde(default = "default_stats")]
+    pub stats: Stats,
+
+    #[serde(default = "default_jobs")]
+    pub jobs: Jobs,
+
+    #[serde(default = "default_audit")]
+    pub audit: Audit,
+
+    pub webhooks: Option<Webhooks>,
+
+    #[serde(default = "default_log")]
+    pub log: Log,
+
     pub backend: Vec<Backend>
 }
 
@@ -104,18 +495,25 @@ impl Config {
     pub fn get_image_path(&self, backend: &str, name: &str) -> Result<String> {
         let backend = match self.get_backend(backend) {
             Some(b) => b,
-            None => return Err(Error::new("Unknown backend"))
+            None => return Err(Error::invalid_argument("Unknown backend"))
         };
 
         Ok(format!("{}/{}.image", backend.image.path, name))
     }
 
+    /*
+     * Return the directory holding the VMs of a backend
+     */
+    pub fn get_vm_dir(&self, backend: &str) -> String {
+        // TODO: Use configuration
+        format!("/var/lib/wir/olvm/vms/{}", backend)
+    }
+
     /*
      * Return the path of a VM's disk image
      */
     pub fn get_vm_disk(&self, backend: &str, name: &str) -> Result<String> {
-        // TODO: Use configuration
-        Ok(format!("/var/lib/wir/olvm/vms/{}/{}/disk.data", backend, name))
+        Ok(format!("{}/{}/disk.data", self.get_vm_dir(backend), name))
     }
 }
 
@@ -134,11 +532,60 @@ pub fn open(path: &str) -> Result<Config> {
         Err(e) => return Err(Error::new(format!("read: {}", e)))
     };
 
-    // Parse the configuration as a toml document
-    let conf: Config = match toml::from_str(data.as_str()) {
+    parse(data.as_str())
+}
+
+/*
+ * Parse the configuration from a toml document
+ */
+pub fn parse(data: &str) -> Result<Config> {
+    let conf: Config = match toml::from_str(data) {
         Ok(conf) => conf,
         Err(e) => return Err(Error::new(format!("parse: {}", e)))
     };
 
+    try!(check_roles(&conf));
+    try!(check_intervals(&conf));
+
     Ok(conf)
 }
+
+/*
+ * Check that the periodic tasks do not run in a busy loop
+ */
+fn check_intervals(conf: &Config) -> Result<()> {
+    if conf.supervisor.interval == 0 {
+        return Err(Error::new("supervisor: interval must be at least 1 second"));
+    }
+
+    if conf.stats.interval == 0 {
+        return Err(Error::new("stats: interval must be at least 1 second"));
+    }
+
+    Ok(())
+}
+
+/*
+ * Check that every role given to a client is built-in or defined in [[auth.role]]
+ */
+fn check_roles(conf: &Config) -> Result<()> {
+    let known = |role: &str| {
+        BUILTIN_ROLES.contains(&role) || conf.auth.as_ref().and_then(|a| a.get_role(role)).is_some()
+    };
+
+    if let Some(ref unix) = conf.unix {
+        if !known(unix.role.as_str()) {
+            return Err(Error::new(format!("unix: unknown role '{}'", unix.role)));
+        }
+    }
+
+    if let Some(ref auth) = conf.auth {
+        for t in &auth.token {
+            if !known(t.role.as_str()) {
+                return Err(Error::new(format!("auth: unknown role '{}' for token '{}'", t.role, t.name)));
+            }
+        }
+    }
+
+    Ok(())
+}
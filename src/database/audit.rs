@@ -0,0 +1,29 @@
+/*
+ * Audit log database transations
+ */
+
+use std::vec::Vec;
+
+use common::{Context, Result};
+use common::structs::{Audit, AuditFilter};
+
+/*
+ * Append an entry to the audit log
+ */
+pub fn create(ctx: &Context, entry: &Audit) -> Result<()> {
+    ctx.db.audit_create(entry)
+}
+
+/*
+ * List the entries matching a filter, oldest first
+ */
+pub fn list(ctx: &Context, filter: &AuditFilter) -> Result<Vec<Audit>> {
+    ctx.db.audit_list(filter)
+}
+
+/*
+ * Remove the entries recorded before the specified unix timestamp
+ */
+pub fn prune(ctx: &Context, before: i64) -> Result<()> {
+    ctx.db.audit_prune(before)
+}
@@ -0,0 +1,498 @@
+/*
+ * File storage backend
+ * Every object is stored as a JSON document in its own file, under a per-collection directory:
+ *
+ * <path>/images/<name>.json
+ * <path>/vms/<name>.json
+ * <path>/networks/<name>.json
+ * <path>/snapshots/<vm>/<name>.json
+ * <path>/jobs/<id>.json
+ *
+ * Files are never modified in place: a new version is written to a temporary file,
+ * flushed to disk, then renamed over the previous one
+ *
+ * Like the queries of the MongoDB store, reads, updates and deletions only see the objects of the local node
+ * Listings skip the files that can not be read, so that a damaged object does not hide the others
+ *
+ * The audit log is the exception, its entries are appended to <path>/audit.log as JSON lines.
+ * The file is rewritten without the entries older than the retention period when it is pruned.
+ */
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::vec::Vec;
+
+use serde::{Serialize, Deserialize};
+use serde_json;
+use serde_json::value::Value;
+
+use common::{Result, Error};
+use common::structs::{Image, VM, Network, Snapshot, Job, Audit, AuditFilter};
+
+use super::Store;
+
+const COLLECTIONS: [&'static str; 5] = ["images", "vms", "networks", "snapshots", "jobs"];
+
+pub struct FileStore {
+    path: PathBuf,
+    node: i32,
+    lock: Mutex<()> // Serializes writes, so that existence checks and writes are atomic
+}
+
+impl FileStore {
+    /*
+     * Open the store located in the specified directory, creating it if needed
+     */
+    pub fn open(path: &str, node: i32) -> Result<FileStore> {
+        let root = PathBuf::from(path);
+
+        for c in COLLECTIONS.iter() {
+            let dir = root.join(c);
+            try!(storage(&dir, fs::create_dir_all(&dir)));
+        }
+
+        try!(cleanup(&root));
+
+        info!("database", "using file database"; path = path);
+
+        Ok(FileStore {
+            path: root,
+            node: node,
+            lock: Mutex::new(())
+        })
+    }
+
+    fn file(&self, collection: &str, name: &str) -> Result<PathBuf> {
+        try!(check_name(name));
+        Ok(self.path.join(collection).join(format!("{}.json", name)))
+    }
+
+    fn snapshot_dir(&self, vm: &str) -> Result<PathBuf> {
+        try!(check_name(vm));
+        Ok(self.path.join("snapshots").join(vm))
+    }
+
+    /*
+     * Write a new object, failing if it already exists
+     */
+    fn insert<T: Serialize>(&self, path: &Path, obj: &T, what: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+
+        if path.exists() {
+            return Err(Error::already_exists(format!("{} already exists", what)));
+        }
+
+        write(path, obj)
+    }
+
+    /*
+     * Check if an existing object belongs to the local node
+     */
+    fn is_local(&self, obj: &Value) -> bool {
+        obj.get("node").and_then(|n| n.as_i64()).unwrap_or(0) == self.node as i64
+    }
+
+    /*
+     * Read an object of the local node, with a proper error message if it does not exist
+     */
+    fn get<T: Deserialize>(&self, path: &Path, what: &str) -> Result<T> {
+        if !path.exists() {
+            return Err(Error::not_found(format!("{} not found", what)));
+        }
+
+        let obj: Value = try!(read(path));
+        if !self.is_local(&obj) {
+            return Err(Error::not_found(format!("{} not found", what)));
+        }
+
+        match serde_json::from_value(obj) {
+            Ok(obj) => Ok(obj),
+            Err(e) => Err(Error::storage(format!("{}: {}", path.display(), e)))
+        }
+    }
+
+    /*
+     * Overwrite an existing object of the local node
+     */
+    fn replace<T: Serialize>(&self, path: &Path, obj: &T, what: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+
+        if !path.exists() || !self.is_local(&try!(read(path))) {
+            return Err(Error::not_found(format!("{} not found", what)));
+        }
+
+        write(path, obj)
+    }
+
+    /*
+     * Remove an object of the local node, if it exists
+     */
+    fn remove(&self, path: &Path) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+
+        if path.exists() && !self.is_local(&try!(read(path))) {
+            return Ok(());
+        }
+
+        match fs::remove_file(path) {
+            Ok(_) => {},
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::storage(format!("{}: {}", path.display(), e)))
+        };
+
+        sync_dir(path)
+    }
+
+    /*
+     * Return all the objects of a directory belonging to the local node
+     */
+    fn list<T: Deserialize, F: Fn(&T) -> i32>(&self, dir: &Path, node: F) -> Result<Vec<T>> {
+        let mut objs = Vec::new();
+
+        if !dir.exists() {
+            return Ok(objs);
+        }
+
+        for entry in try!(storage(dir, fs::read_dir(dir))) {
+            let path = try!(storage(dir, entry)).path();
+
+            if !path.extension().map(|e| e == "json").unwrap_or(false) {
+                continue;
+            }
+
+            // A damaged file only hides its own object
+            match read::<T>(&path) {
+                Ok(obj) => {
+                    if node(&obj) == self.node {
+                        objs.push(obj);
+                    }
+                },
+                Err(e) => error!("database", "skipping unreadable object: {}", e)
+            };
+        }
+
+        Ok(objs)
+    }
+}
+
+/*
+ * Object names are used as file names, make sure they can not escape their directory
+ */
+fn check_name(name: &str) -> Result<()> {
+    if name.len() == 0 || name.starts_with('.') || name.contains('/') || name.contains('\0') {
+        return Err(Error::invalid_argument(format!("Invalid object name '{}'", name)));
+    }
+
+    Ok(())
+}
+
+/*
+ * Remove the temporary files left over by an interrupted write
+ */
+fn cleanup(dir: &Path) -> Result<()> {
+    for entry in try!(storage(dir, fs::read_dir(dir))) {
+        let path = try!(storage(dir, entry)).path();
+
+        if path.is_dir() {
+            try!(cleanup(&path));
+        }
+        else if path.extension().map(|e| e == "tmp").unwrap_or(false) {
+            try!(storage(&path, fs::remove_file(&path)));
+        }
+    }
+
+    Ok(())
+}
+
+/*
+ * Report the I/O errors of the store as storage failures, naming the file
+ */
+fn storage<T>(path: &Path, res: io::Result<T>) -> Result<T> {
+    match res {
+        Ok(v) => Ok(v),
+        Err(e) => Err(Error::storage(format!("{}: {}", path.display(), e)))
+    }
+}
+
+/*
+ * Flush the directory containing the specified file, to persist renames and deletions
+ */
+fn sync_dir(path: &Path) -> Result<()> {
+    let dir = try!(path.parent().ok_or(Error::new("Invalid database path")));
+    try!(storage(dir, try!(storage(dir, File::open(dir))).sync_all()));
+
+    Ok(())
+}
+
+/*
+ * Atomically write an object to the specified file
+ */
+fn write<T: Serialize>(path: &Path, obj: &T) -> Result<()> {
+    let data = try!(serde_json::to_string_pretty(obj));
+    let tmp = path.with_extension(format!("{}.tmp", process::id()));
+
+    {
+        let mut f = try!(storage(&tmp, File::create(&tmp)));
+        try!(storage(&tmp, f.write_all(data.as_bytes())));
+        try!(storage(&tmp, f.sync_all()));
+    }
+
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(Error::storage(format!("{}: {}", path.display(), e)));
+    }
+
+    sync_dir(path)
+}
+
+/*
+ * Read an object from the specified file
+ */
+fn read<T: Deserialize>(path: &Path) -> Result<T> {
+    let mut data = String::new();
+    let mut f = try!(storage(path, File::open(path)));
+
+    try!(storage(path, f.read_to_string(&mut data)));
+
+    match serde_json::from_str(data.as_str()) {
+        Ok(obj) => Ok(obj),
+        Err(e) => Err(Error::storage(format!("{}: {}", path.display(), e)))
+    }
+}
+
+impl Store for FileStore {
+    /*
+     * Images
+     */
+    fn image_create(&self, img: &Image) -> Result<()> {
+        let path = try!(self.file("images", img.name.as_str()));
+        self.insert(&path, img, "Image")
+    }
+
+    fn image_list(&self) -> Result<Vec<Image>> {
+        self.list(&self.path.join("images"), |img: &Image| img.node)
+    }
+
+    fn image_get(&self, name: &str) -> Result<Image> {
+        let path = try!(self.file("images", name));
+        self.get(&path, "Image")
+    }
+
+    fn image_update(&self, img: &Image) -> Result<()> {
+        let path = try!(self.file("images", img.name.as_str()));
+        self.replace(&path, img, "Image")
+    }
+
+    fn image_delete(&self, name: &str) -> Result<()> {
+        let path = try!(self.file("images", name));
+        self.remove(&path)
+    }
+
+    /*
+     * VMs
+     */
+    fn vm_create(&self, vm: &VM) -> Result<()> {
+        let path = try!(self.file("vms", vm.name.as_str()));
+        self.insert(&path, vm, "VM")
+    }
+
+    fn vm_list(&self) -> Result<Vec<VM>> {
+        self.list(&self.path.join("vms"), |vm: &VM| vm.node)
+    }
+
+    fn vm_get(&self, name: &str) -> Result<VM> {
+        let path = try!(self.file("vms", name));
+        self.get(&path, "VM")
+    }
+
+    fn vm_update(&self, vm: &VM) -> Result<()> {
+        let path = try!(self.file("vms", vm.name.as_str()));
+        self.replace(&path, vm, "VM")
+    }
+
+    fn vm_delete(&self, name: &str) -> Result<()> {
+        let path = try!(self.file("vms", name));
+        self.remove(&path)
+    }
+
+    /*
+     * Networks
+     */
+    fn network_create(&self, net: &Network) -> Result<()> {
+        let path = try!(self.file("networks", net.name.as_str()));
+        self.insert(&path, net, "Network")
+    }
+
+    fn network_list(&self) -> Result<Vec<Network>> {
+        self.list(&self.path.join("networks"), |net: &Network| net.node)
+    }
+
+    fn network_get(&self, name: &str) -> Result<Network> {
+        let path = try!(self.file("networks", name));
+        self.get(&path, "Network")
+    }
+
+    fn network_update(&self, net: &Network) -> Result<()> {
+        let path = try!(self.file("networks", net.name.as_str()));
+        self.replace(&path, net, "Network")
+    }
+
+    fn network_delete(&self, name: &str) -> Result<()> {
+        let path = try!(self.file("networks", name));
+        self.remove(&path)
+    }
+
+    /*
+     * Snapshots
+     */
+    fn snapshot_create(&self, snap: &Snapshot) -> Result<()> {
+        let dir = try!(self.snapshot_dir(snap.vm.as_str()));
+        try!(check_name(snap.name.as_str()));
+        try!(storage(&dir, fs::create_dir_all(&dir)));
+
+        self.insert(&dir.join(format!("{}.json", snap.name)), snap, "Snapshot")
+    }
+
+    fn snapshot_list(&self, vm: &str) -> Result<Vec<Snapshot>> {
+        let dir = try!(self.snapshot_dir(vm));
+        self.list(&dir, |snap: &Snapshot| snap.node)
+    }
+
+    fn snapshot_get(&self, vm: &str, name: &str) -> Result<Snapshot> {
+        let dir = try!(self.snapshot_dir(vm));
+        try!(check_name(name));
+
+        self.get(&dir.join(format!("{}.json", name)), "Snapshot")
+    }
+
+    fn snapshot_delete(&self, vm: &str, name: &str) -> Result<()> {
+        let dir = try!(self.snapshot_dir(vm));
+        try!(check_name(name));
+
+        self.remove(&dir.join(format!("{}.json", name)))
+    }
+
+    /*
+     * Jobs
+     */
+    fn job_create(&self, job: &Job) -> Result<()> {
+        let path = try!(self.file("jobs", job.id.as_str()));
+        self.insert(&path, job, "Job")
+    }
+
+    fn job_list(&self) -> Result<Vec<Job>> {
+        self.list(&self.path.join("jobs"), |job: &Job| job.node)
+    }
+
+    fn job_get(&self, id: &str) -> Result<Job> {
+        let path = try!(self.file("jobs", id));
+        self.get(&path, "Job")
+    }
+
+    fn job_update(&self, job: &Job) -> Result<()> {
+        let path = try!(self.file("jobs", job.id.as_str()));
+        self.replace(&path, job, "Job")
+    }
+
+    fn job_delete(&self, id: &str) -> Result<()> {
+        let path = try!(self.file("jobs", id));
+        self.remove(&path)
+    }
+
+    /*
+     * Audit log
+     */
+    fn audit_create(&self, entry: &Audit) -> Result<()> {
+        let line = format!("{}\n", try!(serde_json::to_string(entry)));
+        let path = self.path.join("audit.log");
+        let _guard = self.lock.lock().unwrap();
+
+        let mut f = try!(storage(&path, OpenOptions::new().create(true).append(true).open(&path)));
+        try!(storage(&path, f.write_all(line.as_bytes())));
+        try!(storage(&path, f.sync_data()));
+
+        Ok(())
+    }
+
+    fn audit_list(&self, filter: &AuditFilter) -> Result<Vec<Audit>> {
+        let path = self.path.join("audit.log");
+        let mut entries = VecDeque::new();
+
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        // Only keep the latest matching entries while reading
+        for line in BufReader::new(try!(storage(&path, File::open(&path)))).lines() {
+            // Skip the lines cut by a crash
+            if let Ok(entry) = serde_json::from_str::<Audit>(try!(storage(&path, line)).as_str()) {
+                if entry.node == self.node && filter.matches(&entry) {
+                    entries.push_back(entry);
+                }
+            }
+
+            if filter.limit > 0 && entries.len() > filter.limit as usize {
+                entries.pop_front();
+            }
+        }
+
+        Ok(entries.into_iter().collect())
+    }
+
+    fn audit_prune(&self, before: i64) -> Result<()> {
+        let path = self.path.join("audit.log");
+        let _guard = self.lock.lock().unwrap();
+
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let mut kept = String::new();
+        let mut pruned = false;
+        let mut invalid = 0;
+
+        for line in BufReader::new(try!(storage(&path, File::open(&path)))).lines() {
+            let line = try!(storage(&path, line));
+
+            // Only drop the entries known to be expired, the others are kept as they are
+            match serde_json::from_str::<Audit>(line.as_str()) {
+                Ok(ref entry) if entry.time < before => {
+                    pruned = true;
+                    continue;
+                },
+                Ok(_) => {},
+                Err(_) => invalid += 1
+            };
+
+            kept.push_str(line.as_str());
+            kept.push('\n');
+        }
+
+        if invalid > 0 {
+            warn!("database", "kept {} unreadable audit entries", invalid; path = path.display());
+        }
+
+        if !pruned {
+            return Ok(());
+        }
+
+        let tmp = path.with_extension(format!("{}.tmp", process::id()));
+
+        {
+            let mut f = try!(storage(&tmp, File::create(&tmp)));
+            try!(storage(&tmp, f.write_all(kept.as_bytes())));
+            try!(storage(&tmp, f.sync_all()));
+        }
+
+        if let Err(e) = fs::rename(&tmp, &path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(Error::storage(format!("{}: {}", path.display(), e)));
+        }
+
+        sync_dir(&path)
+    }
+}
@@ -0,0 +1,46 @@
+/*
+ * Job-related database transations
+ */
+
+use std::vec::Vec;
+
+use common::{Context, Result};
+use common::structs::Job;
+
+/*
+ * Create a new job in database
+ */
+pub fn create(ctx: &Context, job: &Job) -> Result<()> {
+    ctx.db.job_create(job)
+}
+
+/*
+ * List jobs in database, oldest first
+ */
+pub fn list(ctx: &Context) -> Result<Vec<Job>> {
+    let mut jobs = try!(ctx.db.job_list());
+    jobs.sort_by(|a, b| a.created.cmp(&b.created));
+
+    Ok(jobs)
+}
+
+/*
+ * Get a job from the database
+ */
+pub fn get(ctx: &Context, id: &str) -> Result<Job> {
+    ctx.db.job_get(id)
+}
+
+/*
+ * Update a job in database
+ */
+pub fn update(ctx: &Context, job: &Job) -> Result<()> {
+    ctx.db.job_update(job)
+}
+
+/*
+ * Delete a job from the database
+ */
+pub fn delete(ctx: &Context, id: &str) -> Result<()> {
+    ctx.db.job_delete(id)
+}
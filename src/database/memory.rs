@@ -0,0 +1,203 @@
+/*
+ * In-memory storage backend
+ * Nothing is persisted, mostly useful for testing
+ * Objects are only those of the running server, so they are not told apart by node
+ */
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::vec::Vec;
+
+use common::{Result, Error};
+use common::structs::{Image, VM, Network, Snapshot, Job, Audit, AuditFilter};
+
+use super::Store;
+
+pub struct Memory {
+    images: Mutex<HashMap<String, Image>>,
+    vms: Mutex<HashMap<String, VM>>,
+    networks: Mutex<HashMap<String, Network>>,
+    snapshots: Mutex<HashMap<(String, String), Snapshot>>,
+    jobs: Mutex<HashMap<String, Job>>,
+    audit: Mutex<Vec<Audit>>
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory {
+            images: Mutex::new(HashMap::new()),
+            vms: Mutex::new(HashMap::new()),
+            networks: Mutex::new(HashMap::new()),
+            snapshots: Mutex::new(HashMap::new()),
+            jobs: Mutex::new(HashMap::new()),
+            audit: Mutex::new(Vec::new())
+        }
+    }
+}
+
+/*
+ * Insert an object, failing if its key is already used
+ */
+fn insert<K: ::std::hash::Hash + Eq, T: Clone>(map: &Mutex<HashMap<K, T>>, key: K, obj: &T, what: &str) -> Result<()> {
+    let mut map = map.lock().unwrap();
+
+    if map.contains_key(&key) {
+        return Err(Error::already_exists(format!("{} already exists", what)));
+    }
+
+    map.insert(key, obj.clone());
+    Ok(())
+}
+
+/*
+ * Replace an existing object
+ */
+fn replace<K: ::std::hash::Hash + Eq, T: Clone>(map: &Mutex<HashMap<K, T>>, key: K, obj: &T, what: &str) -> Result<()> {
+    let mut map = map.lock().unwrap();
+
+    if !map.contains_key(&key) {
+        return Err(Error::not_found(format!("{} not found", what)));
+    }
+
+    map.insert(key, obj.clone());
+    Ok(())
+}
+
+impl Store for Memory {
+    /*
+     * Images
+     */
+    fn image_create(&self, img: &Image) -> Result<()> {
+        insert(&self.images, img.name.clone(), img, "Image")
+    }
+
+    fn image_list(&self) -> Result<Vec<Image>> {
+        Ok(self.images.lock().unwrap().values().cloned().collect())
+    }
+
+    fn image_get(&self, name: &str) -> Result<Image> {
+        self.images.lock().unwrap().get(name).cloned().ok_or(Error::not_found("Image not found"))
+    }
+
+    fn image_update(&self, img: &Image) -> Result<()> {
+        replace(&self.images, img.name.clone(), img, "Image")
+    }
+
+    fn image_delete(&self, name: &str) -> Result<()> {
+        self.images.lock().unwrap().remove(name);
+        Ok(())
+    }
+
+    /*
+     * VMs
+     */
+    fn vm_create(&self, vm: &VM) -> Result<()> {
+        insert(&self.vms, vm.name.clone(), vm, "VM")
+    }
+
+    fn vm_list(&self) -> Result<Vec<VM>> {
+        Ok(self.vms.lock().unwrap().values().cloned().collect())
+    }
+
+    fn vm_get(&self, name: &str) -> Result<VM> {
+        self.vms.lock().unwrap().get(name).cloned().ok_or(Error::not_found("VM not found"))
+    }
+
+    fn vm_update(&self, vm: &VM) -> Result<()> {
+        replace(&self.vms, vm.name.clone(), vm, "VM")
+    }
+
+    fn vm_delete(&self, name: &str) -> Result<()> {
+        self.vms.lock().unwrap().remove(name);
+        Ok(())
+    }
+
+    /*
+     * Networks
+     */
+    fn network_create(&self, net: &Network) -> Result<()> {
+        insert(&self.networks, net.name.clone(), net, "Network")
+    }
+
+    fn network_list(&self) -> Result<Vec<Network>> {
+        Ok(self.networks.lock().unwrap().values().cloned().collect())
+    }
+
+    fn network_get(&self, name: &str) -> Result<Network> {
+        self.networks.lock().unwrap().get(name).cloned().ok_or(Error::not_found("Network not found"))
+    }
+
+    fn network_update(&self, net: &Network) -> Result<()> {
+        replace(&self.networks, net.name.clone(), net, "Network")
+    }
+
+    fn network_delete(&self, name: &str) -> Result<()> {
+        self.networks.lock().unwrap().remove(name);
+        Ok(())
+    }
+
+    /*
+     * Snapshots
+     */
+    fn snapshot_create(&self, snap: &Snapshot) -> Result<()> {
+        insert(&self.snapshots, (snap.vm.clone(), snap.name.clone()), snap, "Snapshot")
+    }
+
+    fn snapshot_list(&self, vm: &str) -> Result<Vec<Snapshot>> {
+        let snaps = self.snapshots.lock().unwrap();
+        Ok(snaps.values().filter(|s| s.vm.as_str() == vm).cloned().collect())
+    }
+
+    fn snapshot_get(&self, vm: &str, name: &str) -> Result<Snapshot> {
+        let key = (vm.to_string(), name.to_string());
+        self.snapshots.lock().unwrap().get(&key).cloned().ok_or(Error::not_found("Snapshot not found"))
+    }
+
+    fn snapshot_delete(&self, vm: &str, name: &str) -> Result<()> {
+        let key = (vm.to_string(), name.to_string());
+        self.snapshots.lock().unwrap().remove(&key);
+        Ok(())
+    }
+
+    /*
+     * Jobs
+     */
+    fn job_create(&self, job: &Job) -> Result<()> {
+        insert(&self.jobs, job.id.clone(), job, "Job")
+    }
+
+    fn job_list(&self) -> Result<Vec<Job>> {
+        Ok(self.jobs.lock().unwrap().values().cloned().collect())
+    }
+
+    fn job_get(&self, id: &str) -> Result<Job> {
+        self.jobs.lock().unwrap().get(id).cloned().ok_or(Error::not_found("Job not found"))
+    }
+
+    fn job_update(&self, job: &Job) -> Result<()> {
+        replace(&self.jobs, job.id.clone(), job, "Job")
+    }
+
+    fn job_delete(&self, id: &str) -> Result<()> {
+        self.jobs.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    /*
+     * Audit log
+     */
+    fn audit_create(&self, entry: &Audit) -> Result<()> {
+        self.audit.lock().unwrap().push(entry.clone());
+        Ok(())
+    }
+
+    fn audit_list(&self, filter: &AuditFilter) -> Result<Vec<Audit>> {
+        let entries = self.audit.lock().unwrap().clone();
+        Ok(filter.apply(entries))
+    }
+
+    fn audit_prune(&self, before: i64) -> Result<()> {
+        self.audit.lock().unwrap().retain(|e| e.time >= before);
+        Ok(())
+    }
+}
@@ -6,22 +6,83 @@ pub mod image;
 pub mod vm;
 pub mod network;
 pub mod snapshot;
+pub mod job;
+pub mod audit;
+pub mod mongo;
+pub mod file;
+pub mod memory;
 
-use mongodb::Client;
-use mongodb::db::{Database, ThreadedDatabase};
-use mongodb::ThreadedClient;
+use std::vec::Vec;
 
-use common::Result;
+use common::{Result, Error};
+use common::structs::{Image, VM, Network, Snapshot, Job, Audit, AuditFilter};
+use config::Config;
 
 /*
- * Open a connection to the database
+ * Storage backend interface
+ * Implementations only deal with the objects belonging to the local node, and behave the same way:
+ * creating an object whose key is used fails with 'already_exists', updating an object that does
+ * not exist fails with 'not_found', and deleting one that does not exist succeeds
  */
-pub fn open(host: &str, port: u16) -> Result<Database> {
-    let c = try!(Client::connect(host, port));
-    let db = c.db("olvm");
-    let ver = try!(db.version());
+pub trait Store: Send + Sync {
+    fn image_create(&self, img: &Image) -> Result<()>;
+    fn image_list(&self) -> Result<Vec<Image>>;
+    fn image_get(&self, name: &str) -> Result<Image>;
+    fn image_update(&self, img: &Image) -> Result<()>;
+    fn image_delete(&self, name: &str) -> Result<()>;
 
-    println!("Connected to MongoDB version {}", ver);
+    fn vm_create(&self, vm: &VM) -> Result<()>;
+    fn vm_list(&self) -> Result<Vec<VM>>;
+    fn vm_get(&self, name: &str) -> Result<VM>;
+    fn vm_update(&self, vm: &VM) -> Result<()>;
+    fn vm_delete(&self, name: &str) -> Result<()>;
 
-    Ok(db)
+    fn network_create(&self, net: &Network) -> Result<()>;
+    fn network_list(&self) -> Result<Vec<Network>>;
+    fn network_get(&self, name: &str) -> Result<Network>;
+    fn network_update(&self, net: &Network) -> Result<()>;
+    fn network_delete(&self, name: &str) -> Result<()>;
+
+    fn snapshot_create(&self, snap: &Snapshot) -> Result<()>;
+    fn snapshot_list(&self, vm: &str) -> Result<Vec<Snapshot>>;
+    fn snapshot_get(&self, vm: &str, name: &str) -> Result<Snapshot>;
+    fn snapshot_delete(&self, vm: &str, name: &str) -> Result<()>;
+
+    fn job_create(&self, job: &Job) -> Result<()>;
+    fn job_list(&self) -> Result<Vec<Job>>;
+    fn job_get(&self, id: &str) -> Result<Job>;
+    fn job_update(&self, job: &Job) -> Result<()>;
+    fn job_delete(&self, id: &str) -> Result<()>;
+
+    // The audit log is append-only, entries are returned oldest first
+    // and only removed once older than the retention period
+    fn audit_create(&self, entry: &Audit) -> Result<()>;
+    fn audit_list(&self, filter: &AuditFilter) -> Result<Vec<Audit>>;
+    fn audit_prune(&self, before: i64) -> Result<()>;
 }
+
+/*
+ * Open the storage backend
+ */
+pub fn open(conf: &Config) -> Result<Box<Store>> {
+    let node = conf.global.node;
+
+    match conf.database.kind.as_str() {
+        "mongodb" => {
+            let db = try!(mongo::Mongo::open(conf.database.host.as_str(), conf.database.port, node));
+            Ok(Box::new(db))
+        },
+        "file" => {
+            let db = try!(file::FileStore::open(conf.database.path.as_str(), node));
+            Ok(Box::new(db))
+        },
+        "memory" => Ok(Box::new(memory::Memory::new())),
+        _ => Err(Error::new(format!("Unknown database type '{}'", conf.database.kind)))
+    }
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,452 @@
+/*
+ * MongoDB storage backend
+ */
+
+use std::vec::Vec;
+
+use bson::{self, Bson, Document, Array};
+use mongodb::Client;
+use mongodb::coll::options::{FindOptions, IndexOptions};
+use mongodb::coll::results::{InsertOneResult, UpdateResult};
+use mongodb::db::{Database, ThreadedDatabase};
+use mongodb::ThreadedClient;
+
+use common::{Result, Error};
+use common::structs::{Image, VM, Network, Snapshot, Job, Audit, AuditFilter};
+
+use super::Store;
+
+const DUPLICATE_KEY: i32 = 11000;
+
+/*
+ * Unique keys of the collections, objects are only identified by their name on their node
+ */
+const INDEXES: [(&'static str, &'static [&'static str]); 5] = [
+    ("images", &["node", "name"]),
+    ("vms", &["node", "name"]),
+    ("networks", &["node", "name"]),
+    ("snapshots", &["node", "vm", "name"]),
+    ("jobs", &["node", "id"])
+];
+
+pub struct Mongo {
+    db: Database,
+    node: i32
+}
+
+impl Mongo {
+    /*
+     * Open a connection to the database
+     */
+    pub fn open(host: &str, port: u16, node: i32) -> Result<Mongo> {
+        let c = try!(Client::connect(host, port));
+        let db = c.db("olvm");
+        let ver = try!(db.version());
+
+        info!("database", "connected to MongoDB"; host = host, port = port, version = ver);
+
+        // Concurrent inserts of the same object are rejected by the database itself
+        for &(collection, fields) in INDEXES.iter() {
+            try!(create_index(&db, collection, fields));
+        }
+
+        Ok(Mongo {
+            db: db,
+            node: node
+        })
+    }
+}
+
+/*
+ * Create the unique index of a collection
+ * It can not be built while the collection holds duplicates, left over by a version without indexes
+ */
+fn create_index(db: &Database, collection: &str, fields: &[&str]) -> Result<()> {
+    let mut keys = Document::new();
+    for field in fields {
+        keys.insert(field.to_string(), 1);
+    }
+
+    let mut opts = IndexOptions::new();
+    opts.unique = Some(true);
+
+    let e = match db.collection(collection).create_index(keys, Some(opts)) {
+        Ok(_) => return Ok(()),
+        Err(e) => e.to_string()
+    };
+
+    if e.contains(format!("E{}", DUPLICATE_KEY).as_str()) || e.contains(format!("code: {}", DUPLICATE_KEY).as_str()) {
+        return Err(Error::storage(format!("Collection '{}' holds several objects with the same ({}), remove the duplicates before starting the server: {}",
+            collection, fields.join(", "), e)));
+    }
+
+    Err(Error::storage(format!("Failed to create the unique index of collection '{}': {}", collection, e)))
+}
+
+/*
+ * Check the result of an insert, a duplicate key means that the object already exists
+ */
+fn inserted(res: InsertOneResult, what: &str) -> Result<()> {
+    match res.write_exception {
+        Some(e) => match e.write_error {
+            Some(ref err) if err.code == DUPLICATE_KEY => Err(Error::already_exists(format!("{} already exists", what))),
+            _ => Err(Error::storage(e.message))
+        },
+        None => Ok(())
+    }
+}
+
+/*
+ * Check the result of an update, which only succeeds if the object still exists
+ */
+fn updated(res: UpdateResult, what: &str) -> Result<()> {
+    if let Some(e) = res.write_exception {
+        return Err(Error::storage(e.message));
+    }
+
+    if res.matched_count == 0 {
+        return Err(Error::not_found(format!("{} not found", what)));
+    }
+
+    Ok(())
+}
+
+impl Store for Mongo {
+    /*
+     * Images
+     */
+    fn image_create(&self, img: &Image) -> Result<()> {
+        let doc = try!(img.to_bson());
+        let res = try!(self.db.collection("images").insert_one(doc, None));
+
+        inserted(res, "Image")
+    }
+
+    fn image_list(&self) -> Result<Vec<Image>> {
+        let mut imgs = Vec::new();
+        let cursor = try!(self.db.collection("images").find(Some(doc!{"node" => self.node}), None));
+
+        for result in cursor {
+            if let Ok(doc) = result {
+                imgs.push(try!(Image::from_bson(doc)));
+            }
+        }
+
+        Ok(imgs)
+    }
+
+    fn image_get(&self, name: &str) -> Result<Image> {
+        let doc = try!(self.db.collection("images").find_one(Some(doc!{"name" => name, "node" => self.node}), None));
+
+        if let Some(img) = doc {
+            return Ok(try!(Image::from_bson(img)));
+        }
+
+        Err(Error::not_found("Image not found"))
+    }
+
+    fn image_update(&self, img: &Image) -> Result<()> {
+        let name = img.name.as_str();
+        let file = img.file.as_str();
+
+        let mut p = Document::new();
+        for (k, v) in &img.parameters {
+            p.insert(k.clone(), v.clone());
+        }
+
+        let update = doc! {
+            "file" => file,
+            "parameters" => p
+        };
+
+        let res = try!(self.db.collection("images").update_one(doc!{"name" => name, "node" => self.node}, doc! {
+            "$set" => update
+        }, None));
+
+        updated(res, "Image")
+    }
+
+    fn image_delete(&self, name: &str) -> Result<()> {
+        try!(self.db.collection("images").delete_one(doc!{"name" => name, "node" => self.node}, None));
+        Ok(())
+    }
+
+    /*
+     * VMs
+     */
+    fn vm_create(&self, vm: &VM) -> Result<()> {
+        let doc = try!(vm.to_bson());
+        let res = try!(self.db.collection("vms").insert_one(doc, None));
+
+        inserted(res, "VM")
+    }
+
+    fn vm_list(&self) -> Result<Vec<VM>> {
+        let mut vms = Vec::new();
+        let cursor = try!(self.db.collection("vms").find(Some(doc!{"node" => self.node}), None));
+
+        for result in cursor {
+            if let Ok(doc) = result {
+                vms.push(try!(VM::from_bson(doc)));
+            }
+        }
+
+        Ok(vms)
+    }
+
+    fn vm_get(&self, name: &str) -> Result<VM> {
+        let doc = try!(self.db.collection("vms").find_one(Some(doc!{"name" => name, "node" => self.node}), None));
+
+        if let Some(vm) = doc {
+            return Ok(try!(VM::from_bson(vm)));
+        }
+
+        Err(Error::not_found("VM not found"))
+    }
+
+    fn vm_update(&self, vm: &VM) -> Result<()> {
+        let name = vm.name.as_str();
+
+        let mut i = Array::new();
+        for iface in &vm.interfaces {
+            i.push(bson::to_bson(&iface).unwrap());
+        }
+
+        let mut p = Document::new();
+        for (k, v) in &vm.parameters {
+            p.insert(k.clone(), v.clone());
+        }
+
+        let update = doc! {
+            "interfaces" => i,
+            "state" => vm.state.as_str(),
+            "restart_policy" => bson::to_bson(&vm.restart_policy).unwrap(),
+            "autostart" => vm.autostart,
+            "parameters" => p
+        };
+
+        let res = try!(self.db.collection("vms").update_one(doc!{"name" => name, "node" => self.node}, doc! {
+            "$set" => update
+        }, None));
+
+        updated(res, "VM")
+    }
+
+    fn vm_delete(&self, name: &str) -> Result<()> {
+        try!(self.db.collection("vms").delete_one(doc!{"name" => name, "node" => self.node}, None));
+        Ok(())
+    }
+
+    /*
+     * Networks
+     */
+    fn network_create(&self, net: &Network) -> Result<()> {
+        let doc = try!(net.to_bson());
+        let res = try!(self.db.collection("networks").insert_one(doc, None));
+
+        inserted(res, "Network")
+    }
+
+    fn network_list(&self) -> Result<Vec<Network>> {
+        let mut nets = Vec::new();
+        let cursor = try!(self.db.collection("networks").find(Some(doc!{"node" => self.node}), None));
+
+        for result in cursor {
+            if let Ok(doc) = result {
+                nets.push(try!(Network::from_bson(doc)));
+            }
+        }
+
+        Ok(nets)
+    }
+
+    fn network_get(&self, name: &str) -> Result<Network> {
+        let doc = try!(self.db.collection("networks").find_one(Some(doc!{"name" => name, "node" => self.node}), None));
+
+        if let Some(net) = doc {
+            return Ok(try!(Network::from_bson(net)));
+        }
+
+        Err(Error::not_found("Network not found"))
+    }
+
+    fn network_update(&self, net: &Network) -> Result<()> {
+        let name = net.name.as_str();
+        let cidr = net.cidr.as_str();
+        let router = net.router.as_str();
+
+        let mut dnsv = Vec::new();
+        for d in &net.dns {
+            dnsv.push(Bson::String(d.clone()));
+        }
+
+        let update = doc! {
+            "cidr" => cidr,
+            "router" => router,
+            "dns" => dnsv
+        };
+
+        let res = try!(self.db.collection("networks").update_one(doc!{"name" => name, "node" => self.node}, doc! {
+            "$set" => update
+        }, None));
+
+        updated(res, "Network")
+    }
+
+    fn network_delete(&self, name: &str) -> Result<()> {
+        try!(self.db.collection("networks").delete_one(doc!{"name" => name, "node" => self.node}, None));
+        Ok(())
+    }
+
+    /*
+     * Snapshots
+     */
+    fn snapshot_create(&self, snap: &Snapshot) -> Result<()> {
+        let doc = try!(snap.to_bson());
+        let res = try!(self.db.collection("snapshots").insert_one(doc, None));
+
+        inserted(res, "Snapshot")
+    }
+
+    fn snapshot_list(&self, vm: &str) -> Result<Vec<Snapshot>> {
+        let mut snaps = Vec::new();
+        let cursor = try!(self.db.collection("snapshots").find(Some(doc!{"node" => self.node, "vm" => vm}), None));
+
+        for result in cursor {
+            if let Ok(doc) = result {
+                snaps.push(try!(Snapshot::from_bson(doc)));
+            }
+        }
+
+        Ok(snaps)
+    }
+
+    fn snapshot_get(&self, vm: &str, name: &str) -> Result<Snapshot> {
+        let doc = try!(self.db.collection("snapshots").find_one(Some(doc!{"name" => name, "vm" => vm, "node" => self.node}), None));
+
+        if let Some(snap) = doc {
+            return Ok(try!(Snapshot::from_bson(snap)));
+        }
+
+        Err(Error::not_found("Snapshot not found"))
+    }
+
+    fn snapshot_delete(&self, vm: &str, name: &str) -> Result<()> {
+        try!(self.db.collection("snapshots").delete_one(doc!{"name" => name, "vm" => vm, "node" => self.node}, None));
+        Ok(())
+    }
+
+    /*
+     * Jobs
+     */
+    fn job_create(&self, job: &Job) -> Result<()> {
+        let doc = try!(job.to_bson());
+        let res = try!(self.db.collection("jobs").insert_one(doc, None));
+
+        inserted(res, "Job")
+    }
+
+    fn job_list(&self) -> Result<Vec<Job>> {
+        let mut jobs = Vec::new();
+        let cursor = try!(self.db.collection("jobs").find(Some(doc!{"node" => self.node}), None));
+
+        for result in cursor {
+            if let Ok(doc) = result {
+                jobs.push(try!(Job::from_bson(doc)));
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    fn job_get(&self, id: &str) -> Result<Job> {
+        let doc = try!(self.db.collection("jobs").find_one(Some(doc!{"id" => id, "node" => self.node}), None));
+
+        if let Some(job) = doc {
+            return Ok(try!(Job::from_bson(job)));
+        }
+
+        Err(Error::not_found("Job not found"))
+    }
+
+    fn job_update(&self, job: &Job) -> Result<()> {
+        let update = doc! {
+            "state" => job.state.as_str(),
+            "progress" => job.progress as i64,
+            "result" => job.result.as_str(),
+            "error" => job.error.as_str(),
+            "code" => job.code.as_str(),
+            "started" => job.started,
+            "finished" => job.finished
+        };
+
+        let res = try!(self.db.collection("jobs").update_one(doc!{"id" => job.id.as_str(), "node" => self.node}, doc! {
+            "$set" => update
+        }, None));
+
+        updated(res, "Job")
+    }
+
+    fn job_delete(&self, id: &str) -> Result<()> {
+        try!(self.db.collection("jobs").delete_one(doc!{"id" => id, "node" => self.node}, None));
+        Ok(())
+    }
+
+    /*
+     * Audit log
+     */
+    fn audit_create(&self, entry: &Audit) -> Result<()> {
+        let doc = try!(entry.to_bson());
+        let res = try!(self.db.collection("audit").insert_one(doc, None));
+
+        inserted(res, "Audit entry")
+    }
+
+    fn audit_list(&self, filter: &AuditFilter) -> Result<Vec<Audit>> {
+        let mut query = doc!{"node" => self.node};
+
+        let mut time = Document::new();
+        if filter.since > 0 {
+            time.insert("$gte", filter.since);
+        }
+        if filter.until > 0 {
+            time.insert("$lte", filter.until);
+        }
+        if !time.is_empty() {
+            query.insert("time", time);
+        }
+
+        for &(key, value) in [("kind", &filter.kind), ("object", &filter.object), ("command", &filter.command)].iter() {
+            if value.len() > 0 {
+                query.insert(key, value.as_str());
+            }
+        }
+
+        // Fetch the latest entries, and put them back in chronological order
+        let mut opts = FindOptions::new();
+        opts.sort = Some(doc!{"time" => (-1), "_id" => (-1)});
+        if filter.limit > 0 {
+            opts.limit = Some(filter.limit as i64);
+        }
+
+        let mut entries = Vec::new();
+        let cursor = try!(self.db.collection("audit").find(Some(query), Some(opts)));
+
+        for result in cursor {
+            if let Ok(doc) = result {
+                entries.push(try!(Audit::from_bson(doc)));
+            }
+        }
+
+        entries.reverse();
+        Ok(entries)
+    }
+
+    fn audit_prune(&self, before: i64) -> Result<()> {
+        let mut query = doc!{"node" => self.node};
+        query.insert("time", doc!{"$lt" => before});
+
+        try!(self.db.collection("audit").delete_many(query, None));
+        Ok(())
+    }
+}
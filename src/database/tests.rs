@@ -0,0 +1,80 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::process;
+
+use serde_json;
+
+use common::ErrorKind;
+use common::structs::{VM, State, Audit, AuditFilter};
+
+use super::Store;
+use super::file::FileStore;
+
+fn vm(node: i32) -> VM {
+    let mut vm = VM::from_json(r#"{"name": "web", "backend": "kvm", "state": "stopped"}"#).unwrap();
+    vm.node = node;
+    vm
+}
+
+#[test]
+fn file_store_semantics() {
+    let dir = env::temp_dir().join(format!("olvm-test-{}-file-store", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let local = FileStore::open(dir.to_str().unwrap(), 1).unwrap();
+    let other = FileStore::open(dir.to_str().unwrap(), 2).unwrap();
+
+    local.vm_create(&vm(1)).unwrap();
+    assert_eq!(local.vm_create(&vm(1)).unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+    let mut running = vm(1);
+    running.state = State::Running;
+    local.vm_update(&running).unwrap();
+    assert_eq!(local.vm_get("web").unwrap().state, State::Running);
+
+    // Objects of other nodes are not seen, like with the MongoDB store
+    assert_eq!(other.vm_get("web").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(other.vm_update(&vm(2)).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(other.vm_list().unwrap().len(), 0);
+
+    other.vm_delete("web").unwrap();
+    assert!(local.vm_get("web").is_ok());
+
+    local.vm_delete("web").unwrap();
+    local.vm_delete("web").unwrap();
+    assert_eq!(local.vm_get("web").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(local.vm_update(&vm(1)).unwrap_err().kind(), ErrorKind::NotFound);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn file_store_damaged_files() {
+    let dir = env::temp_dir().join(format!("olvm-test-{}-file-store-damaged", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let store = FileStore::open(dir.to_str().unwrap(), 1).unwrap();
+    store.vm_create(&vm(1)).unwrap();
+
+    // A damaged object does not hide the others
+    File::create(dir.join("vms").join("broken.json")).unwrap().write_all(b"{\"name\": ").unwrap();
+    assert_eq!(store.vm_list().unwrap().len(), 1);
+    assert_eq!(store.vm_get("broken").unwrap_err().kind(), ErrorKind::StorageFailure);
+
+    // Pruning the audit log only drops the expired entries
+    for time in &[100, 200] {
+        let entry: Audit = serde_json::from_str(format!(r#"{{"node": 1, "time": {}, "command": "listvm"}}"#, time).as_str()).unwrap();
+        store.audit_create(&entry).unwrap();
+    }
+    OpenOptions::new().append(true).open(dir.join("audit.log")).unwrap().write_all(b"{\"node\": 1, \"ti\n").unwrap();
+
+    store.audit_prune(150).unwrap();
+    assert_eq!(store.audit_list(&AuditFilter::default()).unwrap().len(), 1);
+
+    let mut log = String::new();
+    File::open(dir.join("audit.log")).unwrap().read_to_string(&mut log).unwrap();
+    assert_eq!(log.lines().count(), 2);
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -5,9 +5,6 @@
 use std::vec::Vec;
 use std::collections::HashMap;
 
-use mongodb::db::ThreadedDatabase;
-use bson::{self, Document, Array};
-
 use common::{Context, Result, Error};
 use common::structs::VM;
 
@@ -15,73 +12,48 @@ use common::structs::VM;
  * Create a new VM in database
  */
 pub fn create(ctx: &Context, vm: &VM) -> Result<()> {
-    let doc = try!(vm.to_bson());
-    try!(ctx.db.collection("vms").insert_one(doc, None));
-
-    Ok(())
+    ctx.db.vm_create(vm)
 }
 
 /*
  * List VMs in database
  */
 pub fn list(ctx: &Context) -> Result<Vec<VM>> {
-    let mut vms = Vec::new();
-    let node = ctx.conf.global.node;
-    let cursor = try!(ctx.db.collection("vms").find(Some(doc!{"node" => node}), None));
-
-    for result in cursor {
-        if let Ok(doc) = result {
-            vms.push(try!(VM::from_bson(doc)));
-        }
-    }
-
-    Ok(vms)
+    ctx.db.vm_list()
 }
 
 /*
  * Get an VM from the database
  */
 pub fn get(ctx: &Context, name: &str) -> Result<VM> {
-    let node = ctx.conf.global.node;
-    let doc = try!(ctx.db.collection("vms").find_one(Some(doc!{"name" => name, "node" => node}), None));
-
-    if let Some(vm) = doc {
-        return Ok(try!(VM::from_bson(vm)));
-    }
-
-    Err(Error::new("VM not found"))
+    ctx.db.vm_get(name)
 }
 
 /*
  * Get a VM by its MAC address
  */
 pub fn get_mac(ctx: &Context, mac: &str) -> Result<(VM, usize)> {
-    let node = ctx.conf.global.node;
-    let cursor = try!(ctx.db.collection("vms").find(Some(doc!{"node" => node}), None));
-
-    for result in cursor {
-        if let Ok(doc) = result {
-            let vm = try!(VM::from_bson(doc));
-
-            let mut found = false;
-            let mut index = 0;
+    let vms = try!(ctx.db.vm_list());
 
-            for iface in &vm.interfaces {
-                if iface.mac.as_str() == mac {
-                    found = true;
-                    break;
-                }
+    for vm in vms {
+        let mut found = false;
+        let mut index = 0;
 
-                index = index + 1;
+        for iface in &vm.interfaces {
+            if iface.mac.as_str() == mac {
+                found = true;
+                break;
             }
 
-            if found {
-                return Ok((vm, index));
-            }
+            index = index + 1;
+        }
+
+        if found {
+            return Ok((vm, index));
         }
     }
 
-    Err(Error::new("VM not found"))
+    Err(Error::not_found("VM not found"))
 }
 
 /*
@@ -103,36 +75,12 @@ pub fn params(ctx: &Context, vm: &mut VM, params: HashMap<String, String>) -> Re
  * Update an VM in the database
  */
 pub fn update(ctx: &Context, vm: &VM) -> Result<()> {
-    let node = ctx.conf.global.node;
-    let name = vm.name.as_str();
-
-    let mut i = Array::new();
-    for iface in &vm.interfaces {
-        i.push(bson::to_bson(&iface).unwrap());
-    }
-
-    let mut p = Document::new();
-    for (k, v) in &vm.parameters {
-        p.insert(k.clone(), v.clone());
-    }
-
-    let update = doc! {
-        "interfaces" => i,
-        "parameters" => p
-    };
-
-    try!(ctx.db.collection("vms").update_one(doc!{"name" => name, "node" => node}, doc! {
-        "$set" => update
-    }, None));
-
-    Ok(())
+    ctx.db.vm_update(vm)
 }
 
 /*
  * Delete an VM from the database
  */
 pub fn delete(ctx: &Context, name: &str) -> Result<()> {
-    let node = ctx.conf.global.node;
-    try!(ctx.db.collection("vms").delete_one(doc!{"name" => name, "node" => node}, None));
-    Ok(())
+    ctx.db.vm_delete(name)
 }
@@ -0,0 +1,169 @@
+/*
+ * Events - Notable things happening to the managed objects
+ * Events are logged, and sent to the clients subscribed to them (see the HTTP and Unix interfaces)
+ */
+
+use std::sync::Mutex;
+use std::sync::mpsc::{self, SyncSender, Receiver, TrySendError, RecvTimeoutError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::value::Value;
+
+use common::{Context, Result};
+
+/*
+ * Maximum number of events waiting to be sent to a subscriber
+ * Subscribers falling that far behind are disconnected, rather than silently missing events
+ */
+const BACKLOG: usize = 256;
+
+/*
+ * Delay without events after which subscribers are sent a keepalive, in seconds
+ * This is how the streams of disconnected clients get noticed
+ */
+pub const KEEPALIVE: u64 = 15;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Event {
+    pub kind: String, // Type of the event, for example "vm.crashed"
+    pub object: String, // Name of the object concerned by the event
+    pub time: u64, // Unix timestamp
+    pub data: Value // Details, depending on the type
+}
+
+impl Event {
+    pub fn new(kind: &str, object: &str, data: Value) -> Event {
+        let time = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs(),
+            Err(_) => 0
+        };
+
+        Event {
+            kind: kind.to_string(),
+            object: object.to_string(),
+            time: time,
+            data: data
+        }
+    }
+}
+
+struct Subscriber {
+    kinds: Vec<String>,
+    sender: SyncSender<Event>
+}
+
+/*
+ * Dispatches the published events to the subscribers
+ */
+pub struct Bus {
+    subscribers: Mutex<Vec<Subscriber>>
+}
+
+/*
+ * Check if an event type matches one of the patterns, such as "vm.started" or "vm.*"
+ * An empty list matches every event
+ */
+fn matches(kinds: &[String], kind: &str) -> bool {
+    if kinds.len() == 0 {
+        return true;
+    }
+
+    kinds.iter().any(|k| {
+        if k.ends_with('*') {
+            kind.starts_with(&k[..k.len() - 1])
+        }
+        else {
+            k.as_str() == kind
+        }
+    })
+}
+
+/*
+ * Parse a comma-separated list of event types, as sent by the clients
+ */
+pub fn parse_kinds(s: &str) -> Vec<String> {
+    s.split(',').map(|k| k.trim()).filter(|k| k.len() > 0).map(|k| k.to_string()).collect()
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus {
+            subscribers: Mutex::new(Vec::new())
+        }
+    }
+
+    /*
+     * Subscribe to the events matching the specified types, the subscription ends when the receiver is dropped
+     */
+    pub fn subscribe(&self, kinds: Vec<String>) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(BACKLOG);
+
+        self.subscribers.lock().unwrap().push(Subscriber {
+            kinds: kinds,
+            sender: sender
+        });
+
+        receiver
+    }
+
+    /*
+     * Send an event to the interested subscribers, and forget the ones that are gone
+     */
+    fn publish(&self, event: &Event) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+
+        subscribers.retain(|s| {
+            if !matches(&s.kinds, event.kind.as_str()) {
+                return true;
+            }
+
+            match s.sender.try_send(event.clone()) {
+                Ok(_) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("events", "disconnecting a subscriber unable to keep up");
+                    false
+                },
+                Err(TrySendError::Disconnected(_)) => false
+            }
+        });
+    }
+
+    #[cfg(test)]
+    fn subscribers(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+/*
+ * Publish an event
+ */
+pub fn publish(ctx: &Context, event: Event) {
+    info!("events", "{}", event.kind; object = event.object, data = event.data);
+    ctx.events.publish(&event);
+}
+
+/*
+ * Send the events matching the specified types to a client, until it goes away
+ * The callback is given None when a keepalive must be sent, and fails once the client is disconnected
+ */
+pub fn stream<F: FnMut(Option<&Event>) -> Result<()>>(ctx: &Context, kinds: Vec<String>, mut send: F) {
+    let receiver = ctx.events.subscribe(kinds);
+
+    loop {
+        let res = match receiver.recv_timeout(Duration::from_secs(KEEPALIVE)) {
+            Ok(event) => send(Some(&event)),
+            Err(RecvTimeoutError::Timeout) => send(None),
+            Err(RecvTimeoutError::Disconnected) => return // Dropped by the bus
+        };
+
+        if let Err(_) = res {
+            return;
+        }
+    }
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
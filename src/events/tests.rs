@@ -0,0 +1,65 @@
+use serde_json::value::Value;
+
+use super::{Bus, Event, BACKLOG, matches, parse_kinds};
+
+fn event(kind: &str) -> Event {
+    Event::new(kind, "test", Value::Null)
+}
+
+#[test]
+fn kinds() {
+    assert_eq!(parse_kinds(" vm.*, job.done,,"), vec!["vm.*", "job.done"]);
+    assert_eq!(parse_kinds("").len(), 0);
+
+    let kinds = parse_kinds("vm.*,image.deleted");
+    assert!(matches(&kinds, "vm.started"));
+    assert!(matches(&kinds, "image.deleted"));
+    assert!(!matches(&kinds, "image.created"));
+    assert!(matches(&[], "network.created"));
+}
+
+#[test]
+fn subscribe() {
+    let bus = Bus::new();
+    let all = bus.subscribe(Vec::new());
+    let vms = bus.subscribe(parse_kinds("vm.*"));
+
+    bus.publish(&event("vm.started"));
+    bus.publish(&event("network.created"));
+
+    assert_eq!(all.try_recv().unwrap().kind.as_str(), "vm.started");
+    assert_eq!(all.try_recv().unwrap().kind.as_str(), "network.created");
+    assert!(all.try_recv().is_err());
+
+    assert_eq!(vms.try_recv().unwrap().kind.as_str(), "vm.started");
+    assert!(vms.try_recv().is_err());
+}
+
+#[test]
+fn unsubscribe() {
+    let bus = Bus::new();
+    let receiver = bus.subscribe(Vec::new());
+    assert_eq!(bus.subscribers(), 1);
+
+    // Subscribers are forgotten at the first event following the end of their subscription
+    drop(receiver);
+    bus.publish(&event("vm.started"));
+    assert_eq!(bus.subscribers(), 0);
+}
+
+#[test]
+fn slow_subscriber() {
+    let bus = Bus::new();
+    let receiver = bus.subscribe(Vec::new());
+
+    for _ in 0..BACKLOG {
+        bus.publish(&event("vm.started"));
+    }
+    assert_eq!(bus.subscribers(), 1);
+
+    // The subscriber gets the events sent so far, then notices it was disconnected
+    bus.publish(&event("vm.stopped"));
+    assert_eq!(bus.subscribers(), 0);
+
+    assert_eq!(receiver.iter().count(), BACKLOG);
+}
@@ -1,5 +1,6 @@
 use std::path::Path;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{Read, Write};
 
 use serde_json;
 
@@ -7,6 +8,8 @@ use common::{Context, Result, Error};
 use common::structs::Image;
 use database;
 use backend;
+use events::{self, Event};
+use jobs;
 
 /*
  * Validates the user-specified parameters for image creation/update
@@ -16,40 +19,88 @@ fn validate(ctx: &Context, obj: &str) -> Result<Image> {
     img.node = ctx.conf.global.node;
 
     if img.name.len() == 0 {
-        return Err(Error::new("A 'name' is required"));
+        return Err(Error::invalid_argument("A 'name' is required"));
     }
     if img.backend.len() == 0 {
-        return Err(Error::new("A 'backend' parameter is required"));
+        return Err(Error::invalid_argument("A 'backend' parameter is required"));
     }
     if img.file.len() == 0 {
-        return Err(Error::new("A 'file' is required"));
+        return Err(Error::invalid_argument("A 'file' is required"));
     }
 
     // Check if the file exists
     if !Path::new(img.file.as_str()).exists() {
-        return Err(Error::new(format!("{}: file not found", img.file)));
+        return Err(Error::invalid_argument(format!("{}: file not found", img.file)));
     }
 
     Ok(img)
 }
 
+/*
+ * Copy an image file, reporting the progress to the job
+ * The copy accounts for most of the job, the backend script does the rest
+ */
+fn copy(job: &jobs::Handle, src: &str, dst: &str) -> Result<()> {
+    let mut input = try!(File::open(src));
+    let mut output = try!(File::create(dst));
+
+    let total = try!(input.metadata()).len();
+    let mut copied = 0;
+    let mut buf = vec![0; 1 << 20];
+
+    loop {
+        try!(job.check());
+
+        let n = try!(input.read(&mut buf));
+        if n == 0 {
+            break;
+        }
+
+        try!(output.write_all(&buf[..n]));
+        copied += n as u64;
+
+        if total > 0 {
+            job.progress((copied * 90 / total) as u32);
+        }
+    }
+
+    try!(output.sync_all());
+    Ok(())
+}
+
 /*
  * Handle a 'createimg' command
  */
-pub fn create(ctx: &Context, obj: &str) -> Result<String> {
+pub fn create(ctx: &Context, job: &jobs::Handle, obj: &str) -> Result<String> {
     let mut img = try!(validate(ctx, &obj));
 
     if let Ok(_) = database::image::get(ctx, img.name.as_str()) {
-        return Err(Error::new("This image name is not available"));
+        return Err(Error::already_exists("This image name is not available"));
     }
 
     let path = try!(ctx.conf.get_image_path(img.backend.as_str(), img.name.as_str()));
-    try!(fs::copy(img.file.as_str(), path.as_str()));
 
-    img.file = path;
+    if let Err(e) = copy(job, img.file.as_str(), path.as_str()) {
+        let _ = fs::remove_file(path.as_str());
+        return Err(e);
+    }
 
-    try!(database::image::create(ctx, &img));
-    try!(backend::image::script_create(ctx, &mut img));
+    img.file = path.clone();
+
+    if let Err(e) = database::image::create(ctx, &img) {
+        let _ = fs::remove_file(path.as_str());
+        return Err(e);
+    }
+
+    if let Err(e) = backend::image::script_create(ctx, &mut img) {
+        let _ = database::image::delete(ctx, img.name.as_str());
+        let _ = fs::remove_file(path.as_str());
+        return Err(e);
+    }
+
+    events::publish(ctx, Event::new("image.created", img.name.as_str(), json!({
+        "backend": img.backend
+    })));
 
     Ok(String::new())
 }
@@ -81,6 +132,8 @@ pub fn update(ctx: &Context, obj: &str) -> Result<String> {
     let img = try!(validate(ctx, &obj));
     try!(database::image::update(ctx, &img));
 
+    events::publish(ctx, Event::new("image.updated", img.name.as_str(), json!({})));
+
     Ok(String::new())
 }
 
@@ -93,5 +146,7 @@ pub fn delete(ctx: &Context, name: &str) -> Result<String> {
     try!(backend::image::script_delete(ctx, &img));
     try!(database::image::delete(ctx, img.name.as_str()));
 
+    events::publish(ctx, Event::new("image.deleted", img.name.as_str(), json!({})));
+
     Ok(String::new())
 }
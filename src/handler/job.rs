@@ -0,0 +1,45 @@
+use serde_json;
+
+use audit;
+use common::{Context, Result};
+use common::structs::Job;
+use database;
+use jobs;
+
+/*
+ * Hide the secrets of the job argument, it is only kept whole to run the command
+ */
+fn redacted(mut job: Job) -> Job {
+    job.argument = audit::redact(job.argument.as_str());
+    job
+}
+
+/*
+ * Handle a 'listjob' command
+ */
+pub fn list(ctx: &Context) -> Result<String> {
+    let jobs: Vec<Job> = try!(database::job::list(ctx)).into_iter().map(redacted).collect();
+    let s = try!(serde_json::to_string(&jobs));
+
+    Ok(s)
+}
+
+/*
+ * Handle a 'getjob' command
+ */
+pub fn get(ctx: &Context, id: &str) -> Result<String> {
+    let job = try!(database::job::get(ctx, id));
+    let s = try!(serde_json::to_string(&redacted(job)));
+
+    Ok(s)
+}
+
+/*
+ * Handle a 'canceljob' command
+ */
+pub fn cancel(ctx: &Context, id: &str) -> Result<String> {
+    let job = try!(jobs::cancel(ctx, id));
+    let s = try!(serde_json::to_string(&redacted(job)));
+
+    Ok(s)
+}
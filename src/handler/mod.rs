@@ -3,40 +3,146 @@
  */
 
 mod image;
-mod vm;
+pub mod vm;
 mod network;
 mod snapshot;
+mod job;
 
-use std::collections::HashMap;
+use std::time::Instant;
 
-use serde_json::{self, Number};
+use serde_json::{self, Map};
 use serde_json::value::Value;
 
 use common::{Context, Result, Error};
-use utils;
+use auth::Identity;
+use audit;
+use database;
+use jobs;
+use metrics;
+use stats;
+
+/*
+ * Commands running in the background as jobs, see the jobs module
+ * Clients get the ID of the job right away instead of the command's result
+ */
+const JOBS: &'static [&'static str] = &[
+    "createimg", "delimg",
+    "createvm", "delvm", "startvm", "stopvm", "migratevm",
+    "createsnap", "restoresnap", "delsnap"
+];
+
+/*
+ * Commands answered right away
+ */
+const COMMANDS: &'static [&'static str] = &[
+    "status",
+    "listimg", "getimg", "updateimg",
+    "listvm", "getvm", "updatevm", "statusvm", "statsvm",
+    "createnet", "listnet", "getnet", "updatenet", "delnet",
+    "listsnap",
+    "listjob", "getjob", "canceljob",
+    "listaudit",
+    "metrics",
+    "subscribe"
+];
+
+/*
+ * Check if a command runs as a job
+ */
+pub fn is_job(cmd: &str) -> bool {
+    JOBS.iter().any(|c| *c == cmd)
+}
+
+/*
+ * Check if a command exists, whether it runs as a job or not
+ */
+pub fn is_command(cmd: &str) -> bool {
+    is_job(cmd) || COMMANDS.iter().any(|c| *c == cmd)
+}
+
+/*
+ * Return the type and name of the object modified by a command, if any
+ * Commands modifying the same object are serialized, see the locks module
+ */
+pub fn target(cmd: &str, obj: &str) -> Option<(&'static str, String)> {
+    let field = |key: &str| -> Option<String> {
+        match serde_json::from_str::<Value>(obj) {
+            Ok(json) => json.get(key).and_then(|v| v.as_str()).map(|v| v.to_string()),
+            Err(_) => None
+        }
+    };
+
+    match cmd {
+        "createimg" | "updateimg" => field("name").map(|name| ("image", name)),
+        "delimg" => Some(("image", obj.to_string())),
+
+        "createvm" | "updatevm" | "migratevm" => field("name").map(|name| ("vm", name)),
+        "delvm" | "startvm" | "stopvm" => Some(("vm", obj.to_string())),
+
+        // Snapshots are taken and restored by the VM's backend
+        "createsnap" | "restoresnap" | "delsnap" => field("vm").map(|vm| ("vm", vm)),
+
+        "createnet" | "updatenet" => field("name").map(|name| ("network", name)),
+        "delnet" => Some(("network", obj.to_string())),
+
+        _ => None
+    }
+}
+
+/*
+ * Check if a client is allowed to run a command
+ */
+pub fn authorize(client: &str, identity: &Identity, cmd: &str) -> Result<()> {
+    if !identity.allows(cmd) {
+        warn!("handler", "permission denied"; client = client, identity = identity.name, role = identity.role, command = cmd);
+        return Err(Error::permission_denied(format!("The '{}' role is not allowed to run '{}'", identity.role, cmd)));
+    }
+
+    Ok(())
+}
 
 /*
  * Handle a command, and return its result as a string
  */
-pub fn handle(ctx: &Context, client: &str, cmd: &str, obj: &str) -> Result<String> {
+pub fn handle(ctx: &Context, client: &str, identity: &Identity, cmd: &str, obj: &str) -> Result<String> {
+    let started = Instant::now();
+    let res = execute(ctx, client, identity, cmd, obj);
+
+    // Submitted jobs are recorded once they are done, see the jobs module
+    if !is_job(cmd) || res.is_err() {
+        let duration = started.elapsed();
+
+        audit::record(ctx, client, identity.name.as_str(), cmd, obj, "", duration, &res);
+        ctx.metrics.command(cmd, duration, res.is_ok());
+    }
+
+    res
+}
+
+fn execute(ctx: &Context, client: &str, identity: &Identity, cmd: &str, obj: &str) -> Result<String> {
+    try!(authorize(client, identity, cmd));
+
+    if is_job(cmd) {
+        let job = try!(jobs::submit(ctx, client, identity.name.as_str(), cmd, obj));
+        info!("handler", "command queued"; client = client, command = cmd, job = job.id);
+
+        return Ok(json!({"job": job.id}).to_string());
+    }
+
+    let _lock = target(cmd, obj).map(|(kind, name)| ctx.locks.lock(kind, name.as_str()));
+
     let res = match cmd {
         "status" => status(ctx),
 
-        "createimg" => image::create(ctx, obj),
         "listimg" => image::list(ctx),
         "getimg" => image::get(ctx, obj),
         "updateimg" => image::update(ctx, obj),
-        "delimg" => image::delete(ctx, obj),
 
-        "createvm" => vm::create(ctx, obj),
         "listvm" => vm::list(ctx),
         "getvm" => vm::get(ctx, obj),
         "updatevm" => vm::update(ctx, obj),
-        "delvm" => vm::delete(ctx, obj),
-        "startvm" => vm::start(ctx, obj),
-        "stopvm" => vm::stop(ctx, obj),
         "statusvm" => vm::status(ctx, obj),
-        "migratevm" => vm::migrate(ctx, obj),
+        "statsvm" => stats::get(ctx, obj),
 
         "createnet" => network::create(ctx, obj),
         "listnet" => network::list(ctx),
@@ -44,38 +150,94 @@ pub fn handle(ctx: &Context, client: &str, cmd: &str, obj: &str) -> Result<Strin
         "updatenet" => network::update(ctx, obj),
         "delnet" => network::delete(ctx, obj),
 
-        "createsnap" => snapshot::create(ctx, obj),
         "listsnap" => snapshot::list(ctx, obj),
-        "restoresnap" => snapshot::restore(ctx, obj),
-        "delsnap" => snapshot::delete(ctx, obj),
 
-        _ => Err(Error::new("Unknown command"))
+        "listjob" => job::list(ctx),
+        "getjob" => job::get(ctx, obj),
+        "canceljob" => job::cancel(ctx, obj),
+
+        "listaudit" => audit::list(ctx, obj),
+
+        "metrics" => metrics::render(ctx),
+
+        // Streams are handled by the interfaces supporting them
+        "subscribe" => Err(Error::invalid_argument("Events can only be streamed over the HTTP and Unix interfaces")),
+
+        _ => Err(Error::invalid_argument("Unknown command"))
     };
 
     match res {
         Ok(s) => {
-            println!("[{}]: {}: command executed successfully", client, cmd);
+            info!("handler", "command executed successfully"; client = client, command = cmd);
             Ok(s)
         },
         Err(e) => {
-            println!("[{}]: {} '{}': error: {}", client, cmd, obj, e);
+            error!("handler", "command failed: {}", e; client = client, command = cmd, argument = audit::redact(obj));
             Err(e)
         }
     }
 }
 
+/*
+ * Run a command submitted as a job, called by the job workers
+ */
+pub fn run(ctx: &Context, job: &jobs::Handle, cmd: &str, obj: &str) -> Result<String> {
+    let _lock = target(cmd, obj).map(|(kind, name)| ctx.locks.lock(kind, name.as_str()));
+
+    match cmd {
+        "createimg" => image::create(ctx, job, obj),
+        "delimg" => image::delete(ctx, obj),
+
+        "createvm" => vm::create(ctx, obj),
+        "delvm" => vm::delete(ctx, obj),
+        "startvm" => vm::start(ctx, obj),
+        "stopvm" => vm::stop(ctx, obj),
+        "migratevm" => vm::migrate(ctx, job, obj),
+
+        "createsnap" => snapshot::create(ctx, obj),
+        "restoresnap" => snapshot::restore(ctx, obj),
+        "delsnap" => snapshot::delete(ctx, obj),
+
+        _ => Err(Error::invalid_argument("Unknown command"))
+    }
+}
+
 /*
  * Handle a 'status' command, return information about the host system
+ * The usage of the host comes from the last sample, see the stats module
  */
 fn status(ctx: &Context) -> Result<String> {
-    // Construct a JSON object to return. Memory values are returned as MiB, and CPU usage as %
-    let mut data = HashMap::new();
-    let mem = try!(utils::system::global_memory_info());
+    let host = try!(ctx.stats.host(ctx));
+    let vms = try!(database::vm::list(ctx));
+
+    let mut states = Map::new();
+    states.insert(String::from("total"), json!(vms.len()));
+
+    for vm in &vms {
+        let count = states.get(vm.state.as_str()).and_then(|c| c.as_u64()).unwrap_or(0);
+        states.insert(vm.state.as_str().to_string(), json!(count + 1));
+    }
 
-    data.insert("node", Value::Number(Number::from(ctx.conf.global.node)));
-    data.insert("mem_usage", Value::Number(Number::from_f64(mem.0 as f64).unwrap()));
-    data.insert("mem_total", Value::Number(Number::from_f64(mem.1 as f64).unwrap()));
-    data.insert("cpu_usage", Value::Number(Number::from_f64(try!(utils::system::global_cpu_usage()) as f64).unwrap()));
+    // Room left for new VMs: idle cores and memory in MiB
+    let idle = (100.0 - host.cpu_usage) / 100.0;
+    let capacity = json!({
+        "cpus": host.cpus.len() as f32 * idle,
+        "memory": (host.mem_total - host.mem_usage).max(0.0)
+    });
 
-    Ok(try!(serde_json::to_string(&data)))
+    let mut data = try!(serde_json::to_value(&host));
+    {
+        let obj = try!(data.as_object_mut().ok_or(Error::new("Invalid host status")));
+        obj.insert(String::from("node"), json!(ctx.conf.global.node));
+        obj.insert(String::from("vms"), Value::Object(states));
+        obj.insert(String::from("capacity"), capacity);
+    }
+
+    Ok(data.to_string())
 }
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
@@ -3,6 +3,7 @@ use serde_json;
 use common::{Context, Result, Error};
 use common::structs::Network;
 use database;
+use events::{self, Event};
 use net;
 
 /*
@@ -13,22 +14,22 @@ fn validate(ctx: &Context, obj: &str) -> Result<Network> {
     net.node = ctx.conf.global.node;
 
     if net.name.len() == 0 {
-        return Err(Error::new("A 'name' is required"));
+        return Err(Error::invalid_argument("A 'name' is required"));
     }
     if net.cidr.len() > 0 {
         if !net::is_valid_cidr(net.cidr.as_str()) {
-            return Err(Error::new("Invalid CIDR network address"));
+            return Err(Error::invalid_argument("Invalid CIDR network address"));
         }
     }
     if net.router.len() > 0 {
         if !net::is_valid_ip(net.router.as_str()) {
-            return Err(Error::new("Invalid router IP address"));
+            return Err(Error::invalid_argument("Invalid router IP address"));
         }
     }
     if net.dns.len() > 0 {
         for dns in &net.dns {
             if !net::is_valid_ip(dns) {
-                return Err(Error::new("Invalid router IP address"));
+                return Err(Error::invalid_argument("Invalid router IP address"));
             }
         }
     }
@@ -44,12 +45,16 @@ pub fn create(ctx: &Context, obj: &str) -> Result<String> {
     try!(database::network::create(ctx, &net));
 
     let netname = net::net_dev(net.name.as_str());
-    try!(net::system::bridge_create(netname.as_str()));
+    try!(net::system::bridge_create(ctx, netname.as_str()));
 
     if net.interface.len() > 0 {
-        try!(net::system::bridge_addif(net.interface.as_str(), netname.as_str()));
+        try!(net::system::bridge_addif(ctx, net.interface.as_str(), netname.as_str()));
     }
 
+    events::publish(ctx, Event::new("network.created", net.name.as_str(), json!({
+        "cidr": net.cidr
+    })));
+
     Ok(String::new())
 }
 
@@ -80,6 +85,8 @@ pub fn update(ctx: &Context, obj: &str) -> Result<String> {
     let net = try!(validate(ctx, &obj));
     try!(database::network::update(ctx, &net));
 
+    events::publish(ctx, Event::new("network.updated", net.name.as_str(), json!({})));
+
     Ok(String::new())
 }
 
@@ -92,7 +99,9 @@ pub fn delete(ctx: &Context, name: &str) -> Result<String> {
     try!(database::network::delete(ctx, net.name.as_str()));
 
     let netname = net::net_dev(net.name.as_str());
-    try!(net::system::bridge_delete(netname.as_str()));
+    try!(net::system::bridge_delete(ctx, netname.as_str()));
+
+    events::publish(ctx, Event::new("network.deleted", net.name.as_str(), json!({})));
 
     Ok(String::new())
 }
@@ -4,6 +4,7 @@ use common::{Context, Result, Error};
 use common::structs::Snapshot;
 use database;
 use backend;
+use events::{self, Event};
 
 /*
  * Validates the user-specified parameters for snapshot creation/update
@@ -13,7 +14,7 @@ fn validate(ctx: &Context, obj: &str) -> Result<Snapshot> {
     snap.node = ctx.conf.global.node;
 
     if let Err(_) = database::vm::get(ctx, snap.vm.as_str()) {
-        return Err(Error::new("VM not found"));
+        return Err(Error::not_found("VM not found"));
     }
 
     Ok(snap)
@@ -26,20 +27,23 @@ pub fn create(ctx: &Context, obj: &str) -> Result<String> {
     let snap = try!(validate(ctx, &obj));
 
     if let Ok(_) = database::snapshot::get(ctx, snap.vm.as_str(), snap.name.as_str()) {
-        return Err(Error::new("This snapshot name is not available"));
+        return Err(Error::already_exists("This snapshot name is not available"));
     }
 
     let vm = try!(database::vm::get(ctx, snap.vm.as_str()));
 
     try!(database::snapshot::create(ctx, &snap));
 
-    match backend::vm::script_snapshot_create(ctx, &vm, snap.name.as_str()) {
-        Ok(_) => Ok(String::new()),
-        Err(e) => {
-            let _ = database::snapshot::delete(ctx, snap.vm.as_str(), snap.name.as_str());
-            Err(e)
-        }
+    if let Err(e) = backend::vm::script_snapshot_create(ctx, &vm, snap.name.as_str()) {
+        let _ = database::snapshot::delete(ctx, snap.vm.as_str(), snap.name.as_str());
+        return Err(e);
     }
+
+    events::publish(ctx, Event::new("snapshot.created", snap.name.as_str(), json!({
+        "vm": snap.vm
+    })));
+
+    Ok(String::new())
 }
 
 /*
@@ -62,6 +66,10 @@ pub fn restore(ctx: &Context, obj: &str) -> Result<String> {
 
     try!(backend::vm::script_snapshot_restore(ctx, &vm, snap.name.as_str()));
 
+    events::publish(ctx, Event::new("snapshot.restored", snap.name.as_str(), json!({
+        "vm": snap.vm
+    })));
+
     Ok(String::new())
 }
 
@@ -76,5 +84,9 @@ pub fn delete(ctx: &Context, obj: &str) -> Result<String> {
     try!(backend::vm::script_snapshot_delete(ctx, &vm, snap.name.as_str()));
     try!(database::snapshot::delete(ctx, snap.vm.as_str(), snap.name.as_str()));
 
+    events::publish(ctx, Event::new("snapshot.deleted", snap.name.as_str(), json!({
+        "vm": snap.vm
+    })));
+
     Ok(String::new())
 }
@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use serde_json;
+use serde_json::value::Value;
+
+use auth::Identity;
+use common::{Context, Result, Error, ErrorKind};
+use common::structs::{Job, JobState};
+use database;
+use jobs;
+use events;
+use utils::exec::{Runner, Output};
+
+use super::{handle, is_job};
+
+/*
+ * Runner recording the executed programs instead of spawning them
+ */
+struct Fake {
+    calls: Arc<Mutex<Vec<String>>>,
+    outputs: HashMap<String, String>, // Standard output of the programs
+    failures: Vec<String> // Programs exiting with an error
+}
+
+impl Runner for Fake {
+    fn exec(&self, program: &str, args: &[&str]) -> Result<Output> {
+        self.calls.lock().unwrap().push(format!("{} {}", program, args.join(" ")));
+
+        if self.failures.iter().any(|f| f.as_str() == program) {
+            return Ok(Output {
+                success: false,
+                stdout: String::new(),
+                stderr: String::from("fake failure")
+            });
+        }
+
+        Ok(Output {
+            success: true,
+            stdout: self.outputs.get(program).cloned().unwrap_or(String::new()),
+            stderr: String::new()
+        })
+    }
+}
+
+struct Env {
+    ctx: Context,
+    calls: Arc<Mutex<Vec<String>>>,
+    dir: PathBuf
+}
+
+impl Env {
+    fn new(name: &str, failures: &[&str]) -> Env {
+        let dir = env::temp_dir().join(format!("olvm-test-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+
+        let conf = format!(r#"
+            [global]
+            node = 1
+
+            [database]
+            type = "memory"
+
+            [[backend]]
+            name = "fake"
+
+            [backend.image]
+            path = "{}"
+            create = "image/create"
+            delete = "image/delete"
+
+            [backend.vm]
+            create = "vm/create"
+            delete = "vm/delete"
+            start = "vm/start"
+            stop = "vm/stop"
+            status = "vm/status"
+            snapshot_create = "vm/snapshot_create"
+            snapshot_restore = "vm/snapshot_restore"
+            snapshot_delete = "vm/snapshot_delete"
+        "#, dir.display());
+
+        let mut outputs = HashMap::new();
+        outputs.insert(String::from("vm/start"), String::from("pid 42\n"));
+        outputs.insert(String::from("vm/status"), String::from("running true\n"));
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let runner = Fake {
+            calls: calls.clone(),
+            outputs: outputs,
+            failures: failures.iter().map(|f| f.to_string()).collect()
+        };
+
+        Env {
+            ctx: Context::test_with_runner(conf.as_str(), Box::new(runner)),
+            calls: calls,
+            dir: dir
+        }
+    }
+
+    /*
+     * Run a command, waiting for the end of its job if it runs as one
+     */
+    fn cmd(&self, cmd: &str, obj: &str) -> Result<String> {
+        let res = try!(handle(&self.ctx, "test", &Identity::anonymous(), cmd, obj));
+        if !is_job(cmd) {
+            return Ok(res);
+        }
+
+        let job = self.run(res.as_str());
+
+        match job.state {
+            JobState::Done => Ok(job.result),
+            _ => Err(Error::with_kind(ErrorKind::from_code(job.code.as_str()), job.error))
+        }
+    }
+
+    /*
+     * Run the queued jobs, and return the one whose ID was returned by a command
+     */
+    fn run(&self, res: &str) -> Job {
+        let res: Value = serde_json::from_str(res).unwrap();
+
+        jobs::run_queued(&self.ctx);
+        database::job::get(&self.ctx, res["job"].as_str().unwrap()).unwrap()
+    }
+
+    fn json(&self, cmd: &str, obj: &str) -> Value {
+        serde_json::from_str(self.cmd(cmd, obj).unwrap().as_str()).unwrap()
+    }
+
+    fn error(&self, cmd: &str, obj: &str) -> ErrorKind {
+        self.cmd(cmd, obj).unwrap_err().kind()
+    }
+
+    fn called(&self, prefix: &str) -> bool {
+        self.calls.lock().unwrap().iter().any(|c| c.starts_with(prefix))
+    }
+}
+
+impl Drop for Env {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+const NET: &'static str = r#"{"name": "lan", "cidr": "10.0.0.0/24", "router": "10.0.0.1"}"#;
+const VM: &'static str = r#"{"name": "test", "backend": "fake", "interfaces": [{"network": "lan", "ip": "10.0.0.2"}]}"#;
+
+#[test]
+fn unknown_command() {
+    let env = Env::new("unknown", &[]);
+    assert_eq!(env.error("foo", ""), ErrorKind::InvalidArgument);
+}
+
+#[test]
+fn network_lifecycle() {
+    let env = Env::new("network", &[]);
+
+    env.cmd("createnet", NET).unwrap();
+    assert!(env.called("ip link add netlan type bridge"));
+
+    assert_eq!(env.json("listnet", "").as_array().unwrap().len(), 1);
+    assert_eq!(env.json("getnet", "lan")["router"].as_str(), Some("10.0.0.1"));
+
+    env.cmd("delnet", "lan").unwrap();
+    assert!(env.called("ip link del netlan"));
+    assert_eq!(env.error("getnet", "lan"), ErrorKind::NotFound);
+}
+
+#[test]
+fn vm_lifecycle() {
+    let env = Env::new("vm", &[]);
+
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+
+    assert!(env.called("ip tuntap add vmtest.0 mode tap"));
+    assert!(env.called("ip link set master netlan dev vmtest.0"));
+    assert!(env.called("vm/create"));
+
+    let vms = env.json("listvm", "");
+    assert_eq!(vms.as_array().unwrap().len(), 1);
+
+    let vm = env.json("getvm", "test");
+    assert_eq!(vm["interfaces"][0]["ip"].as_str(), Some("10.0.0.2"));
+    assert!(vm["interfaces"][0]["mac"].as_str().unwrap().len() > 0);
+
+    // Parameters returned by the start script are stored
+    env.cmd("startvm", "test").unwrap();
+    assert_eq!(env.json("getvm", "test")["parameters"]["pid"].as_str(), Some("42"));
+
+    assert_eq!(env.json("statusvm", "test")["running"].as_bool(), Some(true));
+
+    env.cmd("stopvm", "test").unwrap();
+    assert!(env.called("vm/stop"));
+
+    env.cmd("delvm", "test").unwrap();
+    assert!(env.called("vm/delete"));
+    assert!(env.called("ip tuntap del vmtest.0 mode tap"));
+    assert!(env.cmd("getvm", "test").is_err());
+}
+
+#[test]
+fn vm_events() {
+    let env = Env::new("vm-events", &["vm/stop"]);
+    let events = env.ctx.events.subscribe(events::parse_kinds("vm.*,snapshot.*"));
+
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+    env.cmd("startvm", "test").unwrap();
+    env.cmd("createsnap", r#"{"vm": "test", "name": "snap1"}"#).unwrap();
+    assert!(env.cmd("stopvm", "test").is_err());
+    env.cmd("delvm", "test").unwrap();
+
+    let kinds: Vec<String> = events.try_iter().map(|e| e.kind).collect();
+    assert_eq!(kinds, vec!["vm.created", "vm.started", "snapshot.created", "vm.error", "vm.deleted"]);
+}
+
+#[test]
+fn audit_log() {
+    let env = Env::new("audit", &[]);
+
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+    env.cmd("listvm", "").unwrap();
+    assert!(env.cmd("createnet", NET).is_err());
+
+    // Reads are not recorded, jobs are recorded when they end
+    let entries = env.json("listaudit", "");
+    let commands: Vec<&str> = entries.as_array().unwrap().iter().map(|e| e["command"].as_str().unwrap()).collect();
+    assert_eq!(commands, vec!["createnet", "createvm", "createnet"]);
+
+    let entries = env.json("listaudit", r#"{"kind": "vm", "object": "test"}"#);
+    let entry = &entries.as_array().unwrap()[0];
+    assert_eq!(entry["client"].as_str(), Some("test"));
+    assert_eq!(entry["identity"].as_str(), Some("anonymous"));
+    assert_eq!(entry["success"].as_bool(), Some(true));
+    assert!(entry["job"].as_str().unwrap().len() > 0);
+
+    let entries = env.json("listaudit", r#"{"command": "createnet", "limit": 1}"#);
+    assert_eq!(entries[0]["success"].as_bool(), Some(false));
+    assert_eq!(entries[0]["code"].as_str(), Some("already_exists"));
+
+    assert_eq!(env.error("listaudit", "{"), ErrorKind::InvalidArgument);
+}
+
+#[test]
+fn vm_states() {
+    let env = Env::new("vm-states", &[]);
+    let state = |env: &Env| env.json("getvm", "test")["state"].as_str().unwrap().to_string();
+
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+    assert_eq!(state(&env).as_str(), "stopped");
+
+    // The state can not be set by clients
+    let vm = r#"{"name": "test", "backend": "fake", "state": "running", "interfaces": [{"network": "lan", "ip": "10.0.0.2"}]}"#;
+    env.cmd("updatevm", vm).unwrap();
+    assert_eq!(state(&env).as_str(), "stopped");
+
+    assert_eq!(env.error("stopvm", "test"), ErrorKind::InvalidState);
+
+    env.cmd("startvm", "test").unwrap();
+    assert_eq!(state(&env).as_str(), "running");
+    assert_eq!(env.json("statusvm", "test")["state"].as_str(), Some("running"));
+
+    // Illegal operations on a running VM
+    assert_eq!(env.error("startvm", "test"), ErrorKind::InvalidState);
+    assert_eq!(env.error("delvm", "test"), ErrorKind::InvalidState);
+    assert_eq!(env.error("migratevm", r#"{"name": "test", "destination": "127.0.0.1:1997"}"#), ErrorKind::InvalidState);
+
+    env.cmd("stopvm", "test").unwrap();
+    assert_eq!(state(&env).as_str(), "stopped");
+
+    env.cmd("delvm", "test").unwrap();
+}
+
+#[test]
+fn vm_start_script_failure() {
+    let env = Env::new("vm-start-failure", &["vm/start"]);
+
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+
+    assert_eq!(env.error("startvm", "test"), ErrorKind::BackendFailure);
+    assert_eq!(env.json("getvm", "test")["state"].as_str(), Some("error"));
+
+    // VMs in error can be stopped or deleted
+    env.cmd("stopvm", "test").unwrap();
+    assert_eq!(env.json("getvm", "test")["state"].as_str(), Some("stopped"));
+    env.cmd("delvm", "test").unwrap();
+}
+
+#[test]
+fn vm_create_invalid() {
+    let env = Env::new("vm-invalid", &[]);
+
+    // Unknown network
+    assert!(env.cmd("createvm", VM).is_err());
+
+    env.cmd("createnet", NET).unwrap();
+
+    assert_eq!(env.error("createvm", r#"{"backend": "fake"}"#), ErrorKind::InvalidArgument);
+    assert_eq!(env.error("createvm", r#"{"name": "waytoolongname", "backend": "fake"}"#), ErrorKind::InvalidArgument);
+    assert_eq!(env.error("createvm", r#"{"name": "test", "backend": "fake", "image": "none"}"#), ErrorKind::InvalidArgument);
+    assert_eq!(env.error("createvm", "not json"), ErrorKind::InvalidArgument);
+
+    env.cmd("createvm", VM).unwrap();
+    assert_eq!(env.error("createvm", VM), ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn vm_duplicate_mac() {
+    let env = Env::new("vm-mac", &[]);
+    let vm = |name: &str, mac: &str| format!(r#"{{"name": "{}", "backend": "fake", "interfaces": [{{"network": "lan", "mac": "{}"}}]}}"#, name, mac);
+
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", vm("vm1", "52:54:00:00:00:01").as_str()).unwrap();
+
+    assert_eq!(env.error("createvm", vm("vm2", "52:54:00:00:00:01").as_str()), ErrorKind::AlreadyExists);
+    assert_eq!(env.error("getvm", "vm2"), ErrorKind::NotFound);
+
+    // A VM keeps its own addresses when updated, but can not take the ones of another VM
+    env.cmd("createvm", vm("vm2", "52:54:00:00:00:02").as_str()).unwrap();
+    env.cmd("updatevm", vm("vm2", "52:54:00:00:00:02").as_str()).unwrap();
+    assert_eq!(env.error("updatevm", vm("vm2", "52:54:00:00:00:01").as_str()), ErrorKind::AlreadyExists);
+
+    // The addresses are released at the end of the commands
+    assert!(!env.ctx.locks.is_locked("mac", "52:54:00:00:00:01"));
+    assert!(!env.ctx.locks.is_locked("mac", "52:54:00:00:00:02"));
+}
+
+#[test]
+fn vm_create_script_failure() {
+    let env = Env::new("vm-failure", &["vm/create"]);
+
+    env.cmd("createnet", NET).unwrap();
+
+    assert_eq!(env.error("createvm", VM), ErrorKind::BackendFailure);
+    assert_eq!(env.error("getvm", "test"), ErrorKind::NotFound);
+}
+
+#[test]
+fn snapshot_lifecycle() {
+    let env = Env::new("snapshot", &[]);
+    let snap = r#"{"vm": "test", "name": "snap1"}"#;
+
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+
+    env.cmd("createsnap", snap).unwrap();
+    assert!(env.called("vm/snapshot_create"));
+    assert!(env.cmd("createsnap", snap).is_err());
+
+    let snaps = env.json("listsnap", "test");
+    assert_eq!(snaps.as_array().unwrap().len(), 1);
+    assert_eq!(snaps[0]["name"].as_str(), Some("snap1"));
+
+    env.cmd("restoresnap", snap).unwrap();
+    assert!(env.called("vm/snapshot_restore"));
+
+    env.cmd("delsnap", snap).unwrap();
+    assert!(env.called("vm/snapshot_delete"));
+    assert_eq!(env.json("listsnap", "test").as_array().unwrap().len(), 0);
+
+    // Snapshots of unknown VMs
+    assert!(env.cmd("createsnap", r#"{"vm": "none", "name": "snap1"}"#).is_err());
+}
+
+#[test]
+fn snapshot_create_script_failure() {
+    let env = Env::new("snapshot-failure", &["vm/snapshot_create"]);
+
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+
+    assert!(env.cmd("createsnap", r#"{"vm": "test", "name": "snap1"}"#).is_err());
+    assert_eq!(env.json("listsnap", "test").as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn host_status() {
+    let env = Env::new("host-status", &[]);
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+    env.cmd("startvm", "test").unwrap();
+
+    let status = env.json("status", "");
+    assert_eq!(status["node"].as_i64(), Some(1));
+    assert_eq!(status["vms"]["total"].as_u64(), Some(1));
+    assert_eq!(status["vms"]["running"].as_u64(), Some(1));
+
+    assert_eq!(status["load"].as_array().unwrap().len(), 3);
+    assert!(status["cpus"].as_array().unwrap().len() > 0);
+    assert!(status["cpus"][0]["core"].is_u64());
+    assert!(status["cpus"][0]["usage"].is_number());
+    assert!(status["mem_total"].as_f64().unwrap() > 0.0);
+    assert!(status["kernel"].as_str().unwrap().len() > 0);
+    assert!(status["kvm"].is_boolean());
+    assert!(status["capacity"]["memory"].is_number());
+
+    // The image directory exists, the VM one does not
+    let storage = status["storage"].as_array().unwrap();
+    assert_eq!(storage.len(), 1);
+    assert_eq!(storage[0]["content"].as_str(), Some("images"));
+    assert!(storage[0]["total"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn image_lifecycle() {
+    let env = Env::new("image", &[]);
+
+    let src = env.dir.join("source.img");
+    File::create(&src).unwrap().write_all(b"disk").unwrap();
+
+    let img = format!(r#"{{"name": "debian", "backend": "fake", "file": "{}"}}"#, src.display());
+    env.cmd("createimg", img.as_str()).unwrap();
+
+    assert!(env.called("image/create"));
+    assert!(env.dir.join("debian.image").exists());
+    assert!(env.cmd("createimg", img.as_str()).is_err());
+
+    assert_eq!(env.json("listimg", "").as_array().unwrap().len(), 1);
+    assert_eq!(env.json("getimg", "debian")["file"].as_str(), Some(env.dir.join("debian.image").to_str().unwrap()));
+
+    env.cmd("delimg", "debian").unwrap();
+    assert!(env.called("image/delete"));
+    assert!(env.cmd("getimg", "debian").is_err());
+}
+
+#[test]
+fn image_create_failure() {
+    let env = Env::new("image-failure", &["image/create"]);
+
+    let src = env.dir.join("source.img");
+    File::create(&src).unwrap().write_all(b"disk").unwrap();
+
+    // Neither the copy nor the record are left behind
+    let img = format!(r#"{{"name": "debian", "backend": "fake", "file": "{}"}}"#, src.display());
+    assert!(env.cmd("createimg", img.as_str()).is_err());
+
+    assert!(!env.dir.join("debian.image").exists());
+    assert!(env.cmd("getimg", "debian").is_err());
+}
+
+#[test]
+fn job_lifecycle() {
+    let env = Env::new("job", &[]);
+    env.cmd("createnet", NET).unwrap();
+
+    // Commands running as jobs only return the job's ID
+    let res = handle(&env.ctx, "test", &Identity::anonymous(), "createvm", VM).unwrap();
+    let id = serde_json::from_str::<Value>(res.as_str()).unwrap()["job"].as_str().unwrap().to_string();
+
+    assert!(!env.called("vm/create"));
+    assert_eq!(env.json("getjob", id.as_str())["state"].as_str(), Some("queued"));
+    assert_eq!(env.json("listjob", "").as_array().unwrap().len(), 1);
+
+    let job = env.run(res.as_str());
+    assert!(env.called("vm/create"));
+    assert_eq!(job.state, JobState::Done);
+    assert_eq!(job.progress, 100);
+
+    let job = env.json("getjob", id.as_str());
+    assert_eq!(job["state"].as_str(), Some("done"));
+    assert_eq!(job["command"].as_str(), Some("createvm"));
+
+    assert_eq!(env.error("getjob", "none"), ErrorKind::NotFound);
+    assert_eq!(env.error("canceljob", id.as_str()), ErrorKind::InvalidState);
+}
+
+#[test]
+fn job_redacted() {
+    let env = Env::new("job-redacted", &[]);
+
+    let job = jobs::submit(&env.ctx, "test", "anonymous", "createimg", r#"{"name": "debian", "password": "hunter2"}"#).unwrap();
+
+    // The secret is kept to run the command, but never returned
+    assert!(database::job::get(&env.ctx, job.id.as_str()).unwrap().argument.contains("hunter2"));
+
+    let listed = env.cmd("listjob", "").unwrap();
+    assert!(!listed.contains("hunter2"));
+
+    let job = env.json("getjob", job.id.as_str());
+    let argument: Value = serde_json::from_str(job["argument"].as_str().unwrap()).unwrap();
+    assert_eq!(argument["name"].as_str(), Some("debian"));
+    assert_eq!(argument["password"].as_str(), Some("<redacted>"));
+}
+
+#[test]
+fn job_failure() {
+    let env = Env::new("job-failure", &["vm/create"]);
+    env.cmd("createnet", NET).unwrap();
+
+    let res = handle(&env.ctx, "test", &Identity::anonymous(), "createvm", VM).unwrap();
+    let job = env.run(res.as_str());
+
+    assert_eq!(job.state, JobState::Failed);
+    assert_eq!(job.code.as_str(), "backend_failure");
+    assert!(job.error.len() > 0);
+}
+
+#[test]
+fn job_cancel() {
+    let env = Env::new("job-cancel", &[]);
+    env.cmd("createnet", NET).unwrap();
+    env.cmd("createvm", VM).unwrap();
+
+    let res = handle(&env.ctx, "test", &Identity::anonymous(), "startvm", "test").unwrap();
+    let id = serde_json::from_str::<Value>(res.as_str()).unwrap()["job"].as_str().unwrap().to_string();
+
+    assert_eq!(env.json("canceljob", id.as_str())["state"].as_str(), Some("cancelled"));
+
+    let job = env.run(res.as_str());
+    assert_eq!(job.state, JobState::Cancelled);
+    assert!(!env.called("vm/start"));
+    assert_eq!(env.json("getvm", "test")["state"].as_str(), Some("stopped"));
+}
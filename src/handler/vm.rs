@@ -4,12 +4,45 @@ use serde_json;
 use serde_json::value::Value;
 
 use common::{Context, Result, Error};
-use common::structs::VM;
+use common::structs::{VM, State};
 use database;
-use handler;
 use backend;
 use remote;
 use net;
+use events::{self, Event};
+use jobs;
+use locks::Guard;
+
+/*
+ * Check if a VM can go from a state to another
+ */
+fn can_transition(from: State, to: State) -> bool {
+    match (from, to) {
+        (State::Defined, State::Creating) => true,
+        (State::Creating, State::Stopped) | (State::Creating, State::Error) => true,
+        (State::Stopped, State::Starting) | (State::Error, State::Starting) | (State::Crashed, State::Starting) => true,
+        (State::Starting, State::Running) | (State::Starting, State::Error) => true,
+        (State::Running, State::Stopping) | (State::Error, State::Stopping) | (State::Crashed, State::Stopping) => true,
+        (State::Running, State::Crashed) => true,
+        (State::Stopping, State::Stopped) | (State::Stopping, State::Error) => true,
+        (State::Stopped, State::Migrating) => true,
+        (State::Migrating, State::Stopped) => true,
+        _ => false
+    }
+}
+
+/*
+ * Move a VM to a new state and store it
+ * The action is only used in the error message
+ */
+fn transition(ctx: &Context, vm: &mut VM, to: State, action: &str) -> Result<()> {
+    if !can_transition(vm.state, to) {
+        return Err(Error::invalid_state(format!("Cannot {} VM '{}' while it is {}", action, vm.name, vm.state.as_str())));
+    }
+
+    vm.state = to;
+    database::vm::update(ctx, vm)
+}
 
 /*
  * Validates the user-specified parameters for VM creation, and sets up the VM's network interfaces
@@ -17,22 +50,23 @@ use net;
 fn validate(ctx: &Context, obj: &str) -> Result<VM> {
     let mut vm = try!(VM::from_json(obj));
     vm.node = ctx.conf.global.node;
+    vm.state = State::Defined;
 
     if vm.name.len() == 0 {
-        return Err(Error::new("A 'name' is required"));
+        return Err(Error::invalid_argument("A 'name' is required"));
     }
     if vm.name.len() > 11 {
-        return Err(Error::new("The 'name' must be less than 11 characters long"));
+        return Err(Error::invalid_argument("The 'name' must be less than 11 characters long"));
     }
     if vm.backend.len() == 0 {
-        return Err(Error::new("A 'backend' is required"));
+        return Err(Error::invalid_argument("A 'backend' is required"));
     }
 
     // TODO: Check backend, make sure it exists
 
     if vm.image.len() > 0 {
         if let Err(_) = database::image::get(ctx, vm.image.as_str()) {
-            return Err(Error::new("Image not found"));
+            return Err(Error::invalid_argument("Image not found"));
         }
     }
 
@@ -47,10 +81,10 @@ fn validate(ctx: &Context, obj: &str) -> Result<VM> {
                 let ifname = net::iface_dev(vm.name.as_str(), index);
                 let netname = net::net_dev(net.name.as_str());
 
-                try!(net::system::tap_create(ifname.as_str()));
-                try!(net::system::bridge_addif(ifname.as_str(), netname.as_str()));
+                try!(net::system::tap_create(ctx, ifname.as_str()));
+                try!(net::system::bridge_addif(ctx, ifname.as_str(), netname.as_str()));
             },
-            Err(_) => return Err(Error::new(format!("Interface: network '{}' not found", iface.network)))
+            Err(_) => return Err(Error::invalid_argument(format!("Interface: network '{}' not found", iface.network)))
         };
 
         index = index + 1;
@@ -59,27 +93,49 @@ fn validate(ctx: &Context, obj: &str) -> Result<VM> {
     Ok(vm)
 }
 
+/*
+ * Lock the MAC addresses of a VM, so that concurrent commands on other VMs can not take them
+ * The locks are taken in order, two commands sharing several addresses can not deadlock
+ */
+fn lock_macs<'a>(ctx: &'a Context, vm: &VM) -> Vec<Guard<'a>> {
+    let mut macs: Vec<&str> = vm.interfaces.iter().map(|iface| iface.mac.as_str()).collect();
+    macs.sort();
+    macs.dedup();
+
+    macs.iter().map(|mac| ctx.locks.lock("mac", mac)).collect()
+}
+
+/*
+ * Make sure the MAC addresses of a VM are not used by another VM
+ */
+fn check_macs(ctx: &Context, vm: &VM) -> Result<()> {
+    for iface in &vm.interfaces {
+        match database::vm::get_mac(ctx, iface.mac.as_str()) {
+            Ok((ref other, _)) if other.name != vm.name => return Err(Error::already_exists("The specified 'mac' address is not available")),
+            _ => {}
+        };
+    }
+
+    Ok(())
+}
+
 /*
  * Handle a 'createvm' command
  */
 pub fn create(ctx: &Context, obj: &str) -> Result<String> {
     // Validate and retreive VM info from the client-specified parameters
     let mut vm = try!(validate(ctx, &obj));
+    let _macs = lock_macs(ctx, &vm);
 
     if let Ok(_) = database::vm::get(ctx, vm.name.as_str()) {
-        return Err(Error::new("This VM name is not available"));
+        return Err(Error::already_exists("This VM name is not available"));
     }
 
-    // Check interfaces and generate MAC addresses
-    for iface in &mut vm.interfaces {
-        match database::vm::get_mac(ctx, iface.mac.as_str()) {
-            Ok(_) => return Err(Error::new("The specified 'mac' address is not available")),
-            Err(_) => {}
-        };
-    }
+    try!(check_macs(ctx, &vm));
 
     // Create the VM
     try!(database::vm::create(ctx, &vm));
+    try!(transition(ctx, &mut vm, State::Creating, "create"));
 
     match backend::vm::script_create(ctx, &mut vm) {
         Ok(_) => {},
@@ -89,6 +145,12 @@ pub fn create(ctx: &Context, obj: &str) -> Result<String> {
         }
     };
 
+    try!(transition(ctx, &mut vm, State::Stopped, "create"));
+
+    events::publish(ctx, Event::new("vm.created", vm.name.as_str(), json!({
+        "backend": vm.backend
+    })));
+
     Ok(String::new())
 }
 
@@ -116,9 +178,25 @@ pub fn get(ctx: &Context, name: &str) -> Result<String> {
  * Handle a 'updatevm' command
  */
 pub fn update(ctx: &Context, obj: &str) -> Result<String> {
-    let vm = try!(validate(ctx, &obj));
+    let mut vm = try!(validate(ctx, &obj));
+    let _macs = lock_macs(ctx, &vm);
+    let current = try!(database::vm::get(ctx, vm.name.as_str()));
+
+    match current.state {
+        State::Creating | State::Starting | State::Stopping | State::Migrating | State::Unknown => {
+            return Err(Error::invalid_state(format!("Cannot update VM '{}' while it is {}", vm.name, current.state.as_str())));
+        },
+        _ => {}
+    };
+
+    try!(check_macs(ctx, &vm));
+
+    // The state can not be changed by clients
+    vm.state = current.state;
     try!(database::vm::update(ctx, &vm));
 
+    events::publish(ctx, Event::new("vm.updated", vm.name.as_str(), json!({})));
+
     Ok(String::new())
 }
 
@@ -126,15 +204,31 @@ pub fn update(ctx: &Context, obj: &str) -> Result<String> {
  * Handle a 'delvm' command
  */
 pub fn delete(ctx: &Context, name: &str) -> Result<String> {
-    let mut vm = try!(database::vm::get(ctx, name));
+    let vm = try!(database::vm::get(ctx, name));
+
+    match vm.state {
+        State::Defined | State::Stopped | State::Error | State::Crashed => {},
+        _ => return Err(Error::invalid_state(format!("Cannot delete VM '{}' while it is {}", vm.name, vm.state.as_str())))
+    };
+
+    try!(remove(ctx, &vm));
+
+    events::publish(ctx, Event::new("vm.deleted", vm.name.as_str(), json!({})));
 
-    try!(database::vm::delete(ctx, name));
-    try!(backend::vm::script_delete(ctx, &mut vm));
+    Ok(String::new())
+}
+
+/*
+ * Delete a VM, its backend resources and its network interfaces
+ */
+fn remove(ctx: &Context, vm: &VM) -> Result<String> {
+    try!(database::vm::delete(ctx, vm.name.as_str()));
+    try!(backend::vm::script_delete(ctx, vm));
 
     let mut index = 0;
     for _ in &vm.interfaces {
         let ifname = net::iface_dev(vm.name.as_str(), index);
-        try!(net::system::tap_delete(ifname.as_str()));
+        try!(net::system::tap_delete(ctx, ifname.as_str()));
 
         index = index + 1;
     }
@@ -147,12 +241,25 @@ pub fn delete(ctx: &Context, name: &str) -> Result<String> {
  */
 pub fn start(ctx: &Context, name: &str) -> Result<String> {
     let mut vm = try!(database::vm::get(ctx, name));
+    try!(transition(ctx, &mut vm, State::Starting, "start"));
 
     match backend::vm::script_start(ctx, &mut vm) {
         Ok(_) => {},
-        Err(e) => return Err(e)
+        Err(e) => {
+            try!(transition(ctx, &mut vm, State::Error, "start"));
+            events::publish(ctx, Event::new("vm.error", vm.name.as_str(), json!({
+                "action": "start",
+                "error": e.to_string()
+            })));
+
+            return Err(e);
+        }
     };
 
+    try!(transition(ctx, &mut vm, State::Running, "start"));
+
+    events::publish(ctx, Event::new("vm.started", vm.name.as_str(), json!({})));
+
     Ok(String::new())
 }
 
@@ -161,11 +268,26 @@ pub fn start(ctx: &Context, name: &str) -> Result<String> {
  */
 pub fn stop(ctx: &Context, name: &str) -> Result<String> {
     let mut vm = try!(database::vm::get(ctx, name));
+    try!(transition(ctx, &mut vm, State::Stopping, "stop"));
 
     match backend::vm::script_stop(ctx, &mut vm) {
-        Ok(_) => Ok(String::new()),
-        Err(e) => Err(e)
-    }
+        Ok(_) => {},
+        Err(e) => {
+            try!(transition(ctx, &mut vm, State::Error, "stop"));
+            events::publish(ctx, Event::new("vm.error", vm.name.as_str(), json!({
+                "action": "stop",
+                "error": e.to_string()
+            })));
+
+            return Err(e);
+        }
+    };
+
+    try!(transition(ctx, &mut vm, State::Stopped, "stop"));
+
+    events::publish(ctx, Event::new("vm.stopped", vm.name.as_str(), json!({})));
+
+    Ok(String::new())
 }
 
 /*
@@ -177,6 +299,8 @@ pub fn status(ctx: &Context, name: &str) -> Result<String> {
     match backend::vm::script_status(ctx, &mut vm) {
         Ok(p) => {
             let mut pp: HashMap<String, Value> = HashMap::new();
+            pp.insert(String::from("state"), Value::String(vm.state.as_str().to_string()));
+
             for (k, v) in p {
                 if v == "true" {
                     pp.insert(k, Value::Bool(true));
@@ -198,38 +322,72 @@ pub fn status(ctx: &Context, name: &str) -> Result<String> {
 /*
  * Migrate a VM to another host
  */
-pub fn migrate(ctx: &Context, obj: &str) -> Result<String> {
+pub fn migrate(ctx: &Context, job: &jobs::Handle, obj: &str) -> Result<String> {
     let req: Value = try!(serde_json::from_str(obj));
-    let name = try!(try!(req.get("name").ok_or(Error::new("Missing `name`"))).as_str().ok_or(Error::new("Invalid `name`")));
-    let dst = try!(try!(req.get("destination").ok_or(Error::new("Missing `destination`"))).as_str().ok_or(Error::new("Invalid `destination`")));
+    let name = try!(try!(req.get("name").ok_or(Error::invalid_argument("Missing `name`"))).as_str().ok_or(Error::invalid_argument("Invalid `name`")));
+    let dst = try!(try!(req.get("destination").ok_or(Error::invalid_argument("Missing `destination`"))).as_str().ok_or(Error::invalid_argument("Invalid `destination`")));
 
     let mut vm = try!(database::vm::get(ctx, name));
 
     if !net::is_valid_ip_port(dst) {
-        return Err(Error::new("Invalid `destination`, must be ip:port"));
-    }
-
-    let status = try!(remote::command(dst, "status", ""));
-    let remote_node = try!(try!(status.get("node").ok_or(Error::new("Remote: invalid `node`"))).as_i64().ok_or(Error::new("Remote: invalid `node`")));
-
-    if remote_node as i32 == ctx.conf.global.node {
-        return Err(Error::new("The remote's node ID is the same as the local one"));
+        return Err(Error::invalid_argument("Invalid `destination`, must be ip:port"));
     }
 
-    vm.node = 0;
-    let json = try!(vm.to_json());
-
     let dst_addr = match dst.find(':') {
         Some(i) => &dst[..i],
-        None => return Err(Error::new("Invalid destination address: missing ':'"))
+        None => return Err(Error::invalid_argument("Invalid destination address: missing ':'"))
     };
 
     let local = try!(ctx.conf.get_vm_disk(vm.backend.as_str(), vm.name.as_str()));
     // TODO: Figure out the destination path
 
-    try!(remote::command(dst, "createvm", json.as_str()));
-    try!(remote::transfer(local.as_str(), dst_addr, local.as_str()));
-    try!(handler::vm::delete(ctx, vm.name.as_str()));
+    try!(transition(ctx, &mut vm, State::Migrating, "migrate"));
 
-    Ok(String::new())
+    match send(ctx, job, &vm, dst, dst_addr, local.as_str()) {
+        Ok(_) => {
+            try!(remove(ctx, &vm));
+
+            events::publish(ctx, Event::new("vm.migrated", vm.name.as_str(), json!({
+                "destination": dst
+            })));
+
+            Ok(String::new())
+        },
+        Err(e) => {
+            try!(transition(ctx, &mut vm, State::Stopped, "migrate"));
+            Err(e)
+        }
+    }
+}
+
+/*
+ * Create a VM on a remote host and transfer its disk
+ */
+fn send(ctx: &Context, job: &jobs::Handle, vm: &VM, dst: &str, dst_addr: &str, disk: &str) -> Result<()> {
+    let status = try!(remote::command(ctx, dst, "status", ""));
+    let remote_node = try!(try!(status.get("node").ok_or(Error::network("Remote: invalid `node`"))).as_i64().ok_or(Error::network("Remote: invalid `node`")));
+
+    if remote_node as i32 == ctx.conf.global.node {
+        return Err(Error::invalid_argument("The remote's node ID is the same as the local one"));
+    }
+
+    try!(job.check());
+
+    let mut copy = vm.clone();
+    copy.node = 0;
+
+    let res = try!(remote::command(ctx, dst, "createvm", try!(copy.to_json()).as_str()));
+    try!(remote::wait(ctx, job, dst, &res));
+
+    job.progress(30);
+
+    // The VM exists on the remote from now on, delete it if the disk does not make it there
+    if let Err(e) = job.check().and_then(|_| remote::transfer(disk, dst_addr, disk)) {
+        let _ = remote::command(ctx, dst, "delvm", vm.name.as_str());
+        return Err(e);
+    }
+
+    job.progress(90);
+
+    Ok(())
 }
@@ -1,110 +1,468 @@
 /*
- * UDP interface - Read commands from a listening UDP socket
+ * HTTP interface - RESTful API on top of the command handlers
  */
 
-use std::error::Error as StdError;
-use std::net::{TcpListener, TcpStream};
-use std::io::{BufReader, Write};
+use std::net::TcpListener;
+use std::io::{BufReader, Read, Write};
 use std::sync::Arc;
 use std::process;
+use std::str;
 use std::thread;
 
+use serde_json;
+use serde_json::value::Value;
+
 use mhttp::Request;
+use openssl::ssl::{SslAcceptor, SslMethod, SslFiletype, SslVerifyMode};
 
-use common::{Context, Result, Error};
+use common::{Context, Result, Error, ErrorKind};
+use events;
 use handler;
+use metrics;
+use auth;
+use config;
 
 /*
- * Return an HTTP error to the client
+ * A request, translated into a command
  */
-fn response_error(socket: &mut TcpStream, status: &str, body: &str) -> Result<()> {
-    let resp = format!("HTTP/1.1 {}\r\nAccess-Control-Allow-Origin: *\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body);
+pub struct Route {
+    pub command: &'static str,
+    pub argument: String,
+    pub status: &'static str // Status returned when the command succeeds
+}
 
-    match socket.write(resp.as_bytes()) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(Error::new(e.description()))
+impl Route {
+    fn new<S: Into<String>>(command: &'static str, argument: S, status: &'static str) -> Route {
+        Route {
+            command: command,
+            argument: argument.into(),
+            status: status
+        }
     }
 }
 
+const OK: &'static str = "200 OK";
+const CREATED: &'static str = "201 Created";
+const ACCEPTED: &'static str = "202 Accepted"; // The command runs as a job
+const NO_CONTENT: &'static str = "204 No Content";
+
 /*
- * Return a 200 OK response to the client
+ * Set a field of a JSON object body, used to inject the names found in the URL
  */
-fn response_ok(socket: &mut TcpStream, body: &str) -> Result<()> {
-    let resp = format!("HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin: *\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+fn with_field(body: &str, key: &str, value: &str) -> Result<String> {
+    let mut obj: Value = match body.len() {
+        0 => json!({}),
+        _ => try!(serde_json::from_str(body))
+    };
+
+    {
+        let map = try!(obj.as_object_mut().ok_or(Error::invalid_argument("The request body must be a JSON object")));
+        map.insert(key.to_string(), Value::String(value.to_string()));
+    }
+
+    Ok(obj.to_string())
+}
+
+/*
+ * Return the decoded value of a query string parameter, or an empty string
+ */
+fn query(query: &str, key: &str) -> String {
+    for param in query.split('&') {
+        let (k, v) = match param.find('=') {
+            Some(i) => (&param[..i], &param[i + 1..]),
+            None => (param, "")
+        };
+
+        if k != key {
+            continue;
+        }
 
-    match socket.write(resp.as_bytes()) {
+        // Percent-decode the value, invalid escapes are kept as they are
+        let bytes = v.as_bytes();
+        let mut value = Vec::new();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let escaped = match bytes[i] {
+                b'%' if i + 2 < bytes.len() => str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()),
+                _ => None
+            };
+
+            match escaped {
+                Some(b) => {
+                    value.push(b);
+                    i += 3;
+                },
+                None => {
+                    value.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                    i += 1;
+                }
+            };
+        }
+
+        return String::from_utf8_lossy(&value).into_owned();
+    }
+
+    String::new()
+}
+
+/*
+ * Build the filter of a 'listaudit' command from the query string parameters
+ */
+fn audit_filter(params: &str) -> Result<String> {
+    let mut filter = serde_json::Map::new();
+
+    for key in &["since", "until", "limit"] {
+        let value = query(params, key);
+        if value.len() == 0 {
+            continue;
+        }
+
+        match value.parse::<i64>() {
+            Ok(n) if n >= 0 => filter.insert(key.to_string(), json!(n)),
+            _ => return Err(Error::invalid_argument(format!("Invalid value '{}' for the '{}' parameter, expected a positive number", value, key)))
+        };
+    }
+
+    for key in &["kind", "object", "command"] {
+        let value = query(params, key);
+        if value.len() > 0 {
+            filter.insert(key.to_string(), json!(value));
+        }
+    }
+
+    Ok(Value::Object(filter).to_string())
+}
+
+/*
+ * Find the command corresponding to a method and a path
+ */
+pub fn route(method: &str, path: &str, body: &str) -> Result<Option<Route>> {
+    let (path, params) = match path.find('?') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => (path, "")
+    };
+
+    let parts: Vec<&str> = path.split('/').filter(|p| p.len() > 0).collect();
+
+    let route = match (method, parts.as_slice()) {
+        ("GET", &["status"]) => Route::new("status", "", OK),
+        ("GET", &["metrics"]) => Route::new("metrics", "", OK),
+        ("GET", &["events"]) => Route::new("subscribe", query(params, "types"), OK),
+
+        // Images
+        ("GET", &["images"]) => Route::new("listimg", "", OK),
+        ("POST", &["images"]) => Route::new("createimg", body, ACCEPTED),
+        ("GET", &["images", name]) => Route::new("getimg", name, OK),
+        ("PUT", &["images", name]) => Route::new("updateimg", try!(with_field(body, "name", name)), NO_CONTENT),
+        ("DELETE", &["images", name]) => Route::new("delimg", name, ACCEPTED),
+
+        // VMs
+        ("GET", &["vms"]) => Route::new("listvm", "", OK),
+        ("POST", &["vms"]) => Route::new("createvm", body, ACCEPTED),
+        ("GET", &["vms", name]) => Route::new("getvm", name, OK),
+        ("PUT", &["vms", name]) => Route::new("updatevm", try!(with_field(body, "name", name)), NO_CONTENT),
+        ("DELETE", &["vms", name]) => Route::new("delvm", name, ACCEPTED),
+        ("POST", &["vms", name, "start"]) => Route::new("startvm", name, ACCEPTED),
+        ("POST", &["vms", name, "stop"]) => Route::new("stopvm", name, ACCEPTED),
+        ("GET", &["vms", name, "status"]) => Route::new("statusvm", name, OK),
+        ("GET", &["vms", name, "stats"]) => Route::new("statsvm", name, OK),
+        ("POST", &["vms", name, "migrate"]) => Route::new("migratevm", try!(with_field(body, "name", name)), ACCEPTED),
+
+        // Snapshots
+        ("GET", &["vms", vm, "snapshots"]) => Route::new("listsnap", vm, OK),
+        ("POST", &["vms", vm, "snapshots"]) => Route::new("createsnap", try!(with_field(body, "vm", vm)), ACCEPTED),
+        ("DELETE", &["vms", vm, "snapshots", name]) => Route::new("delsnap", json!({"vm": vm, "name": name}).to_string(), ACCEPTED),
+        ("POST", &["vms", vm, "snapshots", name, "restore"]) => Route::new("restoresnap", json!({"vm": vm, "name": name}).to_string(), ACCEPTED),
+
+        // Networks
+        ("GET", &["networks"]) => Route::new("listnet", "", OK),
+        ("POST", &["networks"]) => Route::new("createnet", body, CREATED),
+        ("GET", &["networks", name]) => Route::new("getnet", name, OK),
+        ("PUT", &["networks", name]) => Route::new("updatenet", try!(with_field(body, "name", name)), NO_CONTENT),
+        ("DELETE", &["networks", name]) => Route::new("delnet", name, NO_CONTENT),
+
+        // Jobs
+        ("GET", &["jobs"]) => Route::new("listjob", "", OK),
+        ("GET", &["jobs", id]) => Route::new("getjob", id, OK),
+        ("POST", &["jobs", id, "cancel"]) => Route::new("canceljob", id, OK),
+
+        // Audit log
+        ("GET", &["audit"]) => Route::new("listaudit", try!(audit_filter(params)), OK),
+
+        _ => return Ok(None)
+    };
+
+    Ok(Some(route))
+}
+
+/*
+ * Return the value of a request header
+ */
+fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    for (k, v) in &req.headers {
+        if k.eq_ignore_ascii_case(name) {
+            return Some(v.as_str());
+        }
+    }
+
+    None
+}
+
+/*
+ * Return the CORS headers to add to the responses, if the request origin is allowed
+ */
+fn cors_headers(ctx: &Context, req: &Request) -> String {
+    let allowed = match ctx.conf.http {
+        Some(ref http) => &http.cors,
+        None => return String::new()
+    };
+
+    let origin = match header(req, "Origin") {
+        Some(origin) => origin,
+        None => return String::new()
+    };
+
+    if allowed.iter().any(|o| o.as_str() == "*") {
+        return String::from("Access-Control-Allow-Origin: *\r\n");
+    }
+    if allowed.iter().any(|o| o.as_str() == origin) {
+        return format!("Access-Control-Allow-Origin: {}\r\nVary: Origin\r\n", origin);
+    }
+
+    String::new()
+}
+
+/*
+ * Write an HTTP response to the client
+ */
+fn response<S: Write>(socket: &mut S, status: &str, headers: &str, body: &str) -> Result<()> {
+    response_as(socket, status, headers, "application/json", body)
+}
+
+fn response_as<S: Write>(socket: &mut S, status: &str, headers: &str, content_type: &str, body: &str) -> Result<()> {
+    let resp = format!("HTTP/1.1 {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}", status, headers, content_type, body.len(), body);
+
+    // A single write on a TLS stream may only send a part of the data, write() sends all of it
+    write(socket, resp.as_str())
+}
+
+/*
+ * Send the events to the client as server-sent events, until it goes away
+ * Comments are sent as keepalives, the connection is closed at the end of the stream
+ */
+fn response_events<S: Write>(ctx: &Context, socket: &mut S, headers: &str, types: &str) -> Result<()> {
+    let head = format!("HTTP/1.1 {}\r\n{}Content-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n", OK, headers);
+    try!(write(socket, head.as_str()));
+
+    events::stream(ctx, events::parse_kinds(types), |event| {
+        let msg = match event {
+            Some(event) => format!("event: {}\ndata: {}\n\n", event.kind, try!(serde_json::to_string(event))),
+            None => String::from(": keepalive\n\n")
+        };
+
+        write(socket, msg.as_str())
+    });
+
+    Ok(())
+}
+
+/*
+ * Write a part of a streamed response
+ */
+fn write<S: Write>(socket: &mut S, data: &str) -> Result<()> {
+    match socket.write_all(data.as_bytes()).and_then(|_| socket.flush()) {
         Ok(_) => Ok(()),
-        Err(e) => Err(Error::new(e.description()))
+        Err(e) => Err(Error::new(format!("Failed to send HTTP response: {}", e)))
+    }
+}
+
+/*
+ * Answer a CORS preflight request
+ */
+fn response_options<S: Write>(socket: &mut S, headers: &str) -> Result<()> {
+    let headers = format!("{}Access-Control-Allow-Methods: GET, POST, PUT, DELETE\r\nAccess-Control-Allow-Headers: Authorization, Content-Type\r\n", headers);
+    response(socket, NO_CONTENT, headers.as_str(), "")
+}
+
+/*
+ * Return an HTTP error to the client
+ */
+fn response_error<S: Write>(socket: &mut S, headers: &str, e: &Error) -> Result<()> {
+    let status = match e.kind() {
+        ErrorKind::NotFound => "404 Not Found",
+        ErrorKind::AlreadyExists | ErrorKind::InvalidState | ErrorKind::Cancelled => "409 Conflict",
+        ErrorKind::InvalidArgument => "400 Bad Request",
+        ErrorKind::Unauthorized => "401 Unauthorized",
+        ErrorKind::PermissionDenied => "403 Forbidden",
+        ErrorKind::NetworkFailure => "502 Bad Gateway",
+        ErrorKind::StorageFailure | ErrorKind::Busy => "503 Service Unavailable",
+        ErrorKind::BackendFailure | ErrorKind::Internal => "500 Internal Server Error"
+    };
+
+    if e.kind() == ErrorKind::Unauthorized {
+        let headers = format!("{}WWW-Authenticate: Bearer\r\n", headers);
+        return response(socket, status, headers.as_str(), e.description_json().as_str());
     }
+
+    response(socket, status, headers, e.description_json().as_str())
 }
 
 /*
  * Main client loop
  */
-fn client(ctx: &Context, mut socket: TcpStream) -> Result<()> {
+fn client<S: Read + Write>(ctx: &Context, socket: &mut S, peer: &str) -> Result<()> {
     loop {
         let req: Request;
         {
-            let mut r = BufReader::new(&socket);
+            let mut r = BufReader::new(&mut *socket);
             req = match Request::parse(&mut r) {
                 Ok(req) => req,
                 Err(_) => return Ok(())
             };
         }
 
-        if req.url.len() < 2 {
-            return response_error(&mut socket, "400 Bad Request", "{\"error\": \"Please specify the command in the URL\"}")
+        let cors = cors_headers(ctx, &req);
+
+        if req.method.as_str() == "OPTIONS" {
+            try!(response_options(socket, cors.as_str()));
+            continue;
         }
 
-        let client = format!("HTTP {}", try!(socket.peer_addr()));
+        // Authenticate the client before anything else
+        let identity = match auth::bearer(ctx, header(&req, "Authorization")) {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!("http", "authentication failed: {}", e; client = peer);
+                try!(response_error(socket, cors.as_str(), &e));
+                continue;
+            }
+        };
+
+        let client = format!("{} ({})", peer, identity.name);
 
         let body = match String::from_utf8(req.body) {
             Ok(body) => body.trim().to_string(),
             Err(_) => return Ok(())
         };
 
-        try!(match handler::handle(ctx, client.as_str(), &req.url[1..], body.as_str()) {
-            Ok(result) => response_ok(&mut socket, result.as_str()),
-            Err(e) => response_error(&mut socket, "500 Internal Server Error", e.description_json().as_str())
+        let route = match route(req.method.as_str(), req.url.as_str(), body.as_str()) {
+            Ok(Some(route)) => route,
+            Ok(None) => {
+                let e = Error::not_found(format!("No route for {} {}", req.method, req.url));
+                try!(response_error(socket, cors.as_str(), &e));
+                continue;
+            },
+            Err(e) => {
+                try!(response_error(socket, cors.as_str(), &e));
+                continue;
+            }
+        };
+
+        // The connection is dedicated to the stream
+        if route.command == "subscribe" {
+            if let Err(e) = handler::authorize(client.as_str(), &identity, route.command) {
+                try!(response_error(socket, cors.as_str(), &e));
+                continue;
+            }
+
+            info!("http", "subscribed to events"; client = client, types = route.argument);
+            return response_events(ctx, socket, cors.as_str(), route.argument.as_str());
+        }
+
+        try!(match handler::handle(ctx, client.as_str(), &identity, route.command, route.argument.as_str()) {
+            Ok(ref result) if route.command == "metrics" => response_as(socket, OK, cors.as_str(), metrics::CONTENT_TYPE, result.as_str()),
+            Ok(ref result) if result.len() == 0 && route.status == OK => response(socket, NO_CONTENT, cors.as_str(), ""),
+            Ok(result) => response(socket, route.status, cors.as_str(), result.as_str()),
+            Err(e) => response_error(socket, cors.as_str(), &e)
         });
     }
 }
 
+/*
+ * Setup the TLS context from the configuration
+ */
+fn acceptor(tls: &config::TLS) -> Result<SslAcceptor> {
+    let mut builder = try!(SslAcceptor::mozilla_intermediate(SslMethod::tls()));
+
+    try!(builder.set_private_key_file(tls.key.as_str(), SslFiletype::PEM));
+    try!(builder.set_certificate_chain_file(tls.certificate.as_str()));
+    try!(builder.check_private_key());
+
+    // Require clients to present a certificate signed by the specified CA
+    if let Some(ref ca) = tls.client_ca {
+        try!(builder.set_ca_file(ca.as_str()));
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+
+    Ok(builder.build())
+}
+
 pub fn run(ctx: Arc<Context>) {
     // Retreive the listen address
-    let addr = match ctx.conf.http {
-        Some(ref http) => http.addr.clone(),
+    let (addr, tls) = match ctx.conf.http {
+        Some(ref http) => (http.addr.clone(), http.tls.as_ref()),
         None => {
-            println!("Please specify an HTTP listen address in configuration");
+            error!("http", "please specify an HTTP listen address in configuration");
             process::exit(1);
         }
     };
 
+    let acceptor = match tls {
+        Some(tls) => match acceptor(tls) {
+            Ok(acceptor) => Some(Arc::new(acceptor)),
+            Err(e) => {
+                error!("http", "failed to setup TLS: {}", e);
+                process::exit(1);
+            }
+        },
+        None => None
+    };
+
+    let scheme = match acceptor {
+        Some(_) => "HTTPS",
+        None => "HTTP"
+    };
+
     // Bind a listen socket
     let listener = match TcpListener::bind(addr.as_str()) {
         Ok(s) => s,
         Err(e) => {
-            println!("Failed to bind socket: {}", e);
+            error!("http", "failed to bind socket: {}", e; addr = addr);
             process::exit(1);
         }
     };
 
-    println!("Waiting for commands on HTTP {}...", addr);
+    info!("http", "waiting for commands"; addr = addr, scheme = scheme);
 
     // Process all client connections
     for socket in listener.incoming() {
         match socket {
-            Ok(socket) => {
+            Ok(mut socket) => {
                 let ctx = ctx.clone();
+                let acceptor = acceptor.clone();
 
                 thread::spawn(move || {
-                    match client(ctx.as_ref(), socket) {
+                    let peer = match socket.peer_addr() {
+                        Ok(addr) => format!("{} {}", scheme, addr),
+                        Err(e) => return warn!("http", "failed to get the address of a client: {}", e)
+                    };
+
+                    let res = match acceptor {
+                        Some(acceptor) => match acceptor.accept(socket) {
+                            Ok(mut stream) => client(ctx.as_ref(), &mut stream, peer.as_str()),
+                            Err(e) => Err(Error::network(format!("TLS handshake failed: {}", e)))
+                        },
+                        None => client(ctx.as_ref(), &mut socket, peer.as_str())
+                    };
+
+                    match res {
                         Ok(_) => {},
-                        Err(e) => println!("{}", e)
+                        Err(e) => warn!("http", "{}", e; client = peer)
                     };
                 });
             },
-            Err(e) => println!("{}", e)
+            Err(e) => error!("http", "failed to accept connection: {}", e)
         };
     }
 }
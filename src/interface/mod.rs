@@ -6,6 +6,7 @@
 pub mod stdin;
 pub mod udp;
 pub mod http;
+pub mod unix;
 
 /*
  * Parse a command from a string
@@ -5,6 +5,7 @@
 use std::io::{self, BufReader, BufRead, Write};
 
 use common::Context;
+use auth::Identity;
 use handler;
 
 /*
@@ -33,7 +34,7 @@ pub fn run(ctx: &Context) {
 
         let (command, obj) = super::parse_command(line);
 
-        match handler::handle(ctx, "stdin", command.as_str(), obj.as_str()) {
+        match handler::handle(ctx, "stdin", &Identity::anonymous(), command.as_str(), obj.as_str()) {
             Ok(result) => println!("{}", result),
             Err(e) => println!("{}", e)
         };
@@ -1,43 +1,167 @@
-use super::{Parameter, Command};
+use serde_json;
+use serde_json::value::Value;
+
+use common::ErrorKind;
+
+use super::parse_command;
+use super::http::route;
 
 /*
- * Command
+ * parse_command
  */
 #[test]
-fn command_from_str_valid() {
-    let c = Command::from_str("listvm").unwrap();
+fn parse_command_name_only() {
+    let (cmd, obj) = parse_command(String::from("listvm"));
+
+    assert_eq!(cmd.as_str(), "listvm");
+    assert_eq!(obj.as_str(), "");
+
+    let (cmd, obj) = parse_command(String::from("\t listimg   \n"));
+
+    assert_eq!(cmd.as_str(), "listimg");
+    assert_eq!(obj.as_str(), "");
+}
+
+#[test]
+fn parse_command_name_argument() {
+    let (cmd, obj) = parse_command(String::from("getvm test"));
 
-    assert_eq!(c.name.as_str(), "listvm");
-    assert_eq!(c.parameters.len(), 0);
+    assert_eq!(cmd.as_str(), "getvm");
+    assert_eq!(obj.as_str(), "test");
+}
 
-    let c = Command::from_str("\t listimg   ").unwrap();
+#[test]
+fn parse_command_json_argument() {
+    let (cmd, obj) = parse_command(String::from("createvm {\"name\": \"test\",\r\n \"backend\": \"kvm\"}\n"));
 
-    assert_eq!(c.name.as_str(), "listimg");
-    assert_eq!(c.parameters.len(), 0);
+    assert_eq!(cmd.as_str(), "createvm");
+    assert_eq!(obj.as_str(), "{\"name\": \"test\", \"backend\": \"kvm\"}");
+}
 
-    let c = Command::from_str("createvm, name test, cpus 1, ram 512").unwrap();
+#[test]
+fn parse_command_empty() {
+    let (cmd, obj) = parse_command(String::new());
 
-    assert_eq!(c.name.as_str(), "createvm");
-    assert_eq!(c.parameters.len(), 3);
+    assert_eq!(cmd.as_str(), "");
+    assert_eq!(obj.as_str(), "");
+}
+
+/*
+ * HTTP routes
+ */
+#[test]
+fn route_resources() {
+    let r = route("GET", "/vms", "").unwrap().unwrap();
+    assert_eq!(r.command, "listvm");
+    assert_eq!(r.status, "200 OK");
+
+    let r = route("POST", "/vms", "{\"name\": \"test\"}").unwrap().unwrap();
+    assert_eq!(r.command, "createvm");
+    assert_eq!(r.argument.as_str(), "{\"name\": \"test\"}");
+    assert_eq!(r.status, "202 Accepted");
+
+    let r = route("GET", "/vms/test?pretty", "").unwrap().unwrap();
+    assert_eq!(r.command, "getvm");
+    assert_eq!(r.argument.as_str(), "test");
+
+    let r = route("DELETE", "/images/debian/", "").unwrap().unwrap();
+    assert_eq!(r.command, "delimg");
+    assert_eq!(r.argument.as_str(), "debian");
+    assert_eq!(r.status, "202 Accepted");
+
+    let r = route("POST", "/vms/test/start", "").unwrap().unwrap();
+    assert_eq!(r.command, "startvm");
+    assert_eq!(r.argument.as_str(), "test");
+
+    let r = route("GET", "/vms/test/stats", "").unwrap().unwrap();
+    assert_eq!(r.command, "statsvm");
+    assert_eq!(r.argument.as_str(), "test");
+    assert_eq!(r.status, "200 OK");
+}
 
-    assert_eq!(c.parameters[0].key.as_str(), "name");
-    assert_eq!(c.parameters[0].value.as_str(), "test");
+#[test]
+fn route_injects_url_names() {
+    let r = route("PUT", "/networks/lan", "{\"cidr\": \"10.0.0.0/24\"}").unwrap().unwrap();
+    let obj: Value = serde_json::from_str(r.argument.as_str()).unwrap();
+
+    assert_eq!(r.command, "updatenet");
+    assert_eq!(obj["name"].as_str(), Some("lan"));
+    assert_eq!(obj["cidr"].as_str(), Some("10.0.0.0/24"));
+
+    let r = route("POST", "/vms/test/snapshots", "").unwrap().unwrap();
+    let obj: Value = serde_json::from_str(r.argument.as_str()).unwrap();
+
+    assert_eq!(r.command, "createsnap");
+    assert_eq!(obj["vm"].as_str(), Some("test"));
+
+    let r = route("POST", "/vms/test/snapshots/snap1/restore", "").unwrap().unwrap();
+    let obj: Value = serde_json::from_str(r.argument.as_str()).unwrap();
+
+    assert_eq!(r.command, "restoresnap");
+    assert_eq!(obj["vm"].as_str(), Some("test"));
+    assert_eq!(obj["name"].as_str(), Some("snap1"));
+}
+
+#[test]
+fn route_jobs() {
+    let r = route("GET", "/jobs", "").unwrap().unwrap();
+    assert_eq!(r.command, "listjob");
+
+    let r = route("GET", "/jobs/42", "").unwrap().unwrap();
+    assert_eq!(r.command, "getjob");
+    assert_eq!(r.argument.as_str(), "42");
+
+    let r = route("POST", "/jobs/42/cancel", "").unwrap().unwrap();
+    assert_eq!(r.command, "canceljob");
+    assert_eq!(r.argument.as_str(), "42");
+    assert_eq!(r.status, "200 OK");
+}
+
+#[test]
+fn route_events() {
+    let r = route("GET", "/events", "").unwrap().unwrap();
+    assert_eq!(r.command, "subscribe");
+    assert_eq!(r.argument.as_str(), "");
+
+    let r = route("GET", "/events?types=vm.*,job.done", "").unwrap().unwrap();
+    assert_eq!(r.argument.as_str(), "vm.*,job.done");
+
+    let r = route("GET", "/events?since=0&types=vm.%2A%2Cimage.deleted", "").unwrap().unwrap();
+    assert_eq!(r.argument.as_str(), "vm.*,image.deleted");
+}
+
+#[test]
+fn route_metrics() {
+    let r = route("GET", "/metrics", "").unwrap().unwrap();
+    assert_eq!(r.command, "metrics");
+    assert_eq!(r.status, "200 OK");
+}
+
+#[test]
+fn route_audit() {
+    let r = route("GET", "/audit", "").unwrap().unwrap();
+    assert_eq!(r.command, "listaudit");
+    assert_eq!(r.argument.as_str(), "{}");
 
-    assert_eq!(c.parameters[1].key.as_str(), "cpus");
-    assert_eq!(c.parameters[1].value.as_str(), "1");
+    let r = route("GET", "/audit?kind=vm&object=web%201&since=1500000000&limit=10", "").unwrap().unwrap();
+    let filter: Value = serde_json::from_str(r.argument.as_str()).unwrap();
+    assert_eq!(filter["kind"].as_str(), Some("vm"));
+    assert_eq!(filter["object"].as_str(), Some("web 1"));
+    assert_eq!(filter["since"].as_i64(), Some(1500000000));
+    assert_eq!(filter["limit"].as_i64(), Some(10));
 
-    assert_eq!(c.parameters[2].key.as_str(), "ram");
-    assert_eq!(c.parameters[2].value.as_str(), "512");
+    assert!(route("GET", "/audit?until=yesterday", "").is_err());
 }
 
 #[test]
-#[should_panic]
-fn command_from_str_invalid_empty() {
-    Command::from_str("").unwrap();
+fn route_unknown() {
+    assert!(route("GET", "/delvm", "").unwrap().is_none());
+    assert!(route("PATCH", "/vms/test", "").unwrap().is_none());
+    assert!(route("GET", "/vms/test/snapshots/snap1/foo", "").unwrap().is_none());
 }
 
 #[test]
-#[should_panic]
-fn command_from_str_invalid_param() {
-    Command::from_str("createvm, name").unwrap();
+fn route_invalid_body() {
+    let e = route("PUT", "/vms/test", "[1, 2]").err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::InvalidArgument);
 }
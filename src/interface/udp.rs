@@ -1,59 +1,257 @@
 /*
  * UDP interface - Read commands from a listening UDP socket
+ * Requests carrying an identifier are answered with chunked responses, see utils::frame
+ * Commands are handled by a pool of threads, so that slow commands do not block the other clients
  */
 
+use std::collections::VecDeque;
 use std::net::{UdpSocket, SocketAddr};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::process;
+use std::time::{Duration, Instant};
 
 use common::{Context, Result, Error};
+use utils::frame;
+use utils::pool::Pool;
 use handler;
+use auth;
 
-fn send(socket: &UdpSocket, dst: SocketAddr, mut buf: String) -> Result<()> {
-    // Add a newline to improve the client's output
-    buf.push('\n');
+/*
+ * Number of responses kept to answer duplicate requests, and for how long
+ * Requests in progress are forgotten after PENDING_TTL, in case their worker got stuck
+ */
+const CACHE_SIZE: usize = 256;
+const CACHE_TTL: u64 = 60;
+const PENDING_TTL: u64 = 600;
+
+/*
+ * Responses sent recently, by client and request identifier
+ * Requests still being handled have no response yet, they are kept until it is stored
+ */
+struct Cache {
+    entries: VecDeque<(SocketAddr, String, Instant, Option<Vec<u8>>)>
+}
+
+impl Cache {
+    fn new() -> Cache {
+        Cache {
+            entries: VecDeque::new()
+        }
+    }
+
+    fn valid(entry: &(SocketAddr, String, Instant, Option<Vec<u8>>)) -> bool {
+        let ttl = match entry.3 {
+            Some(_) => CACHE_TTL,
+            None => PENDING_TTL
+        };
+
+        entry.2.elapsed() < Duration::from_secs(ttl)
+    }
+
+    fn get(&self, src: SocketAddr, id: &str) -> Option<Option<Vec<u8>>> {
+        for e in &self.entries {
+            if e.0 == src && e.1.as_str() == id && Cache::valid(e) {
+                return Some(e.3.clone());
+            }
+        }
+
+        None
+    }
+
+    fn insert(&mut self, src: SocketAddr, id: String, res: Option<Vec<u8>>) {
+        self.remove(src, id.as_str());
+        self.entries.retain(Cache::valid);
+
+        // Evict the oldest response first, the requests in progress are bounded by the pool
+        if self.entries.len() >= CACHE_SIZE {
+            let i = self.entries.iter().position(|e| e.3.is_some()).unwrap_or(0);
+            self.entries.remove(i);
+        }
+
+        self.entries.push_back((src, id, Instant::now(), res));
+    }
 
+    fn remove(&mut self, src: SocketAddr, id: &str) {
+        self.entries.retain(|e| e.0 != src || e.1.as_str() != id);
+    }
+
+    /*
+     * Remove a request if it is still in progress, its response is kept otherwise
+     */
+    fn forget(&mut self, src: SocketAddr, id: &str) {
+        self.entries.retain(|e| e.0 != src || e.1.as_str() != id || e.3.is_some());
+    }
+}
+
+/*
+ * State shared by the receiving loop and the workers
+ */
+struct Udp {
+    ctx: Arc<Context>,
+    socket: UdpSocket,
+    cache: Mutex<Cache>,
+    nonces: auth::Nonces
+}
+
+/*
+ * Request handed to the workers, forgotten by the cache when the worker is done with it,
+ * whether it stored a response, failed, panicked or never ran because the pool was full
+ */
+struct InProgress {
+    udp: Arc<Udp>,
+    src: SocketAddr,
+    id: Option<String>
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        if let Some(ref id) = self.id {
+            if let Ok(mut cache) = self.udp.cache.lock() {
+                cache.forget(self.src, id.as_str());
+            }
+        }
+    }
+}
+
+fn send(socket: &UdpSocket, dst: SocketAddr, buf: &[u8]) -> Result<()> {
     // Send response to the client
-    match socket.send_to(buf.as_bytes(), &dst) {
+    match socket.send_to(buf, &dst) {
         Ok(_) => Ok(()),
         Err(e) => Err(Error::new(format!("Failed to send UDP packet: {}", e)))
     }
 }
 
-fn command(ctx: &Context, socket: &UdpSocket, src: SocketAddr, buf: Vec<u8>) -> Result<()> {
+/*
+ * Send a response in chunks
+ */
+fn send_chunks(socket: &UdpSocket, dst: SocketAddr, id: &str, res: &[u8]) -> Result<()> {
+    for chunk in frame::chunks(id, res) {
+        try!(send(socket, dst, chunk.as_slice()));
+    }
+
+    Ok(())
+}
+
+/*
+ * Run a command, return the response to send back if any
+ */
+fn command(udp: &Udp, src: SocketAddr, id: Option<&str>, buf: &[u8]) -> Result<Option<String>> {
+    let ctx = udp.ctx.as_ref();
+
     // Parse and handle the command
-    let s = match String::from_utf8(buf) {
+    let s = match String::from_utf8(buf.to_vec()) {
         Ok(s) => s,
         Err(e) => return Err(Error::new(format!("Read string from UDP packet: {}", e)))
     };
 
+    // Ignore empty datagrams
+    if s.trim().len() == 0 {
+        return Ok(None);
+    }
+
+    // Authenticate the client before anything else
+    let (identity, s) = match auth::envelope(ctx, &udp.nonces, id, s.as_str()) {
+        Ok(res) => res,
+        Err(e) => {
+            warn!("udp", "authentication failed: {}", e; client = src);
+            return Ok(Some(e.description_json()));
+        }
+    };
+
     let (command, obj) = super::parse_command(s);
 
     // Ignore empty commands
     if command.len() == 0 {
-        return Ok(());
+        return Ok(None);
+    }
+
+    let client = format!("UDP {} ({})", src, identity.name);
+
+    match handler::handle(ctx, client.as_str(), &identity, command.as_str(), obj.as_str()) {
+        Ok(result) => Ok(Some(result)),
+        Err(e) => Ok(Some(e.description_json()))
     }
+}
+
+/*
+ * Handle a request and send the response, run by the workers
+ */
+fn respond(udp: &Udp, src: SocketAddr, buf: &[u8]) -> Result<()> {
+    let (id, payload) = match frame::parse_request(buf) {
+        Some(req) => req,
+        None => {
+            // Requests without an identifier get a single datagram response
+            return match try!(command(udp, src, None, buf)) {
+                Some(mut res) => {
+                    // Add a newline to improve the client's output
+                    res.push('\n');
+                    send(&udp.socket, src, res.as_bytes())
+                },
+                None => Ok(())
+            };
+        }
+    };
 
-    let client = format!("UDP {}", src);
+    let res = try!(command(udp, src, Some(id.as_str()), payload)).unwrap_or(String::new()).into_bytes();
 
-    try!(match handler::handle(ctx, client.as_str(), command.as_str(), obj.as_str()) {
-        Ok(result) => send(socket, src, result),
-        Err(e) => send(socket, src, e.description_json())
+    udp.cache.lock().unwrap().insert(src, id.clone(), Some(res.clone()));
+    send_chunks(&udp.socket, src, id.as_str(), res.as_slice())
+}
+
+/*
+ * Queue a datagram for the workers, unless it is a duplicate of a previous request
+ */
+fn datagram(udp: &Arc<Udp>, pool: &Pool, src: SocketAddr, buf: Vec<u8>) -> Result<()> {
+    let id = frame::parse_request(buf.as_slice()).map(|(id, _)| id);
+
+    if let Some(ref id) = id {
+        let mut cache = udp.cache.lock().unwrap();
+
+        match cache.get(src, id.as_str()) {
+            // The client did not receive the whole response, send it again
+            Some(Some(res)) => return send_chunks(&udp.socket, src, id.as_str(), res.as_slice()),
+
+            // Still being handled, the response is sent once ready
+            Some(None) => return Ok(()),
+
+            None => cache.insert(src, id.clone(), None)
+        };
+    }
+
+    let request = InProgress {
+        udp: udp.clone(),
+        src: src,
+        id: id.clone()
+    };
+    let queued = pool.execute(move || {
+        match respond(request.udp.as_ref(), src, buf.as_slice()) {
+            Ok(_) => {},
+            Err(e) => error!("udp", "failed to execute command: {}", e; client = src)
+        };
     });
 
-    Ok(())
+    if queued {
+        return Ok(());
+    }
+
+    // Every worker is busy, clients can send the request again later
+    warn!("udp", "too many pending commands, request rejected"; client = src);
+    let res = Error::busy("Too many pending commands, try again later").description_json();
+
+    // The rejected task was dropped, along with its entry in the cache
+    match id {
+        Some(id) => send_chunks(&udp.socket, src, id.as_str(), res.as_bytes()),
+        None => send(&udp.socket, src, format!("{}\n", res).as_bytes())
+    }
 }
 
 pub fn run(ctx: Arc<Context>) {
-    let ctx = ctx.as_ref();
-
-    // 1024 bytes buffer
-    let mut buf = vec![0; 1024];
+    let mut buf = vec![0; frame::MAX_DATAGRAM];
 
-    let addr = match ctx.conf.udp {
-        Some(ref udp) => udp.addr.clone(),
+    let (addr, workers, queue) = match ctx.conf.udp {
+        Some(ref udp) => (udp.addr.clone(), udp.workers, udp.queue),
         None => {
-            println!("Please specify a UDP listen address in configuration");
+            error!("udp", "please specify a UDP listen address in configuration");
             process::exit(1);
         }
     };
@@ -62,23 +260,31 @@ pub fn run(ctx: Arc<Context>) {
     let socket = match UdpSocket::bind(addr.as_str()) {
         Ok(s) => s,
         Err(e) => {
-            println!("Failed to bind socket: {}", e);
+            error!("udp", "failed to bind socket: {}", e; addr = addr);
             process::exit(1);
         }
     };
 
-    println!("Waiting for commands on UDP {}...", addr);
+    info!("udp", "waiting for commands"; addr = addr);
+
+    let pool = Pool::new(workers, queue);
+    let udp = Arc::new(Udp {
+        ctx: ctx,
+        socket: socket,
+        cache: Mutex::new(Cache::new()),
+        nonces: auth::Nonces::new()
+    });
 
-    // Read all the UDP packets and parse their content
+    // Read all the UDP packets and hand them to the workers
     loop {
-        match socket.recv_from(&mut buf) {
+        match udp.socket.recv_from(&mut buf) {
             Ok((len, src)) => {
-                match command(ctx, &socket, src, buf[..len].to_vec()) {
+                match datagram(&udp, &pool, src, buf[..len].to_vec()) {
                     Ok(_) => {},
-                    Err(e) => println!("Failed to execute command: {}", e)
+                    Err(e) => error!("udp", "failed to execute command: {}", e; client = src)
                 }
             },
-            Err(e) => println!("{}", e)
+            Err(e) => error!("udp", "failed to receive datagram: {}", e)
         };
     }
 }
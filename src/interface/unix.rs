@@ -0,0 +1,241 @@
+/*
+ * Unix interface - Read commands from a local Unix stream socket
+ * Each line sent by the client is a command, answered by a single line
+ * The 'subscribe' command turns the connection into a stream of events
+ */
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use libc;
+use serde_json;
+
+use common::{Context, Result, Error};
+use auth::Identity;
+use events;
+use handler;
+use utils::system;
+
+/*
+ * Credentials of the process connected to the socket
+ */
+struct Credentials {
+    pid: i32,
+    uid: u32,
+    gid: u32
+}
+
+fn peer_credentials(socket: &UnixStream) -> Result<Credentials> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void, &mut len)
+    };
+
+    if ret != 0 {
+        return Err(Error::new(format!("Failed to read peer credentials: {}", io::Error::last_os_error())));
+    }
+
+    Ok(Credentials {
+        pid: cred.pid,
+        uid: cred.uid,
+        gid: cred.gid
+    })
+}
+
+/*
+ * Check if the peer is allowed to use the socket, only using the credentials it connected with
+ * Root is always allowed, other users must be listed by uid or by one of their groups
+ */
+fn allowed(ctx: &Context, cred: &Credentials) -> bool {
+    let conf = match ctx.conf.unix {
+        Some(ref unix) => unix,
+        None => return false
+    };
+
+    if cred.uid == 0 || conf.uids.contains(&cred.uid) || conf.gids.contains(&cred.gid) {
+        return true;
+    }
+
+    if conf.gids.len() == 0 {
+        return false;
+    }
+
+    // SO_PEERCRED only gives the primary group, look up the other groups of the user
+    match system::user_groups(cred.uid) {
+        Ok(groups) => groups.iter().any(|gid| conf.gids.contains(gid)),
+        Err(e) => {
+            warn!("unix", "failed to read the groups of the peer: {}", e; uid = cred.uid);
+            false
+        }
+    }
+}
+
+fn send(socket: &mut UnixStream, mut buf: String) -> Result<()> {
+    buf.push('\n');
+
+    match socket.write_all(buf.as_bytes()) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::new(format!("Failed to write to Unix socket: {}", e)))
+    }
+}
+
+/*
+ * Main client loop
+ */
+fn client(ctx: &Context, mut socket: UnixStream) -> Result<()> {
+    let cred = try!(peer_credentials(&socket));
+    let client = format!("Unix pid={} uid={}", cred.pid, cred.uid);
+
+    if !allowed(ctx, &cred) {
+        warn!("unix", "connection refused"; client = client);
+        return send(&mut socket, Error::permission_denied("Access denied").description_json());
+    }
+
+    let role = match ctx.conf.unix {
+        Some(ref unix) => unix.role.clone(),
+        None => String::new()
+    };
+    let identity = Identity::with_role(ctx, format!("uid {}", cred.uid).as_str(), role.as_str());
+
+    let reader = BufReader::new(try!(socket.try_clone()));
+    for line in reader.lines() {
+        let (command, obj) = super::parse_command(try!(line));
+
+        // Ignore empty commands
+        if command.len() == 0 {
+            continue;
+        }
+
+        // Events are sent as JSON lines until the client disconnects, with empty lines as keepalives
+        if command.as_str() == "subscribe" {
+            if let Err(e) = handler::authorize(client.as_str(), &identity, command.as_str()) {
+                try!(send(&mut socket, e.description_json()));
+                continue;
+            }
+
+            info!("unix", "subscribed to events"; client = client, types = obj);
+
+            events::stream(ctx, events::parse_kinds(obj.as_str()), |event| match event {
+                Some(event) => send(&mut socket, try!(serde_json::to_string(event))),
+                None => send(&mut socket, String::new())
+            });
+
+            return Ok(());
+        }
+
+        try!(match handler::handle(ctx, client.as_str(), &identity, command.as_str(), obj.as_str()) {
+            Ok(result) => send(&mut socket, result),
+            Err(e) => send(&mut socket, e.description_json())
+        });
+    }
+
+    Ok(())
+}
+
+/*
+ * Bind the socket in a private directory and move it in place once its permissions are set,
+ * so that nobody can connect in between (the umask is shared with the other threads)
+ */
+fn bind(path: &str, mode: u32) -> Result<UnixListener> {
+    let dir = format!("{}.bind", path);
+    let tmp = format!("{}/socket", dir);
+
+    // Left over by a previous instance stopped while binding, only removed if empty
+    let _ = fs::remove_file(tmp.as_str());
+    let _ = fs::remove_dir(dir.as_str());
+
+    if let Err(e) = fs::DirBuilder::new().mode(0o700).create(dir.as_str()) {
+        return Err(Error::new(format!("Failed to create {}: {}", dir, e)));
+    }
+
+    let res = match UnixListener::bind(tmp.as_str()) {
+        Ok(listener) => {
+            let moved = fs::set_permissions(tmp.as_str(), fs::Permissions::from_mode(mode))
+                .and_then(|_| fs::rename(tmp.as_str(), path));
+
+            match moved {
+                Ok(_) => Ok(listener),
+                Err(e) => Err(Error::new(format!("Failed to set up the socket: {}", e)))
+            }
+        },
+        Err(e) => Err(Error::new(format!("Failed to bind socket: {}", e)))
+    };
+
+    let _ = fs::remove_file(tmp.as_str());
+    let _ = fs::remove_dir(dir.as_str());
+
+    res
+}
+
+pub fn run(ctx: Arc<Context>) {
+    let (path, mode) = match ctx.conf.unix {
+        Some(ref unix) => (unix.path.clone(), unix.mode.clone()),
+        None => {
+            error!("unix", "please specify a Unix socket path in configuration");
+            process::exit(1);
+        }
+    };
+
+    let mode = match u32::from_str_radix(mode.as_str(), 8) {
+        Ok(mode) => mode,
+        Err(_) => {
+            error!("unix", "invalid Unix socket mode '{}', expected an octal number", mode);
+            process::exit(1);
+        }
+    };
+
+    // Remove the socket left over by a previous instance, never anything else
+    if let Ok(metadata) = fs::symlink_metadata(path.as_str()) {
+        if !metadata.file_type().is_socket() {
+            error!("unix", "refusing to replace an existing file that is not a socket"; path = path);
+            process::exit(1);
+        }
+
+        if let Err(e) = fs::remove_file(path.as_str()) {
+            error!("unix", "failed to remove the previous socket: {}", e; path = path);
+            process::exit(1);
+        }
+    }
+
+    // Bind a listen socket
+    let listener = match bind(path.as_str(), mode) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("unix", "{}", e; path = path);
+            process::exit(1);
+        }
+    };
+
+    info!("unix", "waiting for commands"; path = path);
+
+    // Process all client connections
+    for socket in listener.incoming() {
+        match socket {
+            Ok(socket) => {
+                let ctx = ctx.clone();
+
+                thread::spawn(move || {
+                    match client(ctx.as_ref(), socket) {
+                        Ok(_) => {},
+                        Err(e) => warn!("unix", "{}", e)
+                    };
+                });
+            },
+            Err(e) => error!("unix", "failed to accept connection: {}", e)
+        };
+    }
+}
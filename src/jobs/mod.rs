@@ -0,0 +1,341 @@
+/*
+ * Jobs - Run long commands in the background
+ *
+ * Jobs are stored in the database when they are submitted, then picked up by a pool of worker threads
+ * When the server restarts, queued jobs are resumed and the ones that were running are marked as failed
+ * Finished jobs are removed every PRUNE_INTERVAL seconds once older than the retention period
+ */
+
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use uuid::{Uuid, UuidVersion};
+
+use common::{Context, Result, Error, ErrorKind};
+use common::structs::{Job, JobState};
+use audit;
+use events::{self, Event};
+use database;
+use handler;
+
+/*
+ * Delay between two removals of the old jobs, in seconds
+ */
+const PRUNE_INTERVAL: u64 = 3600;
+
+/*
+ * Jobs waiting for a worker, and cancellation flags of the running ones
+ */
+pub struct Queue {
+    pending: Mutex<VecDeque<String>>,
+    available: Condvar,
+    running: Mutex<HashMap<String, Arc<AtomicBool>>> // Also serializes the state changes of queued jobs
+}
+
+impl Queue {
+    pub fn new() -> Queue {
+        Queue {
+            pending: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            running: Mutex::new(HashMap::new())
+        }
+    }
+
+    fn push(&self, id: &str) {
+        self.pending.lock().unwrap().push_back(id.to_string());
+        self.available.notify_one();
+    }
+
+    /*
+     * Wait for the next job to run
+     */
+    fn pop(&self) -> String {
+        let mut pending = self.pending.lock().unwrap();
+
+        loop {
+            if let Some(id) = pending.pop_front() {
+                return id;
+            }
+
+            pending = self.available.wait(pending).unwrap();
+        }
+    }
+}
+
+/*
+ * Given to the commands running as a job, to report their progress and notice cancellations
+ */
+pub struct Handle<'a> {
+    ctx: &'a Context,
+    id: String,
+    cancelled: Arc<AtomicBool>,
+    progress: Cell<u32>
+}
+
+impl<'a> Handle<'a> {
+    /*
+     * Record the progress of the job, as a percentage
+     */
+    pub fn progress(&self, percent: u32) {
+        let percent = percent.min(100);
+        if percent == self.progress.get() {
+            return;
+        }
+
+        self.progress.set(percent);
+
+        let res = database::job::get(self.ctx, self.id.as_str()).and_then(|mut job| {
+            job.progress = percent;
+            database::job::update(self.ctx, &job)
+        });
+
+        if let Err(e) = res {
+            warn!("jobs", "failed to record progress: {}", e; job = self.id);
+        }
+    }
+
+    /*
+     * Fail if the job was cancelled, commands call this between their steps
+     */
+    pub fn check(&self) -> Result<()> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(Error::cancelled("The job was cancelled"));
+        }
+
+        Ok(())
+    }
+}
+
+fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(_) => 0
+    }
+}
+
+/*
+ * Store a new job and queue it
+ */
+pub fn submit(ctx: &Context, client: &str, identity: &str, cmd: &str, arg: &str) -> Result<Job> {
+    let job = Job {
+        id: Uuid::new(UuidVersion::Random).unwrap().hyphenated().to_string(),
+        node: ctx.conf.global.node,
+        command: cmd.to_string(),
+        argument: arg.to_string(),
+        client: client.to_string(),
+        identity: identity.to_string(),
+        state: JobState::Queued,
+        progress: 0,
+        result: String::new(),
+        error: String::new(),
+        code: String::new(),
+        created: now(),
+        started: 0,
+        finished: 0
+    };
+
+    try!(database::job::create(ctx, &job));
+    ctx.jobs.push(job.id.as_str());
+
+    Ok(job)
+}
+
+/*
+ * Cancel a job
+ * Queued jobs are cancelled right away, running ones stop at the next step of their command
+ */
+pub fn cancel(ctx: &Context, id: &str) -> Result<Job> {
+    let running = ctx.jobs.running.lock().unwrap();
+    let mut job = try!(database::job::get(ctx, id));
+
+    match job.state {
+        JobState::Queued => {
+            job.state = JobState::Cancelled;
+            job.error = String::from("The job was cancelled");
+            job.code = ErrorKind::Cancelled.code().to_string();
+            job.finished = now();
+
+            try!(database::job::update(ctx, &job));
+        },
+        JobState::Running => {
+            if let Some(cancelled) = running.get(id) {
+                cancelled.store(true, Ordering::SeqCst);
+            }
+        },
+        _ => return Err(Error::invalid_state(format!("Job '{}' is already {}", id, job.state.as_str())))
+    };
+
+    Ok(job)
+}
+
+/*
+ * Mark a queued job as running, unless it was cancelled in the meantime
+ */
+fn start(ctx: &Context, id: &str) -> Result<Option<(Job, Arc<AtomicBool>)>> {
+    let mut running = ctx.jobs.running.lock().unwrap();
+    let mut job = try!(database::job::get(ctx, id));
+
+    if job.state != JobState::Queued {
+        return Ok(None);
+    }
+
+    job.state = JobState::Running;
+    job.started = now();
+    try!(database::job::update(ctx, &job));
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    running.insert(job.id.clone(), cancelled.clone());
+
+    Ok(Some((job, cancelled)))
+}
+
+/*
+ * Run a job and record its outcome
+ */
+fn execute(ctx: &Context, id: &str) {
+    let (mut job, cancelled) = match start(ctx, id) {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(e) => return error!("jobs", "failed to start job: {}", e; job = id)
+    };
+
+    let handle = Handle {
+        ctx: ctx,
+        id: job.id.clone(),
+        cancelled: cancelled,
+        progress: Cell::new(0)
+    };
+
+    let started = Instant::now();
+    let res = handler::run(ctx, &handle, job.command.as_str(), job.argument.as_str());
+    ctx.jobs.running.lock().unwrap().remove(id);
+
+    let duration = started.elapsed();
+    audit::record(ctx, job.client.as_str(), job.identity.as_str(), job.command.as_str(), job.argument.as_str(), job.id.as_str(), duration, &res);
+    ctx.metrics.command(job.command.as_str(), duration, res.is_ok());
+
+    job.progress = handle.progress.get();
+    job.finished = now();
+
+    match res {
+        Ok(s) => {
+            info!("jobs", "command executed successfully"; client = job.client, command = job.command, job = job.id);
+
+            job.state = JobState::Done;
+            job.progress = 100;
+            job.result = s;
+        },
+        Err(e) => {
+            error!("jobs", "command failed: {}", e; client = job.client, command = job.command, argument = audit::redact(job.argument.as_str()), job = job.id);
+
+            job.state = match e.kind() {
+                ErrorKind::Cancelled => JobState::Cancelled,
+                _ => JobState::Failed
+            };
+            job.error = e.to_string();
+            job.code = e.kind().code().to_string();
+        }
+    };
+
+    if let Err(e) = database::job::update(ctx, &job) {
+        error!("jobs", "failed to record the result: {}", e; job = job.id);
+    }
+
+    events::publish(ctx, Event::new(format!("job.{}", job.state.as_str()).as_str(), job.id.as_str(), json!({
+        "command": job.command,
+        "error": job.error
+    })));
+}
+
+/*
+ * Resume the jobs left over by the previous run of the server
+ */
+fn recover(ctx: &Context) -> Result<()> {
+    for mut job in try!(database::job::list(ctx)) {
+        match job.state {
+            JobState::Queued => ctx.jobs.push(job.id.as_str()),
+            JobState::Running => {
+                // Whatever the command did is unknown, do not run it again
+                job.state = JobState::Failed;
+                job.error = String::from("Interrupted by a restart of the server");
+                job.code = ErrorKind::Internal.code().to_string();
+                job.finished = now();
+
+                try!(database::job::update(ctx, &job));
+            },
+            _ => {}
+        };
+    }
+
+    Ok(())
+}
+
+/*
+ * Forget the jobs finished for longer than the retention period
+ */
+fn prune(ctx: &Context) -> Result<()> {
+    let limit = now() - ctx.conf.jobs.retention as i64;
+
+    for job in try!(database::job::list(ctx)) {
+        if job.state.is_finished() && job.finished < limit {
+            try!(database::job::delete(ctx, job.id.as_str()));
+        }
+    }
+
+    Ok(())
+}
+
+/*
+ * Run the queued jobs in the calling thread, until the queue is empty
+ */
+#[cfg(test)]
+pub fn run_queued(ctx: &Context) {
+    loop {
+        let id = ctx.jobs.pending.lock().unwrap().pop_front();
+
+        match id {
+            Some(id) => execute(ctx, id.as_str()),
+            None => break
+        };
+    }
+}
+
+/*
+ * Start the worker threads
+ */
+pub fn run(ctx: Arc<Context>) {
+    if let Err(e) = recover(ctx.as_ref()) {
+        error!("jobs", "failed to resume jobs: {}", e);
+    }
+
+    for _ in 0..ctx.conf.jobs.workers.max(1) {
+        let ctx = ctx.clone();
+
+        thread::spawn(move || {
+            loop {
+                let id = ctx.jobs.pop();
+                execute(ctx.as_ref(), id.as_str());
+            }
+        });
+    }
+
+    thread::spawn(move || {
+        loop {
+            if let Err(e) = prune(ctx.as_ref()) {
+                warn!("jobs", "failed to remove old jobs: {}", e);
+            }
+
+            thread::sleep(Duration::from_secs(PRUNE_INTERVAL));
+        }
+    });
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,124 @@
+use std::cell::Cell;
+
+use common::{Context, ErrorKind};
+use common::structs::{Job, JobState};
+use database;
+
+use super::{Handle, submit, cancel, start, recover, prune, run_queued, now};
+
+fn context() -> Context {
+    Context::test(r#"
+        backend = []
+
+        [global]
+        node = 1
+
+        [database]
+        type = "memory"
+
+        [jobs]
+        retention = 60
+    "#)
+}
+
+/*
+ * Store a job directly, as left over by a previous run of the server
+ */
+fn job(ctx: &Context, id: &str, state: JobState, finished: i64) {
+    let job = Job::from_json(format!(r#"{{"id": "{}", "node": 1, "command": "startvm", "argument": "none", "state": "{}", "finished": {}}}"#, id, state.as_str(), finished).as_str()).unwrap();
+    database::job::create(ctx, &job).unwrap();
+}
+
+fn get(ctx: &Context, id: &str) -> Job {
+    database::job::get(ctx, id).unwrap()
+}
+
+#[test]
+fn submit_and_run() {
+    let ctx = context();
+
+    let job = submit(&ctx, "test", "anonymous", "startvm", "none").unwrap();
+    assert_eq!(get(&ctx, job.id.as_str()).state, JobState::Queued);
+
+    run_queued(&ctx);
+
+    // The VM does not exist
+    let job = get(&ctx, job.id.as_str());
+    assert_eq!(job.state, JobState::Failed);
+    assert_eq!(job.code.as_str(), "not_found");
+    assert!(job.started > 0 && job.finished >= job.started);
+}
+
+#[test]
+fn cancel_queued() {
+    let ctx = context();
+
+    let job = submit(&ctx, "test", "anonymous", "startvm", "none").unwrap();
+    assert_eq!(cancel(&ctx, job.id.as_str()).unwrap().state, JobState::Cancelled);
+
+    // Cancelled jobs are not run
+    run_queued(&ctx);
+    let job = get(&ctx, job.id.as_str());
+
+    assert_eq!(job.state, JobState::Cancelled);
+    assert_eq!(job.started, 0);
+
+    // Finished jobs can not be cancelled
+    assert_eq!(cancel(&ctx, job.id.as_str()).unwrap_err().kind(), ErrorKind::InvalidState);
+}
+
+#[test]
+fn cancel_running() {
+    let ctx = context();
+
+    let job = submit(&ctx, "test", "anonymous", "startvm", "none").unwrap();
+    let (job, cancelled) = start(&ctx, job.id.as_str()).unwrap().unwrap();
+
+    let handle = Handle {
+        ctx: &ctx,
+        id: job.id.clone(),
+        cancelled: cancelled,
+        progress: Cell::new(0)
+    };
+
+    handle.progress(40);
+    assert_eq!(get(&ctx, job.id.as_str()).progress, 40);
+    assert!(handle.check().is_ok());
+
+    // Running jobs are only flagged, the command notices it at its next step
+    assert_eq!(cancel(&ctx, job.id.as_str()).unwrap().state, JobState::Running);
+    assert_eq!(handle.check().unwrap_err().kind(), ErrorKind::Cancelled);
+}
+
+#[test]
+fn recover_after_restart() {
+    let ctx = context();
+    job(&ctx, "queued", JobState::Queued, 0);
+    job(&ctx, "running", JobState::Running, 0);
+    job(&ctx, "done", JobState::Done, now());
+
+    recover(&ctx).unwrap();
+
+    // Interrupted jobs are not run again
+    assert_eq!(get(&ctx, "running").state, JobState::Failed);
+    assert_eq!(get(&ctx, "done").state, JobState::Done);
+
+    // Queued jobs are resumed
+    run_queued(&ctx);
+    assert_eq!(get(&ctx, "queued").state, JobState::Failed);
+    assert_eq!(get(&ctx, "queued").code.as_str(), "not_found");
+}
+
+#[test]
+fn prune_old_jobs() {
+    let ctx = context();
+    job(&ctx, "old", JobState::Done, now() - 120);
+    job(&ctx, "recent", JobState::Failed, now() - 30);
+    job(&ctx, "queued", JobState::Queued, 0);
+
+    prune(&ctx).unwrap();
+
+    assert_eq!(database::job::get(&ctx, "old").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(get(&ctx, "recent").state, JobState::Failed);
+    assert_eq!(get(&ctx, "queued").state, JobState::Queued);
+}
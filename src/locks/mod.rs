@@ -0,0 +1,88 @@
+/*
+ * Locks - Serialize the operations on the same object
+ * Objects are identified by their type and name, for example ("vm", "test")
+ */
+
+use std::collections::HashSet;
+use std::sync::{Mutex, Condvar};
+
+pub struct Locks {
+    held: Mutex<HashSet<(String, String)>>,
+    released: Condvar
+}
+
+/*
+ * A held lock, released when dropped
+ */
+pub struct Guard<'a> {
+    locks: &'a Locks,
+    key: (String, String)
+}
+
+impl Locks {
+    pub fn new() -> Locks {
+        Locks {
+            held: Mutex::new(HashSet::new()),
+            released: Condvar::new()
+        }
+    }
+
+    /*
+     * Lock an object, waiting for the current holder to release it
+     */
+    pub fn lock(&self, kind: &str, name: &str) -> Guard {
+        let key = (kind.to_string(), name.to_string());
+        let mut held = self.held.lock().unwrap();
+
+        while held.contains(&key) {
+            held = self.released.wait(held).unwrap();
+        }
+
+        held.insert(key.clone());
+
+        Guard {
+            locks: self,
+            key: key
+        }
+    }
+
+    /*
+     * Lock an object if it is not locked already
+     */
+    pub fn try_lock(&self, kind: &str, name: &str) -> Option<Guard> {
+        let key = (kind.to_string(), name.to_string());
+        let mut held = self.held.lock().unwrap();
+
+        if held.contains(&key) {
+            return None;
+        }
+
+        held.insert(key.clone());
+
+        Some(Guard {
+            locks: self,
+            key: key
+        })
+    }
+
+    /*
+     * Check if an object is locked
+     */
+    #[cfg(test)]
+    pub fn is_locked(&self, kind: &str, name: &str) -> bool {
+        self.held.lock().unwrap().contains(&(kind.to_string(), name.to_string()))
+    }
+}
+
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        self.locks.held.lock().unwrap().remove(&self.key);
+        self.locks.released.notify_all();
+    }
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
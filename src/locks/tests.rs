@@ -0,0 +1,61 @@
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use super::Locks;
+
+#[test]
+fn lock_and_release() {
+    let locks = Locks::new();
+
+    {
+        let _guard = locks.lock("vm", "test");
+        assert!(locks.is_locked("vm", "test"));
+
+        // Other objects are independent
+        assert!(!locks.is_locked("vm", "other"));
+        assert!(!locks.is_locked("image", "test"));
+        let _other = locks.lock("image", "test");
+    }
+
+    assert!(!locks.is_locked("vm", "test"));
+    assert!(!locks.is_locked("image", "test"));
+}
+
+#[test]
+fn try_lock() {
+    let locks = Locks::new();
+
+    let guard = locks.try_lock("vm", "test");
+    assert!(guard.is_some());
+    assert!(locks.try_lock("vm", "test").is_none());
+
+    drop(guard);
+    assert!(locks.try_lock("vm", "test").is_some());
+}
+
+#[test]
+fn lock_waits_for_holder() {
+    let locks = Arc::new(Locks::new());
+    let guard = locks.lock("vm", "test");
+
+    let (tx, rx) = mpsc::channel();
+    let waiter = {
+        let locks = locks.clone();
+
+        thread::spawn(move || {
+            let _guard = locks.lock("vm", "test");
+            tx.send(()).unwrap();
+        })
+    };
+
+    // The second lock is only taken once the first one is released
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+    drop(guard);
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    waiter.join().unwrap();
+
+    assert!(!locks.is_locked("vm", "test"));
+}
@@ -0,0 +1,98 @@
+/*
+ * File sink - Append the messages to a file, rotated when it grows too large
+ * Rotated files are named <path>.1 (the most recent one) to <path>.<keep>, older ones are removed
+ */
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use common::Result;
+
+use super::{Record, Sink};
+
+pub struct File {
+    path: String,
+    max_size: u64,
+    keep: usize,
+    json: bool,
+    file: Mutex<Option<(fs::File, u64)>> // Open file, and its size
+}
+
+impl File {
+    pub fn new(path: &str, max_size: u64, keep: usize, json: bool) -> Result<File> {
+        let file = File {
+            path: path.to_string(),
+            max_size: max_size,
+            keep: keep,
+            json: json,
+            file: Mutex::new(None)
+        };
+
+        // Fail right away if the file can not be written to
+        let opened = try!(file.open());
+        *file.file.lock().unwrap() = Some(opened);
+
+        Ok(file)
+    }
+
+    fn open(&self) -> io::Result<(fs::File, u64)> {
+        let file = try!(OpenOptions::new().create(true).append(true).open(&self.path));
+        let size = try!(file.metadata()).len();
+
+        Ok((file, size))
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        for i in (1..self.keep).rev() {
+            let from = format!("{}.{}", self.path, i);
+
+            if Path::new(&from).exists() {
+                try!(fs::rename(&from, format!("{}.{}", self.path, i + 1)));
+            }
+        }
+
+        fs::rename(&self.path, format!("{}.1", self.path))
+    }
+
+    fn append(&self, file: &mut Option<(fs::File, u64)>, line: &[u8]) -> io::Result<()> {
+        if file.is_none() {
+            *file = Some(try!(self.open()));
+        }
+
+        let full = match *file {
+            Some((ref mut f, ref mut size)) => {
+                try!(f.write_all(line));
+                *size += line.len() as u64;
+
+                self.max_size > 0 && *size >= self.max_size
+            },
+            None => false
+        };
+
+        // The next message opens a new file
+        if full {
+            *file = None;
+            try!(self.rotate());
+        }
+
+        Ok(())
+    }
+}
+
+impl Sink for File {
+    fn write(&self, record: &Record) {
+        let line = format!("{}\n", if self.json { record.json() } else { record.text() });
+        let mut file = self.file.lock().unwrap();
+
+        if let Err(e) = self.append(&mut file, line.as_bytes()) {
+            *file = None;
+            eprintln!("[log]: failed to write to {}: {}", self.path, e);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+/*
+ * Log - Leveled messages with key/value fields, written to the sinks configured in the [log] section
+ *
+ * Messages are logged with the error!, warn!, info! and debug! macros. They take the module logging
+ * the message (its target), a format string and its arguments, then the fields after a semicolon:
+ *
+ *     info!("supervisor", "restarting VM (attempt {})", attempt; vm = vm.name);
+ *
+ * Each target can be given its own level. Until the logger is installed, messages down to the
+ * info level are written to stderr.
+ */
+
+mod file;
+mod syslog;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Map;
+use serde_json::value::Value;
+
+use common::{Result, Error};
+use config;
+
+use self::file::File;
+use self::syslog::{Syslog, Journald};
+
+macro_rules! log {
+    ($level:expr, $target:expr, $fmt:expr $(, $arg:expr)* ; $($key:ident = $value:expr),+) => {
+        $crate::log::write($level, $target, &[$((stringify!($key), &$value as &::std::fmt::Display)),+], format_args!($fmt $(, $arg)*))
+    };
+    ($level:expr, $target:expr, $fmt:expr $(, $arg:expr)*) => {
+        $crate::log::write($level, $target, &[], format_args!($fmt $(, $arg)*))
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => (log!($crate::log::Level::Error, $($arg)+))
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => (log!($crate::log::Level::Warn, $($arg)+))
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => (log!($crate::log::Level::Info, $($arg)+))
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => (log!($crate::log::Level::Debug, $($arg)+))
+}
+
+/*
+ * Levels, from the most to the least important
+ */
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug
+}
+
+impl Level {
+    pub fn parse(s: &str) -> Result<Level> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(Error::invalid_argument(format!("Invalid log level '{}', expected error, warn, info or debug", s)))
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug"
+        }
+    }
+
+    /*
+     * Severity as defined by syslog
+     */
+    fn severity(&self) -> u8 {
+        match *self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug => 7
+        }
+    }
+}
+
+/*
+ * A message, along with its fields
+ */
+pub struct Record<'a> {
+    pub time: Duration, // Since the Unix epoch
+    pub level: Level,
+    pub target: &'a str,
+    pub message: String,
+    pub fields: Vec<(&'a str, String)>
+}
+
+/*
+ * Quote the values that could not be told apart from the next field otherwise
+ */
+fn quote(value: &str) -> String {
+    if value.len() == 0 || value.contains(|c: char| c == ' ' || c == '"' || c == '=' || c.is_control()) {
+        format!("{:?}", value)
+    }
+    else {
+        value.to_string()
+    }
+}
+
+/*
+ * Format a time as RFC 3339, in UTC
+ */
+pub fn timestamp(time: Duration) -> String {
+    let secs = time.as_secs();
+
+    // Civil date from the number of days since the epoch, with years starting in March
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let s = secs % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day, s / 3600, s / 60 % 60, s % 60, time.subsec_nanos() / 1000000)
+}
+
+impl<'a> Record<'a> {
+    /*
+     * Target, message and fields: 'supervisor: VM crashed vm=web'
+     */
+    pub fn line(&self) -> String {
+        let mut line = format!("{}: {}", self.target, self.message);
+
+        for &(key, ref value) in &self.fields {
+            line.push_str(format!(" {}={}", key, quote(value.as_str())).as_str());
+        }
+
+        line
+    }
+
+    /*
+     * Line prefixed with the time and the level
+     */
+    pub fn text(&self) -> String {
+        format!("{} {:<5} {}", timestamp(self.time), self.level.as_str().to_uppercase(), self.line())
+    }
+
+    /*
+     * JSON object, with the fields next to the message
+     */
+    pub fn json(&self) -> String {
+        let mut obj = Map::new();
+
+        obj.insert(String::from("time"), Value::String(timestamp(self.time)));
+        obj.insert(String::from("level"), Value::String(self.level.as_str().to_string()));
+        obj.insert(String::from("target"), Value::String(self.target.to_string()));
+        obj.insert(String::from("message"), Value::String(self.message.clone()));
+
+        for &(key, ref value) in &self.fields {
+            obj.insert(key.to_string(), Value::String(value.clone()));
+        }
+
+        Value::Object(obj).to_string()
+    }
+}
+
+/*
+ * Destination of the messages
+ * Sinks can not report their errors through the logger, they write them to stderr instead
+ */
+pub trait Sink: Send + Sync {
+    fn write(&self, record: &Record);
+}
+
+struct Stderr {
+    json: bool
+}
+
+impl Sink for Stderr {
+    fn write(&self, record: &Record) {
+        eprintln!("{}", if self.json { record.json() } else { record.text() });
+    }
+}
+
+/*
+ * Create a sink from its configuration
+ */
+fn open(conf: &config::LogSink) -> Result<Box<Sink>> {
+    let json = match conf.format.as_str() {
+        "text" => false,
+        "json" => true,
+        f => return Err(Error::invalid_argument(format!("Invalid log format '{}', expected text or json", f)))
+    };
+
+    match conf.kind.as_str() {
+        "stderr" => Ok(Box::new(Stderr { json: json })),
+        "file" => match conf.path {
+            Some(ref path) => Ok(Box::new(try!(File::new(path.as_str(), conf.max_size, conf.keep, json)))),
+            None => Err(Error::invalid_argument("The file log sink needs a path"))
+        },
+        "syslog" => Ok(Box::new(try!(Syslog::new(conf.path.as_ref().map(|p| p.as_str()).unwrap_or(syslog::SYSLOG_PATH))))),
+        "journald" => Ok(Box::new(try!(Journald::new(conf.path.as_ref().map(|p| p.as_str()).unwrap_or(syslog::JOURNALD_PATH))))),
+        k => Err(Error::invalid_argument(format!("Invalid log sink '{}', expected stderr, file, syslog or journald", k)))
+    }
+}
+
+pub struct Logger {
+    level: Level,
+    modules: HashMap<String, Level>, // Levels of specific targets
+    sinks: Vec<Box<Sink>>
+}
+
+impl Logger {
+    pub fn new(conf: &config::Log) -> Result<Logger> {
+        let mut modules = HashMap::new();
+        for (target, level) in &conf.modules {
+            modules.insert(target.clone(), try!(Level::parse(level.as_str())));
+        }
+
+        let mut sinks = Vec::new();
+        for sink in &conf.sink {
+            sinks.push(try!(open(sink)));
+        }
+
+        Ok(Logger {
+            level: try!(Level::parse(conf.level.as_str())),
+            modules: modules,
+            sinks: sinks
+        })
+    }
+
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        level <= *self.modules.get(target).unwrap_or(&self.level)
+    }
+
+    pub fn log(&self, record: &Record) {
+        for sink in &self.sinks {
+            sink.write(record);
+        }
+    }
+}
+
+/*
+ * Address of the installed logger, which lives until the program exits
+ */
+static LOGGER: AtomicUsize = AtomicUsize::new(0);
+
+fn logger() -> Option<&'static Logger> {
+    match LOGGER.load(Ordering::SeqCst) {
+        0 => None,
+        ptr => Some(unsafe { &*(ptr as *const Logger) })
+    }
+}
+
+/*
+ * Install the logger configured in the [log] section, can only be done once
+ */
+pub fn init(conf: &config::Log) -> Result<()> {
+    let ptr = Box::into_raw(Box::new(try!(Logger::new(conf)))) as usize;
+
+    if LOGGER.compare_exchange(0, ptr, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        drop(unsafe { Box::from_raw(ptr as *mut Logger) });
+        return Err(Error::new("The logger is already installed"));
+    }
+
+    Ok(())
+}
+
+/*
+ * Log a message, called by the macros
+ */
+pub fn write(level: Level, target: &str, fields: &[(&str, &fmt::Display)], args: fmt::Arguments) {
+    let logger = logger();
+
+    let enabled = match logger {
+        Some(logger) => logger.enabled(level, target),
+        None => level <= Level::Info
+    };
+
+    if !enabled {
+        return;
+    }
+
+    let record = Record {
+        time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0)),
+        level: level,
+        target: target,
+        message: fmt::format(args),
+        fields: fields.iter().map(|&(key, value)| (key, value.to_string())).collect()
+    };
+
+    match logger {
+        Some(logger) => logger.log(&record),
+        None => eprintln!("{}", record.text())
+    };
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
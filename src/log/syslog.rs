@@ -0,0 +1,118 @@
+/*
+ * Syslog and journald sinks - Send the messages to the local daemons, over their datagram sockets
+ * Messages that can not be sent are written to stderr instead, so that they are not lost
+ */
+
+use std::os::unix::net::UnixDatagram;
+use std::process;
+
+use common::Result;
+
+use super::{Record, Sink};
+
+pub const SYSLOG_PATH: &'static str = "/dev/log";
+pub const JOURNALD_PATH: &'static str = "/run/systemd/journal/socket";
+
+const IDENTIFIER: &'static str = "olvm";
+
+/*
+ * Messages are sent with the daemon facility
+ */
+const FACILITY: u8 = 3;
+
+pub struct Syslog {
+    path: String,
+    socket: UnixDatagram
+}
+
+impl Syslog {
+    pub fn new(path: &str) -> Result<Syslog> {
+        Ok(Syslog {
+            path: path.to_string(),
+            socket: try!(UnixDatagram::unbound())
+        })
+    }
+}
+
+/*
+ * Encode a record in the BSD syslog format used on the local socket: '<priority>olvm[pid]: message'
+ */
+pub fn syslog_message(record: &Record) -> String {
+    format!("<{}>{}[{}]: {}", FACILITY * 8 + record.level.severity(), IDENTIFIER, process::id(), record.line())
+}
+
+impl Sink for Syslog {
+    fn write(&self, record: &Record) {
+        if let Err(e) = self.socket.send_to(syslog_message(record).as_bytes(), &self.path) {
+            eprintln!("{} (syslog: {})", record.text(), e);
+        }
+    }
+}
+
+pub struct Journald {
+    path: String,
+    socket: UnixDatagram
+}
+
+impl Journald {
+    pub fn new(path: &str) -> Result<Journald> {
+        Ok(Journald {
+            path: path.to_string(),
+            socket: try!(UnixDatagram::unbound())
+        })
+    }
+}
+
+/*
+ * Journal field names are made of uppercase letters, digits and underscores
+ */
+fn field_name(key: &str) -> String {
+    key.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect()
+}
+
+fn field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+
+    // Values spanning several lines are prefixed with their length instead
+    if value.contains('\n') {
+        entry.push(b'\n');
+
+        let len = value.len() as u64;
+        for i in 0..8 {
+            entry.push((len >> (i * 8)) as u8);
+        }
+    }
+    else {
+        entry.push(b'=');
+    }
+
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/*
+ * Encode a record in the native journal protocol, the fields of the record become journal fields
+ * that can be matched on: 'journalctl SYSLOG_IDENTIFIER=olvm VM=web'
+ */
+pub fn journal_entry(record: &Record) -> Vec<u8> {
+    let mut entry = Vec::new();
+
+    field(&mut entry, "MESSAGE", record.line().as_str());
+    field(&mut entry, "PRIORITY", record.level.severity().to_string().as_str());
+    field(&mut entry, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    field(&mut entry, "OLVM_TARGET", record.target);
+
+    for &(key, ref value) in &record.fields {
+        field(&mut entry, field_name(key).as_str(), value.as_str());
+    }
+
+    entry
+}
+
+impl Sink for Journald {
+    fn write(&self, record: &Record) {
+        if let Err(e) = self.socket.send_to(&journal_entry(record), &self.path) {
+            eprintln!("{} (journald: {})", record.text(), e);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use serde_json;
+use serde_json::value::Value;
+
+use config;
+
+use super::{Level, Logger, Record, Sink, timestamp};
+use super::file;
+use super::syslog::{self, syslog_message, journal_entry};
+
+fn record<'a>(level: Level, message: &str, fields: Vec<(&'a str, &str)>) -> Record<'a> {
+    Record {
+        time: Duration::new(1500000000, 123000000),
+        level: level,
+        target: "supervisor",
+        message: message.to_string(),
+        fields: fields.into_iter().map(|(k, v)| (k, v.to_string())).collect()
+    }
+}
+
+fn conf(level: &str, modules: &[(&str, &str)], sink: &str) -> config::Log {
+    let mut map = HashMap::new();
+    for &(target, level) in modules {
+        map.insert(target.to_string(), level.to_string());
+    }
+
+    config::Log {
+        level: level.to_string(),
+        modules: map,
+        sink: vec![config::LogSink {
+            kind: sink.to_string(),
+            format: String::from("text"),
+            path: None,
+            max_size: 0,
+            keep: 0
+        }]
+    }
+}
+
+fn read(path: &Path) -> String {
+    let mut s = String::new();
+    File::open(path).unwrap().read_to_string(&mut s).unwrap();
+    s
+}
+
+#[test]
+fn levels() {
+    assert_eq!(Level::parse("warn").unwrap(), Level::Warn);
+    assert!(Level::parse("verbose").is_err());
+
+    let logger = Logger::new(&conf("warn", &[("dhcp", "debug"), ("http", "error")], "stderr")).unwrap();
+
+    assert!(logger.enabled(Level::Error, "supervisor"));
+    assert!(logger.enabled(Level::Warn, "supervisor"));
+    assert!(!logger.enabled(Level::Info, "supervisor"));
+
+    // Targets can be more or less verbose than the others
+    assert!(logger.enabled(Level::Debug, "dhcp"));
+    assert!(!logger.enabled(Level::Warn, "http"));
+}
+
+#[test]
+fn invalid_configuration() {
+    assert!(Logger::new(&conf("loud", &[], "stderr")).is_err());
+    assert!(Logger::new(&conf("info", &[("dhcp", "loud")], "stderr")).is_err());
+    assert!(Logger::new(&conf("info", &[], "printer")).is_err());
+
+    // Files need a path
+    assert!(Logger::new(&conf("info", &[], "file")).is_err());
+}
+
+#[test]
+fn timestamps() {
+    assert_eq!(timestamp(Duration::new(0, 0)).as_str(), "1970-01-01T00:00:00.000Z");
+    assert_eq!(timestamp(Duration::new(951782400, 0)).as_str(), "2000-02-29T00:00:00.000Z");
+    assert_eq!(timestamp(Duration::new(1500000000, 123000000)).as_str(), "2017-07-14T02:40:00.123Z");
+}
+
+#[test]
+fn text_format() {
+    let r = record(Level::Warn, "failed to restart VM", vec![("vm", "web"), ("error", "No such file"), ("client", "")]);
+
+    assert_eq!(r.text().as_str(), "2017-07-14T02:40:00.123Z WARN  supervisor: failed to restart VM vm=web error=\"No such file\" client=\"\"");
+}
+
+#[test]
+fn json_format() {
+    let r = record(Level::Info, "VM started", vec![("vm", "web")]);
+    let json: Value = serde_json::from_str(r.json().as_str()).unwrap();
+
+    assert_eq!(json["time"].as_str(), Some("2017-07-14T02:40:00.123Z"));
+    assert_eq!(json["level"].as_str(), Some("info"));
+    assert_eq!(json["target"].as_str(), Some("supervisor"));
+    assert_eq!(json["message"].as_str(), Some("VM started"));
+    assert_eq!(json["vm"].as_str(), Some("web"));
+}
+
+#[test]
+fn file_rotation() {
+    let dir = env::temp_dir().join(format!("olvm-test-{}-log", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("olvm.log");
+    let sink = file::File::new(path.to_str().unwrap(), 100, 2, false).unwrap();
+
+    // Each line is over half the maximum size, the file is rotated after every two lines
+    for i in 0..7 {
+        sink.write(&record(Level::Info, format!("message {}", i).as_str(), vec![("vm", "web")]));
+    }
+
+    let current = read(&path);
+    let last = read(&dir.join("olvm.log.1"));
+    let previous = read(&dir.join("olvm.log.2"));
+    let _ = fs::remove_dir_all(&dir);
+
+    assert_eq!(current.lines().count(), 1);
+    assert!(current.contains("message 6 vm=web"));
+    assert!(last.contains("message 4") && last.contains("message 5"));
+    assert!(previous.contains("message 2") && previous.contains("message 3"));
+    assert!(!dir.join("olvm.log.3").exists());
+}
+
+#[test]
+fn syslog_format() {
+    let msg = syslog_message(&record(Level::Error, "VM crashed", vec![("vm", "web")]));
+    assert_eq!(msg, format!("<27>olvm[{}]: supervisor: VM crashed vm=web", process::id()));
+
+    let path = env::temp_dir().join(format!("olvm-test-{}-syslog.sock", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let server = UnixDatagram::bind(&path).unwrap();
+    let sink = syslog::Syslog::new(path.to_str().unwrap()).unwrap();
+    sink.write(&record(Level::Info, "VM started", vec![]));
+
+    let mut buf = [0; 1024];
+    let n = server.recv(&mut buf).unwrap();
+    let _ = fs::remove_file(&path);
+
+    assert!(String::from_utf8_lossy(&buf[..n]).starts_with("<30>olvm["));
+}
+
+#[test]
+fn journal_fields() {
+    let entry = journal_entry(&record(Level::Warn, "script failed", vec![("vm", "web"), ("stderr", "line 1\nline 2")]));
+
+    let mut expected = Vec::new();
+    expected.extend_from_slice(b"MESSAGE=supervisor: script failed vm=web stderr=\"line 1\\nline 2\"\n");
+    expected.extend_from_slice(b"PRIORITY=4\nSYSLOG_IDENTIFIER=olvm\nOLVM_TARGET=supervisor\nVM=web\n");
+
+    // Multi-line values are sent with their length
+    expected.extend_from_slice(b"STDERR\n");
+    expected.extend_from_slice(&[13, 0, 0, 0, 0, 0, 0, 0]);
+    expected.extend_from_slice(b"line 1\nline 2\n");
+
+    assert_eq!(entry, expected);
+}
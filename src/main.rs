@@ -1,3 +1,4 @@
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
@@ -13,16 +14,30 @@ extern crate regex;
 extern crate toml;
 extern crate dhcp;
 extern crate mhttp;
+extern crate crypto;
+extern crate openssl;
+extern crate libc;
 
+#[macro_use]
+mod log;
 mod utils;
 mod common;
 mod config;
+mod auth;
+mod audit;
 mod database;
 mod interface;
 mod backend;
 mod remote;
 mod handler;
 mod net;
+mod events;
+mod supervisor;
+mod jobs;
+mod locks;
+mod webhooks;
+mod metrics;
+mod stats;
 
 use std::thread;
 use std::sync::Arc;
@@ -32,16 +47,22 @@ fn main() {
     let conf = match config::open("olvm.conf") {
         Ok(conf) => conf,
         Err(e) => {
-            println!("Failed to load configuration: {}", e);
+            error!("main", "failed to load configuration: {}", e);
             return;
         }
     };
 
+    // Send the messages to the configured sinks
+    if let Err(e) = log::init(&conf.log) {
+        error!("main", "failed to setup logging: {}", e);
+        return;
+    }
+
     // Open connection to the database
-    let db = match database::open(conf.database.host.as_str(), conf.database.port) {
+    let db = match database::open(&conf) {
         Ok(db) => db,
         Err(e) => {
-            println!("Failed to connect to database: {}", e);
+            error!("main", "failed to connect to database: {}", e);
             return;
         }
     };
@@ -49,29 +70,69 @@ fn main() {
     // Create global context, shared everywhere
     let ctx = Arc::new(common::Context {
         conf: conf,
-        db: db
+        db: db,
+        runner: Box::new(utils::exec::Process),
+        jobs: jobs::Queue::new(),
+        locks: locks::Locks::new(),
+        events: events::Bus::new(),
+        metrics: metrics::Metrics::new(),
+        stats: stats::Sampler::new()
     });
 
+    // States left behind by a previous run, before any command is accepted
+    if let Err(e) = supervisor::recover(ctx.as_ref()) {
+        error!("main", "failed to recover VM states: {}", e);
+    }
+
+    // Start the job workers
+    jobs::run(ctx.clone());
+
+    // Send the events to the webhook targets
+    webhooks::run(ctx.clone());
+
     let rctx = ctx.clone();
 
     // Setup networking (virtual devices, dhcp server...)
     thread::spawn(move || {
         match net::setup(rctx) {
             Ok(_) => {},
-            Err(e) => println!("Failed to setup networking: {}", e)
+            Err(e) => error!("main", "failed to setup networking: {}", e)
         };
     });
 
+    // Watch the running VMs
+    let rctx = ctx.clone();
+    thread::spawn(move || supervisor::run(rctx));
+
+    // Sample the resource usage of the running VMs
+    let rctx = ctx.clone();
+    thread::spawn(move || stats::run(rctx));
+
+    // Apply the retention of the audit log
+    let rctx = ctx.clone();
+    thread::spawn(move || audit::run(rctx));
+
     // Start the chosen interfaces
+    let mut interfaces = Vec::new();
+
     if ctx.conf.http.is_some() {
         let rctx = ctx.clone();
-        thread::spawn(move || interface::http::run(rctx));
+        interfaces.push(thread::spawn(move || interface::http::run(rctx)));
+    }
+    if ctx.conf.unix.is_some() {
+        let rctx = ctx.clone();
+        interfaces.push(thread::spawn(move || interface::unix::run(rctx)));
     }
     if ctx.conf.udp.is_some() {
         let rctx = ctx.clone();
-        interface::udp::run(rctx);
+        interfaces.push(thread::spawn(move || interface::udp::run(rctx)));
     }
     /*else {
         interface::stdin::run(&ctx);
     }*/
+
+    // Interfaces run until the program is stopped
+    for i in interfaces {
+        let _ = i.join();
+    }
 }
@@ -0,0 +1,334 @@
+/*
+ * Metrics - Count what the daemon does, and expose it in the Prometheus text format
+ * along with the resource usage of the host and of the running VMs
+ */
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use common::{Context, Result};
+use common::structs::{VM, State};
+use database;
+use handler;
+use net;
+use utils::system;
+
+pub const CONTENT_TYPE: &'static str = "text/plain; version=0.0.4";
+
+/*
+ * Upper bounds of the duration histograms, in seconds
+ */
+const BUCKETS: &'static [f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+const STATES: &'static [State] = &[
+    State::Defined, State::Creating, State::Stopped, State::Starting, State::Running,
+    State::Stopping, State::Migrating, State::Crashed, State::Error, State::Unknown
+];
+
+struct Histogram {
+    buckets: Vec<u64>, // Observations in each bucket, not cumulative
+    sum: f64,
+    count: u64
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: vec![0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = BUCKETS.iter().position(|b| value <= *b) {
+            self.buckets[i] += 1;
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/*
+ * Calls of a command, or of a backend script
+ */
+struct Calls {
+    successes: u64,
+    failures: u64,
+    duration: Histogram
+}
+
+impl Calls {
+    fn new() -> Calls {
+        Calls {
+            successes: 0,
+            failures: 0,
+            duration: Histogram::new()
+        }
+    }
+
+    fn record(&mut self, duration: Duration, success: bool) {
+        if success {
+            self.successes += 1;
+        }
+        else {
+            self.failures += 1;
+        }
+
+        self.duration.observe(seconds(duration));
+    }
+}
+
+pub struct Metrics {
+    commands: Mutex<BTreeMap<String, Calls>>,
+    scripts: Mutex<BTreeMap<(String, String), Calls>>, // By backend and script
+    dhcp: Mutex<BTreeMap<String, u64>> // Responses sent, by type
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            commands: Mutex::new(BTreeMap::new()),
+            scripts: Mutex::new(BTreeMap::new()),
+            dhcp: Mutex::new(BTreeMap::new())
+        }
+    }
+
+    /*
+     * Count a command handled, jobs are counted when they end
+     * Unknown commands are counted together, so that clients can not create new series
+     */
+    pub fn command(&self, cmd: &str, duration: Duration, success: bool) {
+        let cmd = if handler::is_command(cmd) { cmd } else { "unknown" };
+        let mut commands = self.commands.lock().unwrap();
+        commands.entry(cmd.to_string()).or_insert_with(Calls::new).record(duration, success);
+    }
+
+    /*
+     * Count a backend script execution
+     */
+    pub fn script(&self, backend: &str, script: &str, duration: Duration, success: bool) {
+        let mut scripts = self.scripts.lock().unwrap();
+        scripts.entry((backend.to_string(), script.to_string())).or_insert_with(Calls::new).record(duration, success);
+    }
+
+    /*
+     * Count a DHCP response sent to a VM ("offer" or "ack")
+     */
+    pub fn dhcp(&self, kind: &str) {
+        *self.dhcp.lock().unwrap().entry(kind.to_string()).or_insert(0) += 1;
+    }
+}
+
+/*
+ * Prometheus text format output
+ */
+struct Writer {
+    out: String
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl Writer {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        self.out.push_str(format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind).as_str());
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let labels: Vec<String> = labels.iter().map(|&(k, v)| format!("{}=\"{}\"", k, escape(v))).collect();
+
+        if labels.len() > 0 {
+            self.out.push_str(format!("{}{{{}}} {}\n", name, labels.join(","), value).as_str());
+        }
+        else {
+            self.out.push_str(format!("{} {}\n", name, value).as_str());
+        }
+    }
+
+    fn histogram(&mut self, name: &str, labels: &[(&str, &str)], h: &Histogram) {
+        let mut cumulative = 0;
+
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            cumulative += h.buckets[i];
+
+            let le = bound.to_string();
+            let mut l = labels.to_vec();
+            l.push(("le", le.as_str()));
+
+            self.sample(format!("{}_bucket", name).as_str(), &l, cumulative as f64);
+        }
+
+        let mut l = labels.to_vec();
+        l.push(("le", "+Inf"));
+
+        self.sample(format!("{}_bucket", name).as_str(), &l, h.count as f64);
+        self.sample(format!("{}_sum", name).as_str(), labels, h.sum);
+        self.sample(format!("{}_count", name).as_str(), labels, h.count as f64);
+    }
+}
+
+/*
+ * Resource usage of a running VM
+ */
+struct Usage {
+    process: Option<system::ProcessStats>,
+    interfaces: Vec<(String, system::InterfaceStats)> // By TAP device
+}
+
+fn usage(vm: &VM) -> Usage {
+    let pid = match vm.state {
+        State::Running => vm.parameters.get("pid").and_then(|pid| pid.parse::<u32>().ok()),
+        _ => None
+    };
+
+    let mut interfaces = Vec::new();
+    for i in 0..vm.interfaces.len() {
+        let dev = net::iface_dev(vm.name.as_str(), i as i32);
+
+        if let Ok(stats) = system::interface_stats(dev.as_str()) {
+            interfaces.push((dev, stats));
+        }
+    }
+
+    Usage {
+        process: pid.and_then(|pid| system::process_stats(pid).ok()),
+        interfaces: interfaces
+    }
+}
+
+fn host(ctx: &Context, w: &mut Writer) -> Result<()> {
+    let host = try!(ctx.stats.host(ctx));
+    let node = ctx.conf.global.node.to_string();
+
+    w.family("olvm_node_info", "gauge", "Node ID of the server");
+    w.sample("olvm_node_info", &[("node", node.as_str())], 1.0);
+
+    w.family("olvm_host_memory_used_bytes", "gauge", "Memory used on the host, without buffers and caches");
+    w.sample("olvm_host_memory_used_bytes", &[], (host.mem_usage as f64 * 1048576.0).round());
+
+    w.family("olvm_host_memory_total_bytes", "gauge", "Memory installed on the host");
+    w.sample("olvm_host_memory_total_bytes", &[], (host.mem_total as f64 * 1048576.0).round());
+
+    w.family("olvm_host_cpu_usage_percent", "gauge", "Share of the CPU time of the host spent in user, system and stolen time");
+    w.sample("olvm_host_cpu_usage_percent", &[], host.cpu_usage as f64);
+
+    Ok(())
+}
+
+fn vms(ctx: &Context, w: &mut Writer) -> Result<()> {
+    let vms = try!(database::vm::list(ctx));
+    let usages: Vec<Usage> = vms.iter().map(usage).collect();
+
+    w.family("olvm_vm_state", "gauge", "State of the VM, 1 for the current one");
+    for vm in &vms {
+        for state in STATES {
+            let value = if vm.state == *state { 1.0 } else { 0.0 };
+            w.sample("olvm_vm_state", &[("vm", vm.name.as_str()), ("backend", vm.backend.as_str()), ("state", state.as_str())], value);
+        }
+    }
+
+    w.family("olvm_vm_uptime_seconds", "gauge", "Time since the VM process started");
+    for (vm, usage) in vms.iter().zip(usages.iter()) {
+        if let Some(ref p) = usage.process {
+            w.sample("olvm_vm_uptime_seconds", &[("vm", vm.name.as_str())], p.uptime);
+        }
+    }
+
+    w.family("olvm_vm_cpu_seconds_total", "counter", "CPU time used by the VM process");
+    for (vm, usage) in vms.iter().zip(usages.iter()) {
+        if let Some(ref p) = usage.process {
+            w.sample("olvm_vm_cpu_seconds_total", &[("vm", vm.name.as_str())], p.cpu_time);
+        }
+    }
+
+    w.family("olvm_vm_memory_rss_bytes", "gauge", "Resident memory of the VM process");
+    for (vm, usage) in vms.iter().zip(usages.iter()) {
+        if let Some(ref p) = usage.process {
+            w.sample("olvm_vm_memory_rss_bytes", &[("vm", vm.name.as_str())], p.rss as f64);
+        }
+    }
+
+    // Counters of the TAP devices: bytes received by the host were sent by the VM
+    w.family("olvm_vm_network_receive_bytes_total", "counter", "Bytes received by the host on the TAP interface of the VM");
+    for (vm, usage) in vms.iter().zip(usages.iter()) {
+        for &(ref dev, ref stats) in &usage.interfaces {
+            w.sample("olvm_vm_network_receive_bytes_total", &[("vm", vm.name.as_str()), ("interface", dev.as_str())], stats.rx_bytes as f64);
+        }
+    }
+
+    w.family("olvm_vm_network_transmit_bytes_total", "counter", "Bytes transmitted by the host on the TAP interface of the VM");
+    for (vm, usage) in vms.iter().zip(usages.iter()) {
+        for &(ref dev, ref stats) in &usage.interfaces {
+            w.sample("olvm_vm_network_transmit_bytes_total", &[("vm", vm.name.as_str()), ("interface", dev.as_str())], stats.tx_bytes as f64);
+        }
+    }
+
+    Ok(())
+}
+
+fn daemon(ctx: &Context, w: &mut Writer) {
+    {
+        let commands = ctx.metrics.commands.lock().unwrap();
+
+        w.family("olvm_commands_total", "counter", "Commands handled, by result");
+        for (cmd, calls) in commands.iter() {
+            w.sample("olvm_commands_total", &[("command", cmd.as_str()), ("result", "success")], calls.successes as f64);
+            w.sample("olvm_commands_total", &[("command", cmd.as_str()), ("result", "failure")], calls.failures as f64);
+        }
+
+        w.family("olvm_command_duration_seconds", "histogram", "Time taken by the commands, until the end of their job");
+        for (cmd, calls) in commands.iter() {
+            w.histogram("olvm_command_duration_seconds", &[("command", cmd.as_str())], &calls.duration);
+        }
+    }
+
+    {
+        let scripts = ctx.metrics.scripts.lock().unwrap();
+
+        w.family("olvm_backend_script_failures_total", "counter", "Backend scripts that failed or could not be run");
+        for (&(ref backend, ref script), calls) in scripts.iter() {
+            w.sample("olvm_backend_script_failures_total", &[("backend", backend.as_str()), ("script", script.as_str())], calls.failures as f64);
+        }
+
+        w.family("olvm_backend_script_duration_seconds", "histogram", "Time taken by the backend scripts");
+        for (&(ref backend, ref script), calls) in scripts.iter() {
+            w.histogram("olvm_backend_script_duration_seconds", &[("backend", backend.as_str()), ("script", script.as_str())], &calls.duration);
+        }
+    }
+
+    let dhcp = ctx.metrics.dhcp.lock().unwrap();
+
+    w.family("olvm_dhcp_responses_total", "counter", "DHCP responses sent to the VMs, by type");
+    for (kind, count) in dhcp.iter() {
+        w.sample("olvm_dhcp_responses_total", &[("type", kind.as_str())], *count as f64);
+    }
+}
+
+/*
+ * Handle a 'metrics' command
+ */
+pub fn render(ctx: &Context) -> Result<String> {
+    let mut w = Writer {
+        out: String::new()
+    };
+
+    try!(host(ctx, &mut w));
+    try!(vms(ctx, &mut w));
+    daemon(ctx, &mut w);
+
+    Ok(w.out)
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
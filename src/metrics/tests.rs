@@ -0,0 +1,95 @@
+use std::process;
+use std::time::Duration;
+
+use common::Context;
+use common::structs::VM;
+use database;
+
+use super::{escape, render};
+
+fn context() -> Context {
+    Context::test(r#"
+        backend = []
+
+        [global]
+        node = 3
+
+        [database]
+        type = "memory"
+    "#)
+}
+
+fn has(out: &str, line: &str) -> bool {
+    out.lines().any(|l| l == line)
+}
+
+#[test]
+fn labels() {
+    assert_eq!(escape("a\"b\\c\nd").as_str(), "a\\\"b\\\\c\\nd");
+}
+
+#[test]
+fn daemon_counters() {
+    let ctx = context();
+
+    ctx.metrics.command("startvm", Duration::from_millis(30), true);
+    ctx.metrics.command("startvm", Duration::from_millis(2000), false);
+    ctx.metrics.script("kvm", "vm_start", Duration::from_millis(1500), false);
+    ctx.metrics.dhcp("offer");
+    ctx.metrics.dhcp("offer");
+    ctx.metrics.dhcp("ack");
+
+    // Whatever the clients send
+    ctx.metrics.command("nope", Duration::from_millis(1), false);
+    ctx.metrics.command("nope\"}", Duration::from_millis(1), false);
+
+    let out = render(&ctx).unwrap();
+
+    assert!(has(&out, "olvm_node_info{node=\"3\"} 1"));
+
+    assert!(has(&out, "# TYPE olvm_commands_total counter"));
+    assert!(has(&out, "olvm_commands_total{command=\"startvm\",result=\"success\"} 1"));
+    assert!(has(&out, "olvm_commands_total{command=\"startvm\",result=\"failure\"} 1"));
+    assert!(has(&out, "olvm_commands_total{command=\"unknown\",result=\"failure\"} 2"));
+    assert!(!out.contains("nope"));
+
+    // Buckets are cumulative
+    assert!(has(&out, "# TYPE olvm_command_duration_seconds histogram"));
+    assert!(has(&out, "olvm_command_duration_seconds_bucket{command=\"startvm\",le=\"0.025\"} 0"));
+    assert!(has(&out, "olvm_command_duration_seconds_bucket{command=\"startvm\",le=\"0.05\"} 1"));
+    assert!(has(&out, "olvm_command_duration_seconds_bucket{command=\"startvm\",le=\"2.5\"} 2"));
+    assert!(has(&out, "olvm_command_duration_seconds_bucket{command=\"startvm\",le=\"+Inf\"} 2"));
+    assert!(has(&out, "olvm_command_duration_seconds_sum{command=\"startvm\"} 2.03"));
+    assert!(has(&out, "olvm_command_duration_seconds_count{command=\"startvm\"} 2"));
+
+    assert!(has(&out, "olvm_backend_script_failures_total{backend=\"kvm\",script=\"vm_start\"} 1"));
+    assert!(has(&out, "olvm_backend_script_duration_seconds_count{backend=\"kvm\",script=\"vm_start\"} 1"));
+
+    assert!(has(&out, "olvm_dhcp_responses_total{type=\"ack\"} 1"));
+    assert!(has(&out, "olvm_dhcp_responses_total{type=\"offer\"} 2"));
+}
+
+#[test]
+fn vm_usage() {
+    let ctx = context();
+
+    let stopped = VM::from_json(r#"{"name": "db", "backend": "kvm", "state": "stopped"}"#).unwrap();
+    database::vm::create(&ctx, &stopped).unwrap();
+
+    // The test process stands in for the VM process
+    let running = VM::from_json(format!(r#"{{"name": "web", "backend": "kvm", "state": "running", "parameters": {{"pid": "{}"}}}}"#, process::id()).as_str()).unwrap();
+    database::vm::create(&ctx, &running).unwrap();
+
+    let out = render(&ctx).unwrap();
+
+    assert!(has(&out, "olvm_vm_state{vm=\"db\",backend=\"kvm\",state=\"stopped\"} 1"));
+    assert!(has(&out, "olvm_vm_state{vm=\"db\",backend=\"kvm\",state=\"running\"} 0"));
+    assert!(has(&out, "olvm_vm_state{vm=\"web\",backend=\"kvm\",state=\"running\"} 1"));
+
+    assert!(out.contains("olvm_vm_uptime_seconds{vm=\"web\"}"));
+    assert!(out.contains("olvm_vm_cpu_seconds_total{vm=\"web\"}"));
+    assert!(out.contains("olvm_vm_memory_rss_bytes{vm=\"web\"}"));
+
+    // Only the VMs with a process have process metrics
+    assert!(!out.contains("olvm_vm_memory_rss_bytes{vm=\"db\"}"));
+}
@@ -22,7 +22,7 @@ pub fn listen(ctx: Arc<Context>) -> Result<()> {
 
     try!(socket.set_broadcast(true));
 
-    println!("DHCP server started...");
+    info!("dhcp", "DHCP server started");
 
     // Forever
     loop {
@@ -36,7 +36,7 @@ pub fn listen(ctx: Arc<Context>) -> Result<()> {
                 match Frame::parse(&buf[..len]) {
                     Ok(frame) => handle(ctx.clone(), &socket, frame),
                     Err(e) => {
-                        println!("Failed to parse DHCP frame: {}", e);
+                        warn!("dhcp", "failed to parse DHCP frame: {}", e);
                         continue;
                     }
                 };
@@ -59,7 +59,7 @@ fn handle(ctx: Arc<Context>, socket: &UdpSocket, req: Frame) {
     let net = match database::network::get(ctx.as_ref(), iface.network.as_str()) {
         Ok(net) => net,
         Err(e) => {
-            println!("Failed to find network: {}", e);
+            error!("dhcp", "failed to find network: {}", e; vm = vm.name, network = iface.network);
             return;
         }
     };
@@ -68,7 +68,7 @@ fn handle(ctx: Arc<Context>, socket: &UdpSocket, req: Frame) {
     let ip = match Ipv4Addr::from_str(iface.ip.as_str()) {
         Ok(ip) => ip,
         Err(_) => {
-            println!("Failed to parse IP: {}", iface.ip);
+            error!("dhcp", "invalid IP address"; vm = vm.name, ip = iface.ip);
             return;
         }
     };
@@ -82,7 +82,7 @@ fn handle(ctx: Arc<Context>, socket: &UdpSocket, req: Frame) {
             data[0]
         },
         None => {
-            println!("Invalid DHCP request: missing OPTION_DHCP_MSG_TYPE");
+            warn!("dhcp", "invalid DHCP request: missing OPTION_DHCP_MSG_TYPE"; vm = vm.name);
             return;
         }
     };
@@ -103,7 +103,7 @@ fn handle(ctx: Arc<Context>, socket: &UdpSocket, req: Frame) {
             t
         },
         _ => {
-            println!("Invalid DHCP request: invalid OPTION_DHCP_MSG_TYPE: {}", req_type);
+            warn!("dhcp", "invalid DHCP request: invalid OPTION_DHCP_MSG_TYPE {}", req_type; vm = vm.name);
             return;
         }
     };
@@ -126,7 +126,7 @@ fn handle(ctx: Arc<Context>, socket: &UdpSocket, req: Frame) {
                     resp.add_option(router);
                 },
                 Err(_) => {
-                    println!("Failed to parse IP: {}", iface.ip);
+                    error!("dhcp", "invalid router address"; network = net.name, router = net.router);
                     return;
                 }
             };
@@ -144,7 +144,7 @@ fn handle(ctx: Arc<Context>, socket: &UdpSocket, req: Frame) {
                     resp.add_option(router);
                 },
                 Err(_) => {
-                    println!("Failed to parse IP: {}", iface.ip);
+                    error!("dhcp", "invalid DNS server address"; network = net.name, dns = net.dns[0]);
                     return;
                 }
             };
@@ -156,7 +156,7 @@ fn handle(ctx: Arc<Context>, socket: &UdpSocket, req: Frame) {
     match lease.set_data_u32(86400) {
         Ok(_) => resp.add_option(lease),
         Err(e) => {
-            println!("Failed to construct DHCP response: {}", e);
+            error!("dhcp", "failed to construct DHCP response: {}", e; vm = vm.name);
             return;
         }
     };
@@ -169,13 +169,19 @@ fn handle(ctx: Arc<Context>, socket: &UdpSocket, req: Frame) {
             let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255)), 68);
             let data = buf.as_slice();
 
+            // Offers answer discovers, and acknowledgements answer requests
+            let kind = if req_type == codes::DHCP_DISCOVER { "offer" } else { "ack" };
+
             match socket.send_to(data, addr) {
-                Ok(_) => {},
-                Err(e) => println!("Failed to send DHCP response: {}", e)
+                Ok(_) => {
+                    ctx.metrics.dhcp(kind);
+                    debug!("dhcp", "sent DHCP {}", kind; vm = vm.name, ip = iface.ip)
+                },
+                Err(e) => error!("dhcp", "failed to send DHCP response: {}", e; vm = vm.name)
             };
         },
         Err(e) => {
-            println!("Failed to construct DHCP response: {}", e)
+            error!("dhcp", "failed to construct DHCP response: {}", e; vm = vm.name)
         }
     };
 }
@@ -12,16 +12,17 @@ use regex::Regex;
 
 use common::{Context, Result};
 use database;
+use supervisor;
 
 /*
- * Setup the networking module: create network interfaces, start DHCP server
+ * Setup the networking module: create network interfaces, start the autostart VMs and the DHCP server
  */
 pub fn setup(ctx: Arc<Context>) -> Result<()> {
     let nets = try!(database::network::list(ctx.as_ref()));
 
     for net in nets {
         let netdev = net_dev(net.name.as_str());
-        try!(system::bridge_create(netdev.as_str()));
+        try!(system::bridge_create(ctx.as_ref(), netdev.as_str()));
     }
 
     let vms = try!(database::vm::list(ctx.as_ref()));
@@ -30,12 +31,17 @@ pub fn setup(ctx: Arc<Context>) -> Result<()> {
         let mut index = 0;
         for _ in vm.interfaces {
             let tap = iface_dev(vm.name.as_str(), index);
-            try!(system::tap_create(tap.as_str()));
+            try!(system::tap_create(ctx.as_ref(), tap.as_str()));
 
             index = index + 1;
         }
     }
 
+    // Interfaces are ready, start the VMs marked as autostart
+    if let Err(e) = supervisor::autostart(ctx.as_ref()) {
+        error!("net", "failed to start VMs: {}", e);
+    }
+
     dhcp::listen(ctx)
 }
 
@@ -72,8 +78,6 @@ pub fn is_valid_cidr(cidr: &str) -> bool {
         None => return false
     };
 
-    println!("{}", &cidr[..index]);
-
     is_valid_ip(&cidr[..index])
 }
 
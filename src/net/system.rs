@@ -2,38 +2,24 @@
  * Network System - OS actions to manage networking
  */
 
-use std::process::Command;
-
-use common::{Result, Error};
+use common::{Context, Result, Error};
 
 /*
  * Create a bridge interface
  */
-pub fn bridge_create(name: &str) -> Result<()> {
-    let exists = try!(Command::new("ip").arg("link").arg("show").arg(name).output());
-    if !exists.status.success() {
-        let out = try!(Command::new("ip")
-            .arg("link").arg("add").arg(name)
-            .arg("type").arg("bridge").output());
-
-        if !out.status.success() {
-            let err = match String::from_utf8(out.stderr) {
-                Ok(err) => err,
-                Err(_) => return Err(Error::new("Failed to read 'ip' output as a string"))
-            };
-
-            return Err(Error::new(format!("Failed to create bridge: {}", err)));
+pub fn bridge_create(ctx: &Context, name: &str) -> Result<()> {
+    let exists = try!(ctx.runner.exec("ip", &["link", "show", name]));
+    if !exists.success {
+        let out = try!(ctx.runner.exec("ip", &["link", "add", name, "type", "bridge"]));
+
+        if !out.success {
+            return Err(Error::network(format!("Failed to create bridge: {}", out.stderr)));
         }
     }
 
-    let up = try!(Command::new("ip").arg("link").arg("set").arg("up").arg("dev").arg(name).output());
-    if !up.status.success() {
-        let err = match String::from_utf8(up.stderr) {
-            Ok(err) => err,
-            Err(_) => return Err(Error::new("Failed to read 'ip' output as a string"))
-        };
-
-        return Err(Error::new(format!("Failed to set up bridge: {}", err)));
+    let up = try!(ctx.runner.exec("ip", &["link", "set", "up", "dev", name]));
+    if !up.success {
+        return Err(Error::network(format!("Failed to set up bridge: {}", up.stderr)));
     }
 
     Ok(())
@@ -42,18 +28,11 @@ pub fn bridge_create(name: &str) -> Result<()> {
 /*
  * Add a network interface to a bridge
  */
-pub fn bridge_addif(iface: &str, bridge: &str) -> Result<()> {
-    let out = try!(Command::new("ip")
-        .arg("link").arg("set").arg("master").arg(bridge)
-        .arg("dev").arg(iface).output());
-
-    if !out.status.success() {
-        let err = match String::from_utf8(out.stderr) {
-            Ok(err) => err,
-            Err(_) => return Err(Error::new("Failed to read 'ip' output as a string"))
-        };
-
-        return Err(Error::new(format!("Failed to add '{}' to '{}' bridge: {}", iface, bridge, err)));
+pub fn bridge_addif(ctx: &Context, iface: &str, bridge: &str) -> Result<()> {
+    let out = try!(ctx.runner.exec("ip", &["link", "set", "master", bridge, "dev", iface]));
+
+    if !out.success {
+        return Err(Error::network(format!("Failed to add '{}' to '{}' bridge: {}", iface, bridge, out.stderr)));
     }
 
     Ok(())
@@ -62,16 +41,11 @@ pub fn bridge_addif(iface: &str, bridge: &str) -> Result<()> {
 /*
  * Delete a bridge interface
  */
-pub fn bridge_delete(name: &str) -> Result<()> {
-    let out = try!(Command::new("ip").arg("link").arg("del").arg(name).output());
+pub fn bridge_delete(ctx: &Context, name: &str) -> Result<()> {
+    let out = try!(ctx.runner.exec("ip", &["link", "del", name]));
 
-    if !out.status.success() {
-        let err = match String::from_utf8(out.stderr) {
-            Ok(err) => err,
-            Err(_) => return Err(Error::new("Failed to read 'ip' output as a string"))
-        };
-
-        return Err(Error::new(format!("Failed to delete bridge: {}", err)));
+    if !out.success {
+        return Err(Error::network(format!("Failed to delete bridge: {}", out.stderr)));
     }
 
     Ok(())
@@ -80,26 +54,14 @@ pub fn bridge_delete(name: &str) -> Result<()> {
 /*
  * Create a TAP interface
  */
-pub fn tap_create(name: &str) -> Result<()> {
-    let exists = try!(Command::new("ip").arg("tuntap").arg("show").arg(name).output());
-
-    let stdout = match String::from_utf8(exists.stdout) {
-        Ok(stdout) => stdout,
-        Err(_) => return Err(Error::new("Failed to read stdout as a string"))
-    };
-
-    if !exists.status.success() || !stdout.contains(name) {
-        let out = try!(Command::new("ip")
-            .arg("tuntap").arg("add").arg(name)
-            .arg("mode").arg("tap").output());
-
-        if !out.status.success() {
-            let err = match String::from_utf8(out.stderr) {
-                Ok(err) => err,
-                Err(_) => return Err(Error::new("Failed to read 'ip' output as a string"))
-            };
-
-            return Err(Error::new(format!("Failed to create TAP: {}", err)));
+pub fn tap_create(ctx: &Context, name: &str) -> Result<()> {
+    let exists = try!(ctx.runner.exec("ip", &["tuntap", "show", name]));
+
+    if !exists.success || !exists.stdout.contains(name) {
+        let out = try!(ctx.runner.exec("ip", &["tuntap", "add", name, "mode", "tap"]));
+
+        if !out.success {
+            return Err(Error::network(format!("Failed to create TAP: {}", out.stderr)));
         }
     }
 
@@ -109,18 +71,11 @@ pub fn tap_create(name: &str) -> Result<()> {
 /*
  * Delete a TAP interface
  */
-pub fn tap_delete(name: &str) -> Result<()> {
-    let out = try!(Command::new("ip")
-        .arg("tuntap").arg("del").arg(name)
-        .arg("mode").arg("tap").output());
-
-    if !out.status.success() {
-        let err = match String::from_utf8(out.stderr) {
-            Ok(err) => err,
-            Err(_) => return Err(Error::new("Failed to read 'ip' output as a string"))
-        };
-
-        return Err(Error::new(format!("Failed to delete TAP: {}", err)));
+pub fn tap_delete(ctx: &Context, name: &str) -> Result<()> {
+    let out = try!(ctx.runner.exec("ip", &["tuntap", "del", name, "mode", "tap"]));
+
+    if !out.success {
+        return Err(Error::network(format!("Failed to delete TAP: {}", out.stderr)));
     }
 
     Ok(())
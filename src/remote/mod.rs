@@ -3,46 +3,116 @@
  */
 
 use std::error::Error as StdError;
-use std::net::UdpSocket;
 use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use serde_json;
 use serde_json::value::Value;
 
-use common::{Result, Error};
+use common::{Context, Result, Error, ErrorKind};
+use utils::frame;
+use auth;
+use jobs;
+
+/*
+ * Time to wait for a response before sending a request again, and number of retries
+ */
+const TIMEOUT: u64 = 5;
+const RETRIES: u32 = 5;
+
+/*
+ * Time between two checks of a remote job, in milliseconds
+ */
+const POLL_INTERVAL: u64 = 1000;
+
+/*
+ * Maximum time to wait for a remote job, in seconds
+ */
+const WAIT_TIMEOUT: u64 = 1800;
 
 /*
  * Send a command to a remote server via UDP
+ * The command is signed with the configured remote token, if any
+ */
+pub fn command(ctx: &Context, srv: &str, cmd: &str, arg: &str) -> Result<Value> {
+    let (socket, dst) = match frame::bind(srv) {
+        Ok(res) => res,
+        Err(e) => return Err(Error::network(format!("Remote {}: {}", srv, e.description())))
+    };
+    let id = frame::request_id();
+    let mut data = format!("{} {}", cmd, arg);
+
+    if let Some(ref auth) = ctx.conf.auth {
+        if let Some(token) = auth.get_remote_token() {
+            data = auth::sign(token, Some(id.as_str()), data.as_str());
+        }
+    }
+
+    let res = match frame::exchange(&socket, dst, id.as_str(), data.as_str(), Duration::from_secs(TIMEOUT), RETRIES) {
+        Ok(res) => res,
+        Err(e) => return Err(Error::network(format!("Remote {}: {}", srv, e.description())))
+    };
+
+    let s = match String::from_utf8(res) {
+        Ok(s) => s,
+        Err(_) => return Err(Error::network("Invalid response: could not read as a string"))
+    };
+
+    if s.contains("\"error\"") {
+        let json: Value = try!(serde_json::from_str(s.as_str()));
+        let msg = try!(json.get("error").ok_or(Error::network("Remote sent invalid error")));
+        let msg = try!(msg.as_str().ok_or(Error::network("Remote sent invalid error")));
+        let kind = match json.get("code").and_then(|c| c.as_str()) {
+            Some(code) => ErrorKind::from_code(code),
+            None => ErrorKind::NetworkFailure
+        };
+
+        Err(Error::with_kind(kind, format!("Remote: {}", msg)))
+    }
+    else if s.trim().len() > 0 {
+        Ok(try!(serde_json::from_str(s.as_str())))
+    }
+    else {
+        Ok(Value::Null)
+    }
+}
+
+/*
+ * Wait for the job started by a command on a remote server, and return its result
+ * Servers without jobs answer with the result right away
+ * The remote job is cancelled when the local one is, or when it takes longer than WAIT_TIMEOUT
  */
-pub fn command(srv: &str, cmd: &str, arg: &str) -> Result<Value> {
-    let socket = try!(UdpSocket::bind("0.0.0.0:0"));
-    let data = format!("{} {}", cmd, arg);
-
-    try!(socket.send_to(data.as_bytes(), srv));
-
-    let mut buf = [0; 1024];
-    match socket.recv_from(&mut buf) {
-        Ok((len, _)) => {
-            match String::from_utf8(buf[..len].to_vec()) {
-                Ok(s) => {
-                    if s.contains("\"error\"") {
-                        let json: Value = try!(serde_json::from_str(s.as_str()));
-                        let msg = try!(json.get("error").ok_or(Error::new("Remote sent invalid error")));
-                        let msg = try!(msg.as_str().ok_or(Error::new("Remote sent invalid error")));
-
-                        Err(Error::new(format!("Remote: {}", msg)))
-                    }
-                    else if s.len() > 2 {
-                        Ok(try!(serde_json::from_str(s.as_str())))
-                    }
-                    else {
-                        Ok(Value::Null)
-                    }
-                }
-                Err(_) => Err(Error::new("Invalid response: could not read as a string"))
-            }
-        },
-        Err(e) => Err(Error::new(e.description()))
+pub fn wait(ctx: &Context, local: &jobs::Handle, srv: &str, res: &Value) -> Result<String> {
+    let id = match res.get("job").and_then(|j| j.as_str()) {
+        Some(id) => id,
+        None => return Ok(res.to_string())
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(WAIT_TIMEOUT);
+
+    loop {
+        let stop = match local.check() {
+            Ok(_) if Instant::now() >= deadline => Err(Error::network(format!("Remote: job '{}' did not finish after {} seconds", id, WAIT_TIMEOUT))),
+            res => res
+        };
+
+        if let Err(e) = stop {
+            let _ = command(ctx, srv, "canceljob", id);
+            return Err(e);
+        }
+
+        let job = try!(command(ctx, srv, "getjob", id));
+        let field = |key: &str| job.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+
+        match field("state").as_str() {
+            "done" => return Ok(field("result")),
+            "failed" | "cancelled" => {
+                return Err(Error::with_kind(ErrorKind::from_code(field("code").as_str()), format!("Remote: {}", field("error"))));
+            },
+            "queued" | "running" => thread::sleep(Duration::from_millis(POLL_INTERVAL)),
+            _ => return Err(Error::network(format!("Remote: invalid job '{}'", id)))
+        };
     }
 }
 
@@ -55,10 +125,10 @@ pub fn transfer(local: &str, dst: &str, path: &str) -> Result<()> {
     if !out.status.success() {
         let s = match String::from_utf8(out.stderr) {
             Ok(s) => s,
-            Err(_) => return Err(Error::new("scp failed"))
+            Err(_) => return Err(Error::network("scp failed"))
         };
 
-        return Err(Error::new(s));
+        return Err(Error::network(s));
     }
 
     Ok(())
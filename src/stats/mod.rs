@@ -0,0 +1,411 @@
+/*
+ * Stats - Resource usage of the host and of the running VMs, sampled in the background
+ *
+ * Every few seconds, the counters of the VM processes (found with the 'pid' parameter returned by
+ * the start script) and of their TAP devices are read from /proc and sysfs. Rates are computed
+ * between two samples, the 'statsvm' command returns the last one without waiting.
+ * The usage of the host is sampled along, for the 'status' and 'metrics' commands.
+ */
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use common::{Context, Result, Error};
+use common::structs::{VM, State};
+use database;
+use net;
+use utils::system;
+
+/*
+ * Counters read at one point in time
+ */
+#[derive(Clone)]
+pub struct Reading {
+    pub at: Instant,
+    pub pid: u32,
+    pub process: system::ProcessStats,
+    pub io: Option<(u64, u64)>, // Bytes read and written, only readable by root and the owner of the process
+    pub interfaces: Vec<(String, system::InterfaceStats)> // By TAP device
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Disk {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub read_rate: f64, // Bytes per second
+    pub write_rate: f64
+}
+
+/*
+ * Counters of a TAP device, received by the host means sent by the VM
+ */
+#[derive(Serialize, Clone, Debug)]
+pub struct Interface {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_rate: f64, // Bytes per second
+    pub tx_rate: f64
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct VMStats {
+    pub vm: String,
+    pub pid: u32,
+    pub time: u64, // Unix time of the sample
+    pub uptime: f64, // Seconds since the process started
+    pub cpu_time: f64, // Seconds
+    pub cpu_percent: f64, // 100 per fully used core
+    pub rss: u64, // Bytes
+    pub disk: Option<Disk>,
+    pub interfaces: Vec<Interface>
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+/*
+ * Increase of a counter per second, counters going backwards were reset
+ */
+fn rate(previous: u64, current: u64, elapsed: f64) -> f64 {
+    if elapsed > 0.0 {
+        current.saturating_sub(previous) as f64 / elapsed
+    }
+    else {
+        0.0
+    }
+}
+
+/*
+ * Compute the usage of a VM from its last two readings
+ * Rates are 0 until the process has been read twice
+ */
+pub fn compute(vm: &str, time: u64, previous: Option<&Reading>, current: &Reading) -> VMStats {
+    // A new process is a restarted VM, its counters started over
+    let previous = previous.and_then(|p| if p.pid == current.pid && current.at > p.at { Some(p) } else { None });
+    let elapsed = previous.map(|p| seconds(current.at.duration_since(p.at))).unwrap_or(0.0);
+
+    let cpu_percent = match previous {
+        Some(p) if current.process.cpu_time > p.process.cpu_time => (current.process.cpu_time - p.process.cpu_time) / elapsed * 100.0,
+        _ => 0.0
+    };
+
+    let disk = current.io.map(|(read, written)| {
+        let (r, w) = previous.and_then(|p| p.io).unwrap_or((read, written));
+
+        Disk {
+            read_bytes: read,
+            write_bytes: written,
+            read_rate: rate(r, read, elapsed),
+            write_rate: rate(w, written, elapsed)
+        }
+    });
+
+    let interfaces = current.interfaces.iter().map(|&(ref name, ref stats)| {
+        let before = previous.and_then(|p| p.interfaces.iter().find(|&&(ref n, _)| n == name)).map(|&(_, ref s)| s).unwrap_or(stats);
+
+        Interface {
+            name: name.clone(),
+            rx_bytes: stats.rx_bytes,
+            tx_bytes: stats.tx_bytes,
+            rx_packets: stats.rx_packets,
+            tx_packets: stats.tx_packets,
+            rx_rate: rate(before.rx_bytes, stats.rx_bytes, elapsed),
+            tx_rate: rate(before.tx_bytes, stats.tx_bytes, elapsed)
+        }
+    }).collect();
+
+    VMStats {
+        vm: vm.to_string(),
+        pid: current.pid,
+        time: time,
+        uptime: current.process.uptime,
+        cpu_time: current.process.cpu_time,
+        cpu_percent: cpu_percent,
+        rss: current.process.rss,
+        disk: disk,
+        interfaces: interfaces
+    }
+}
+
+/*
+ * Return the process of a running VM, as reported by its start script
+ */
+fn pid(vm: &VM) -> Option<u32> {
+    match vm.state {
+        State::Running => vm.parameters.get("pid").and_then(|pid| pid.parse::<u32>().ok()),
+        _ => None
+    }
+}
+
+/*
+ * Read the counters of a VM process and of its TAP devices
+ */
+fn read(vm: &VM, pid: u32) -> Result<Reading> {
+    let process = try!(system::process_stats(pid));
+
+    let mut interfaces = Vec::new();
+    for i in 0..vm.interfaces.len() {
+        let dev = net::iface_dev(vm.name.as_str(), i as i32);
+
+        // The device only exists while the VM is running
+        if let Ok(stats) = system::interface_stats(dev.as_str()) {
+            interfaces.push((dev, stats));
+        }
+    }
+
+    Ok(Reading {
+        at: Instant::now(),
+        pid: pid,
+        process: process,
+        io: system::process_io(pid).ok(),
+        interfaces: interfaces
+    })
+}
+
+/*
+ * Space used on the filesystem of an image or VM directory, in bytes
+ */
+#[derive(Serialize, Clone, Debug)]
+pub struct Storage {
+    pub path: String,
+    pub backend: String,
+    pub content: String, // "images" or "vms"
+    pub total: u64,
+    pub used: u64,
+    pub free: u64
+}
+
+/*
+ * Busy time of an online core, cores are numbered as in /proc/stat
+ */
+#[derive(Serialize, Clone, Debug)]
+pub struct CoreUsage {
+    pub core: u32,
+    pub usage: f32
+}
+
+/*
+ * Usage of the host, memory values are in MiB and CPU usage in %
+ */
+#[derive(Serialize, Clone, Debug)]
+pub struct Host {
+    pub time: u64, // Unix time of the sample
+    pub kernel: String,
+    pub kvm: bool, // /dev/kvm can be opened
+    pub uptime: f64, // Seconds since the host booted
+    pub load: [f64; 3], // Over 1, 5 and 15 minutes
+    pub cpu_usage: f32, // Busy time of all the CPUs
+    pub cpu: system::CpuUsage, // Same, by mode
+    pub cpus: Vec<CoreUsage>, // Busy time of each online core
+    pub mem_usage: f32,
+    pub mem_total: f32,
+    pub swap_usage: f32,
+    pub swap_total: f32,
+    pub storage: Vec<Storage>
+}
+
+/*
+ * Directories of the backends whose filesystem is reported
+ */
+fn storage(ctx: &Context) -> Vec<Storage> {
+    let mut dirs = Vec::new();
+    for backend in &ctx.conf.backend {
+        dirs.push((backend.image.path.clone(), backend.name.as_str(), "images"));
+        dirs.push((ctx.conf.get_vm_dir(backend.name.as_str()), backend.name.as_str(), "vms"));
+    }
+
+    let mut storage = Vec::new();
+    for (path, backend, content) in dirs {
+        match system::disk_usage(path.as_str()) {
+            Ok(usage) => storage.push(Storage {
+                path: path.clone(),
+                backend: backend.to_string(),
+                content: content.to_string(),
+                total: usage.total,
+                used: usage.used,
+                free: usage.free
+            }),
+            Err(e) => debug!("stats", "failed to read disk usage: {}", e; path = path)
+        };
+    }
+
+    storage
+}
+
+struct Sample {
+    reading: Reading,
+    stats: VMStats
+}
+
+pub struct Sampler {
+    vms: Mutex<HashMap<String, Sample>>,
+    host: Mutex<Option<(system::CpuStats, Host)>> // Along with the CPU counters it was computed from
+}
+
+impl Sampler {
+    pub fn new() -> Sampler {
+        Sampler {
+            vms: Mutex::new(HashMap::new()),
+            host: Mutex::new(None)
+        }
+    }
+
+    /*
+     * Sample the host and all the running VMs once
+     */
+    pub fn sample(&self, ctx: &Context) -> Result<()> {
+        let host = self.sample_host(ctx).map(|_| ());
+        try!(self.sample_vms(ctx));
+
+        host
+    }
+
+    /*
+     * Sample the host, CPU usage is computed since the previous sample, or since boot for the first one
+     */
+    fn sample_host(&self, ctx: &Context) -> Result<Host> {
+        let mut last = self.host.lock().unwrap();
+
+        let cpu = try!(system::cpu_stats());
+        let previous = match *last {
+            Some((ref p, _)) => p.clone(),
+            None => system::CpuStats::default()
+        };
+
+        let usage = system::cpu_usage(&previous.all, &cpu.all);
+
+        // Cores brought online in the meantime are compared to boot
+        let zero = system::CpuTimes::default();
+        let cores = cpu.cores.iter().map(|&(core, ref times)| {
+            let before = previous.cores.iter().find(|&&(c, _)| c == core).map(|&(_, ref t)| t).unwrap_or(&zero);
+            CoreUsage {
+                core: core,
+                usage: system::cpu_usage(before, times).busy()
+            }
+        }).collect();
+
+        let (mem_usage, mem_total) = try!(system::global_memory_info());
+        let (swap_usage, swap_total) = try!(system::global_swap_info());
+        let (l1, l5, l15) = try!(system::loadavg());
+
+        let host = Host {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            kernel: try!(system::kernel_version()),
+            kvm: system::kvm_available(),
+            uptime: try!(system::uptime()),
+            load: [l1, l5, l15],
+            cpu_usage: usage.busy(),
+            cpu: usage,
+            cpus: cores,
+            mem_usage: mem_usage,
+            mem_total: mem_total,
+            swap_usage: swap_usage,
+            swap_total: swap_total,
+            storage: storage(ctx)
+        };
+
+        *last = Some((cpu, host.clone()));
+
+        Ok(host)
+    }
+
+    /*
+     * Return the last sample of the host, the host is sampled right away before the first one
+     */
+    pub fn host(&self, ctx: &Context) -> Result<Host> {
+        let last = self.host.lock().unwrap().as_ref().map(|&(_, ref host)| host.clone());
+
+        match last {
+            Some(host) => Ok(host),
+            None => self.sample_host(ctx)
+        }
+    }
+
+    /*
+     * Sample all the running VMs once, and forget the others
+     */
+    fn sample_vms(&self, ctx: &Context) -> Result<()> {
+        let vms = try!(database::vm::list(ctx));
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let mut readings = Vec::new();
+        for vm in &vms {
+            if let Some(pid) = pid(vm) {
+                match read(vm, pid) {
+                    Ok(r) => readings.push((vm.name.clone(), r)),
+                    Err(e) => debug!("stats", "failed to read VM process: {}", e; vm = vm.name, pid = pid)
+                };
+            }
+        }
+
+        let mut samples = self.vms.lock().unwrap();
+        let mut next = HashMap::new();
+
+        for (name, reading) in readings {
+            let stats = compute(name.as_str(), time, samples.get(&name).map(|s| &s.reading), &reading);
+
+            next.insert(name, Sample {
+                reading: reading,
+                stats: stats
+            });
+        }
+
+        *samples = next;
+
+        Ok(())
+    }
+
+    /*
+     * Return the last sample of a VM
+     */
+    pub fn get(&self, name: &str) -> Option<VMStats> {
+        self.vms.lock().unwrap().get(name).map(|s| s.stats.clone())
+    }
+}
+
+/*
+ * Handle a 'statsvm' command, return the last sample of a running VM
+ */
+pub fn get(ctx: &Context, name: &str) -> Result<String> {
+    let vm = try!(database::vm::get(ctx, name));
+
+    if vm.state != State::Running {
+        return Err(Error::invalid_state(format!("VM '{}' is not running", name)));
+    }
+
+    if pid(&vm).is_none() {
+        return Err(Error::invalid_state(format!("The backend of VM '{}' did not report its process", name)));
+    }
+
+    match ctx.stats.get(name) {
+        Some(stats) => Ok(try!(serde_json::to_string(&stats))),
+        None => Err(Error::busy(format!("VM '{}' has not been sampled yet, try again in a few seconds", name)))
+    }
+}
+
+pub fn run(ctx: Arc<Context>) {
+    let interval = Duration::from_secs(ctx.conf.stats.interval);
+
+    loop {
+        match ctx.stats.sample(ctx.as_ref()) {
+            Ok(_) => {},
+            Err(e) => error!("stats", "{}", e)
+        };
+
+        thread::sleep(interval);
+    }
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
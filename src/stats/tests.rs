@@ -0,0 +1,127 @@
+use std::process;
+use std::time::{Duration, Instant};
+
+use serde_json;
+use serde_json::value::Value;
+
+use common::{Context, ErrorKind};
+use common::structs::{VM, State};
+use database;
+use utils::system::{ProcessStats, InterfaceStats};
+
+use super::{Reading, compute, get};
+
+fn context() -> Context {
+    Context::test(r#"
+        backend = []
+
+        [global]
+        node = 1
+
+        [database]
+        type = "memory"
+    "#)
+}
+
+fn reading(at: Instant, pid: u32, cpu_time: f64, io: (u64, u64), rx: u64, tx: u64) -> Reading {
+    Reading {
+        at: at,
+        pid: pid,
+        process: ProcessStats {
+            cpu_time: cpu_time,
+            rss: 512 * 1048576,
+            uptime: 3600.0
+        },
+        io: Some(io),
+        interfaces: vec![(String::from("vmweb.0"), InterfaceStats {
+            rx_bytes: rx,
+            tx_bytes: tx,
+            rx_packets: rx / 1000,
+            tx_packets: tx / 1000
+        })]
+    }
+}
+
+#[test]
+fn rates() {
+    let now = Instant::now();
+    let first = reading(now, 4242, 100.0, (1000000, 2000000), 50000, 10000);
+    let second = reading(now + Duration::from_secs(5), 4242, 112.5, (6000000, 2000000), 550000, 10000);
+
+    // Nothing to compare the first reading with
+    let s = compute("web", 1500000000, None, &first);
+    assert_eq!(s.cpu_percent, 0.0);
+    assert_eq!(s.disk.as_ref().unwrap().read_rate, 0.0);
+    assert_eq!(s.interfaces[0].rx_rate, 0.0);
+
+    // 2.5 cores used over 5 seconds
+    let s = compute("web", 1500000005, Some(&first), &second);
+    assert_eq!(s.vm.as_str(), "web");
+    assert_eq!(s.time, 1500000005);
+    assert_eq!(s.cpu_percent, 250.0);
+    assert_eq!(s.cpu_time, 112.5);
+    assert_eq!(s.rss, 512 * 1048576);
+
+    let disk = s.disk.unwrap();
+    assert_eq!((disk.read_bytes, disk.write_bytes), (6000000, 2000000));
+    assert_eq!((disk.read_rate, disk.write_rate), (1000000.0, 0.0));
+
+    assert_eq!(s.interfaces[0].name.as_str(), "vmweb.0");
+    assert_eq!((s.interfaces[0].rx_bytes, s.interfaces[0].rx_packets), (550000, 550));
+    assert_eq!((s.interfaces[0].rx_rate, s.interfaces[0].tx_rate), (100000.0, 0.0));
+}
+
+#[test]
+fn counters_reset() {
+    let now = Instant::now();
+    let first = reading(now, 4242, 100.0, (1000000, 2000000), 50000, 10000);
+
+    // The VM was restarted, its process and TAP device are new
+    let restarted = reading(now + Duration::from_secs(5), 4343, 1.0, (5000, 0), 100, 100);
+    let s = compute("web", 1500000005, Some(&first), &restarted);
+    assert_eq!(s.pid, 4343);
+    assert_eq!(s.cpu_percent, 0.0);
+    assert_eq!(s.disk.unwrap().read_rate, 0.0);
+
+    // Only the TAP device was recreated
+    let recreated = reading(now + Duration::from_secs(5), 4242, 101.0, (1000000, 2000000), 100, 100);
+    let s = compute("web", 1500000005, Some(&first), &recreated);
+    assert_eq!(s.interfaces[0].rx_rate, 0.0);
+    assert_eq!(s.cpu_percent, 20.0);
+}
+
+#[test]
+fn sample_running_vms() {
+    let ctx = context();
+
+    let stopped = VM::from_json(r#"{"name": "db", "backend": "kvm", "state": "stopped"}"#).unwrap();
+    database::vm::create(&ctx, &stopped).unwrap();
+
+    let unknown = VM::from_json(r#"{"name": "legacy", "backend": "kvm", "state": "running"}"#).unwrap();
+    database::vm::create(&ctx, &unknown).unwrap();
+
+    // The test process stands in for the VM process
+    let mut running = VM::from_json(format!(r#"{{"name": "web", "backend": "kvm", "state": "running", "parameters": {{"pid": "{}"}}}}"#, process::id()).as_str()).unwrap();
+    database::vm::create(&ctx, &running).unwrap();
+
+    assert_eq!(get(&ctx, "web").unwrap_err().kind(), ErrorKind::Busy);
+
+    ctx.stats.sample(&ctx).unwrap();
+
+    let json: Value = serde_json::from_str(get(&ctx, "web").unwrap().as_str()).unwrap();
+    assert_eq!(json["vm"].as_str(), Some("web"));
+    assert_eq!(json["pid"].as_u64(), Some(process::id() as u64));
+    assert!(json["rss"].as_u64().unwrap() > 0);
+    assert!(json["cpu_percent"].is_number());
+
+    assert_eq!(get(&ctx, "db").unwrap_err().kind(), ErrorKind::InvalidState);
+    assert_eq!(get(&ctx, "legacy").unwrap_err().kind(), ErrorKind::InvalidState);
+    assert_eq!(get(&ctx, "nope").unwrap_err().kind(), ErrorKind::NotFound);
+
+    // Stopped VMs are forgotten at the next sample
+    running.state = State::Stopped;
+    database::vm::update(&ctx, &running).unwrap();
+    ctx.stats.sample(&ctx).unwrap();
+
+    assert!(ctx.stats.get("web").is_none());
+}
@@ -0,0 +1,306 @@
+/*
+ * Supervisor - Periodically check that running VMs are still alive,
+ * and restart crashed VMs according to their restart policy
+ */
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{Context, Result};
+use common::structs::{VM, State, Restart};
+use events::{self, Event};
+use database;
+use backend;
+use handler;
+
+/*
+ * Maximum delay between two restarts of a VM, in seconds
+ */
+const MAX_BACKOFF: u64 = 300;
+
+/*
+ * Time a restarted VM must stay up before its restart count is reset, in seconds
+ */
+const RESET_AFTER: u64 = 300;
+
+/*
+ * Automatic restarts of a crashed VM
+ */
+struct Restarts {
+    attempts: u32,
+    next: Instant, // Time of the next attempt
+    last: Option<Instant>, // Time of the last attempt
+    gave_up: bool
+}
+
+pub struct Supervisor {
+    restarts: HashMap<String, Restarts>
+}
+
+/*
+ * Check if a VM is still running, according to its backend status script
+ * Return None when the status script does not tell
+ */
+fn is_running(ctx: &Context, vm: &mut VM) -> Result<Option<bool>> {
+    let status = try!(backend::vm::script_status(ctx, vm));
+
+    match status.get("running").map(|r| r.as_str()) {
+        Some("true") => Ok(Some(true)),
+        Some("false") => Ok(Some(false)),
+        _ => Ok(None)
+    }
+}
+
+/*
+ * Mark a VM as crashed, unless its state changed during the check
+ * Return true if the VM was marked
+ */
+fn crashed(ctx: &Context, name: &str) -> Result<bool> {
+    // A command is running on the VM, check it again later
+    let _lock = match ctx.locks.try_lock("vm", name) {
+        Some(lock) => lock,
+        None => return Ok(false)
+    };
+
+    let mut vm = try!(database::vm::get(ctx, name));
+    if vm.state != State::Running {
+        return Ok(false);
+    }
+
+    vm.state = State::Crashed;
+    try!(database::vm::update(ctx, &vm));
+
+    warn!("supervisor", "VM crashed"; vm = vm.name);
+    events::publish(ctx, Event::new("vm.crashed", vm.name.as_str(), json!({
+        "backend": vm.backend,
+        "previous_state": State::Running.as_str()
+    })));
+
+    Ok(true)
+}
+
+/*
+ * Delay before a restart attempt, doubled after each attempt
+ */
+fn backoff(vm: &VM, attempts: u32) -> Duration {
+    let delay = (vm.restart_policy.backoff as u64).saturating_mul(1 << attempts.min(16));
+    Duration::from_secs(delay.min(MAX_BACKOFF))
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            restarts: HashMap::new()
+        }
+    }
+
+    /*
+     * Restart a crashed VM if its policy allows it
+     */
+    fn restart(&mut self, ctx: &Context, vm: &VM) {
+        let policy = &vm.restart_policy;
+        if policy.kind == Restart::Never {
+            return;
+        }
+
+        let restarts = self.restarts.entry(vm.name.clone()).or_insert(Restarts {
+            attempts: 0,
+            next: Instant::now() + backoff(vm, 0),
+            last: None,
+            gave_up: false
+        });
+
+        if policy.kind == Restart::OnFailure && restarts.attempts >= policy.max_retries {
+            if !restarts.gave_up {
+                error!("supervisor", "VM crashed {} times, giving up", restarts.attempts + 1; vm = vm.name);
+                events::publish(ctx, Event::new("vm.restart_failed", vm.name.as_str(), json!({
+                    "attempts": restarts.attempts
+                })));
+
+                restarts.gave_up = true;
+            }
+
+            return;
+        }
+
+        if Instant::now() < restarts.next {
+            return;
+        }
+
+        let _lock = match ctx.locks.try_lock("vm", vm.name.as_str()) {
+            Some(lock) => lock,
+            None => return
+        };
+
+        restarts.attempts += 1;
+        restarts.next = Instant::now() + backoff(vm, restarts.attempts);
+        restarts.last = Some(Instant::now());
+
+        info!("supervisor", "restarting VM (attempt {})", restarts.attempts; vm = vm.name);
+
+        match handler::vm::start(ctx, vm.name.as_str()) {
+            Ok(_) => events::publish(ctx, Event::new("vm.restarted", vm.name.as_str(), json!({
+                "attempt": restarts.attempts
+            }))),
+            Err(e) => error!("supervisor", "failed to restart VM: {}", e; vm = vm.name)
+        };
+    }
+
+    /*
+     * Forget the restarts of a VM that has been up long enough
+     */
+    fn healthy(&mut self, vm: &VM) {
+        let reset = match self.restarts.get(&vm.name) {
+            Some(r) => match r.last {
+                Some(last) => last.elapsed() >= Duration::from_secs(RESET_AFTER),
+                None => true
+            },
+            None => false
+        };
+
+        if reset {
+            self.restarts.remove(&vm.name);
+        }
+    }
+
+    /*
+     * Check all the VMs once
+     */
+    pub fn check(&mut self, ctx: &Context) -> Result<()> {
+        let vms = try!(database::vm::list(ctx));
+
+        for mut vm in vms.clone() {
+            if vm.state == State::Running {
+                match is_running(ctx, &mut vm) {
+                    Ok(Some(false)) => {
+                        if try!(crashed(ctx, vm.name.as_str())) {
+                            vm.state = State::Crashed;
+                        }
+                    },
+                    Ok(_) => self.healthy(&vm),
+                    Err(e) => error!("supervisor", "failed to check VM: {}", e; vm = vm.name)
+                };
+            }
+
+            // VMs failing to restart end up in error, keep trying
+            match vm.state {
+                State::Crashed => self.restart(ctx, &vm),
+                State::Error if self.restarts.contains_key(&vm.name) => self.restart(ctx, &vm),
+                _ => {}
+            };
+        }
+
+        // Forget the VMs that were stopped or deleted in the meantime
+        self.restarts.retain(|name, _| vms.iter().any(|vm| {
+            &vm.name == name && vm.state != State::Stopped
+        }));
+
+        Ok(())
+    }
+}
+
+/*
+ * Settle the states left behind when the server stopped in the middle of a command, and the ones
+ * of the VMs stored before states existed, called once when the server starts
+ */
+pub fn recover(ctx: &Context) -> Result<()> {
+    let vms = try!(database::vm::list(ctx));
+
+    for mut vm in vms {
+        let previous = vm.state;
+
+        vm.state = match previous {
+            // The backend may have created a part of the VM
+            State::Creating => State::Error,
+
+            State::Unknown | State::Starting | State::Stopping | State::Migrating => {
+                match is_running(ctx, &mut vm) {
+                    Ok(Some(true)) => State::Running,
+                    Ok(Some(false)) => State::Stopped,
+                    Ok(None) => State::Error,
+                    Err(e) => {
+                        error!("supervisor", "failed to check VM: {}", e; vm = vm.name);
+                        State::Error
+                    }
+                }
+            },
+
+            _ => continue
+        };
+
+        try!(database::vm::update(ctx, &vm));
+        warn!("supervisor", "recovered VM state"; vm = vm.name, previous = previous.as_str(), state = vm.state.as_str());
+    }
+
+    Ok(())
+}
+
+/*
+ * Start the VMs marked as autostart, called once when the server starts, after recover
+ */
+pub fn autostart(ctx: &Context) -> Result<()> {
+    let vms = try!(database::vm::list(ctx));
+
+    for mut vm in vms {
+        if !vm.autostart {
+            continue;
+        }
+
+        let _lock = ctx.locks.lock("vm", vm.name.as_str());
+
+        // The recorded state is stale after a reboot of the host
+        match vm.state {
+            State::Running => {
+                // Never start a second instance of a VM that may still be running
+                match is_running(ctx, &mut vm) {
+                    Ok(Some(true)) => continue,
+                    Ok(Some(false)) => {
+                        vm.state = State::Stopped;
+                        try!(database::vm::update(ctx, &vm));
+                    },
+                    Ok(None) => {
+                        warn!("supervisor", "unknown VM status, not starting it"; vm = vm.name);
+                        continue;
+                    },
+                    Err(e) => {
+                        error!("supervisor", "failed to check VM, not starting it: {}", e; vm = vm.name);
+                        continue;
+                    }
+                };
+            },
+            State::Stopped | State::Crashed | State::Error => {},
+            _ => continue
+        };
+
+        info!("supervisor", "starting VM"; vm = vm.name);
+
+        match handler::vm::start(ctx, vm.name.as_str()) {
+            Ok(_) => {},
+            Err(e) => error!("supervisor", "failed to start VM: {}", e; vm = vm.name)
+        };
+    }
+
+    Ok(())
+}
+
+pub fn run(ctx: Arc<Context>) {
+    let interval = Duration::from_secs(ctx.conf.supervisor.interval);
+    let mut supervisor = Supervisor::new();
+
+    loop {
+        thread::sleep(interval);
+
+        match supervisor.check(ctx.as_ref()) {
+            Ok(_) => {},
+            Err(e) => error!("supervisor", "{}", e)
+        };
+    }
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,278 @@
+use std::sync::{Arc, Mutex};
+
+use common::{Context, Result};
+use common::structs::{VM, State};
+use config;
+use database;
+use utils::exec::{Runner, Output};
+
+use super::{Supervisor, autostart, recover};
+
+/*
+ * Runner answering every status script with the same output
+ */
+struct Status {
+    stdout: String,
+    calls: Arc<Mutex<usize>>
+}
+
+impl Runner for Status {
+    fn exec(&self, _program: &str, _args: &[&str]) -> Result<Output> {
+        *self.calls.lock().unwrap() += 1;
+
+        Ok(Output {
+            success: true,
+            stdout: self.stdout.clone(),
+            stderr: String::new()
+        })
+    }
+}
+
+fn context(stdout: &str) -> (Context, Arc<Mutex<usize>>) {
+    let conf = r#"
+        [global]
+        node = 1
+
+        [database]
+        type = "memory"
+
+        [[backend]]
+        name = "fake"
+
+        [backend.image]
+        path = "/tmp"
+
+        [backend.vm]
+        status = "vm/status"
+    "#;
+
+    let calls = Arc::new(Mutex::new(0));
+    let runner = Status {
+        stdout: stdout.to_string(),
+        calls: calls.clone()
+    };
+
+    let ctx = Context::test_with_runner(conf, Box::new(runner));
+
+    (ctx, calls)
+}
+
+fn vm(ctx: &Context, name: &str, state: State) {
+    vm_with(ctx, name, state, "")
+}
+
+/*
+ * Create a VM with extra JSON fields
+ */
+fn vm_with(ctx: &Context, name: &str, state: State, fields: &str) {
+    let mut vm = VM::from_json(format!(r#"{{"name": "{}", "node": 1, "backend": "fake" {}}}"#, name, fields).as_str()).unwrap();
+    vm.state = state;
+
+    database::vm::create(ctx, &vm).unwrap();
+}
+
+fn state(ctx: &Context, name: &str) -> State {
+    database::vm::get(ctx, name).unwrap().state
+}
+
+fn check(ctx: &Context) -> Result<()> {
+    Supervisor::new().check(ctx)
+}
+
+#[test]
+fn detects_crashed_vms() {
+    let (ctx, _) = context("running false\n");
+    vm(&ctx, "test", State::Running);
+
+    check(&ctx).unwrap();
+    assert_eq!(state(&ctx, "test"), State::Crashed);
+}
+
+#[test]
+fn keeps_running_vms() {
+    let (ctx, _) = context("running true\n");
+    vm(&ctx, "test", State::Running);
+
+    check(&ctx).unwrap();
+    assert_eq!(state(&ctx, "test"), State::Running);
+}
+
+#[test]
+fn ignores_unknown_status() {
+    let (ctx, _) = context("");
+    vm(&ctx, "test", State::Running);
+
+    check(&ctx).unwrap();
+    assert_eq!(state(&ctx, "test"), State::Running);
+}
+
+#[test]
+fn only_checks_running_vms() {
+    let (ctx, calls) = context("running false\n");
+    vm(&ctx, "stopped", State::Stopped);
+    vm(&ctx, "error", State::Error);
+
+    check(&ctx).unwrap();
+
+    assert_eq!(*calls.lock().unwrap(), 0);
+    assert_eq!(state(&ctx, "stopped"), State::Stopped);
+    assert_eq!(state(&ctx, "error"), State::Error);
+}
+
+#[test]
+fn skips_busy_vms() {
+    let (ctx, _) = context("running false\n");
+    vm(&ctx, "test", State::Running);
+
+    // A command is running on the VM
+    {
+        let _lock = ctx.locks.lock("vm", "test");
+
+        check(&ctx).unwrap();
+        assert_eq!(state(&ctx, "test"), State::Running);
+    }
+
+    check(&ctx).unwrap();
+    assert_eq!(state(&ctx, "test"), State::Crashed);
+}
+
+#[test]
+fn never_restarts_by_default() {
+    let (ctx, _) = context("running false\n");
+    vm(&ctx, "test", State::Running);
+
+    let mut supervisor = Supervisor::new();
+    supervisor.check(&ctx).unwrap();
+    supervisor.check(&ctx).unwrap();
+
+    assert_eq!(state(&ctx, "test"), State::Crashed);
+}
+
+#[test]
+fn restarts_on_failure() {
+    let (ctx, _) = context("running false\n");
+    vm_with(&ctx, "test", State::Running, r#", "restart_policy": {"type": "on-failure", "max_retries": 2, "backoff": 0}"#);
+
+    let mut supervisor = Supervisor::new();
+
+    // The status script keeps telling the VM is dead, it is restarted twice
+    supervisor.check(&ctx).unwrap();
+    assert_eq!(state(&ctx, "test"), State::Running);
+    supervisor.check(&ctx).unwrap();
+    assert_eq!(state(&ctx, "test"), State::Running);
+
+    supervisor.check(&ctx).unwrap();
+    assert_eq!(state(&ctx, "test"), State::Crashed);
+    supervisor.check(&ctx).unwrap();
+    assert_eq!(state(&ctx, "test"), State::Crashed);
+}
+
+#[test]
+fn restarts_always() {
+    let (ctx, _) = context("running false\n");
+    vm_with(&ctx, "test", State::Running, r#", "restart_policy": {"type": "always", "max_retries": 1, "backoff": 0}"#);
+
+    let mut supervisor = Supervisor::new();
+
+    for _ in 0..5 {
+        supervisor.check(&ctx).unwrap();
+        assert_eq!(state(&ctx, "test"), State::Running);
+    }
+}
+
+#[test]
+fn restart_backoff() {
+    let (ctx, _) = context("running false\n");
+    vm_with(&ctx, "test", State::Running, r#", "restart_policy": {"type": "always", "backoff": 60}"#);
+
+    let mut supervisor = Supervisor::new();
+    supervisor.check(&ctx).unwrap();
+    supervisor.check(&ctx).unwrap();
+
+    assert_eq!(state(&ctx, "test"), State::Crashed);
+}
+
+#[test]
+fn autostart_vms() {
+    let (ctx, _) = context("running false\n");
+
+    // Recorded as running before a reboot of the host
+    vm_with(&ctx, "stale", State::Running, r#", "autostart": true"#);
+    vm_with(&ctx, "stopped", State::Stopped, r#", "autostart": true"#);
+    vm(&ctx, "manual", State::Stopped);
+
+    autostart(&ctx).unwrap();
+
+    assert_eq!(state(&ctx, "stale"), State::Running);
+    assert_eq!(state(&ctx, "stopped"), State::Running);
+    assert_eq!(state(&ctx, "manual"), State::Stopped);
+}
+
+#[test]
+fn autostart_unknown_status() {
+    let (ctx, calls) = context("");
+
+    // The status script does not tell, the VM may still be running
+    vm_with(&ctx, "stale", State::Running, r#", "autostart": true"#);
+
+    autostart(&ctx).unwrap();
+
+    assert_eq!(state(&ctx, "stale"), State::Running);
+    assert_eq!(*calls.lock().unwrap(), 1);
+}
+
+#[test]
+fn recover_states() {
+    let (ctx, _) = context("running true\n");
+
+    // Stored before states existed
+    let legacy = VM::from_json(r#"{"name": "legacy", "node": 1, "backend": "fake"}"#).unwrap();
+    assert_eq!(legacy.state, State::Unknown);
+    database::vm::create(&ctx, &legacy).unwrap();
+
+    vm(&ctx, "starting", State::Starting);
+    vm(&ctx, "creating", State::Creating);
+    vm(&ctx, "stopped", State::Stopped);
+
+    recover(&ctx).unwrap();
+
+    assert_eq!(state(&ctx, "legacy"), State::Running);
+    assert_eq!(state(&ctx, "starting"), State::Running);
+    assert_eq!(state(&ctx, "creating"), State::Error);
+    assert_eq!(state(&ctx, "stopped"), State::Stopped);
+}
+
+#[test]
+fn recover_stopped_and_unknown() {
+    let (ctx, _) = context("running false\n");
+    vm(&ctx, "stopping", State::Stopping);
+    vm(&ctx, "migrating", State::Migrating);
+
+    recover(&ctx).unwrap();
+    assert_eq!(state(&ctx, "stopping"), State::Stopped);
+    assert_eq!(state(&ctx, "migrating"), State::Stopped);
+
+    // The status script does not tell
+    let (ctx, _) = context("");
+    vm(&ctx, "legacy", State::Unknown);
+
+    recover(&ctx).unwrap();
+    assert_eq!(state(&ctx, "legacy"), State::Error);
+}
+
+#[test]
+fn zero_interval() {
+    let conf = r#"
+        backend = []
+
+        [global]
+        node = 1
+
+        [database]
+        type = "memory"
+    "#;
+
+    assert!(config::parse(conf).is_ok());
+    assert!(config::parse(format!("{}\n[supervisor]\ninterval = 0\n", conf).as_str()).is_err());
+    assert!(config::parse(format!("{}\n[stats]\ninterval = 0\n", conf).as_str()).is_err());
+}
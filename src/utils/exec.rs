@@ -0,0 +1,46 @@
+/*
+ * External program execution
+ */
+
+use std::process::Command;
+
+use common::{Result, Error};
+
+/*
+ * Result of an executed program
+ */
+pub struct Output {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String
+}
+
+/*
+ * Executes the external programs the daemon relies on (backend scripts, networking tools...)
+ */
+pub trait Runner: Send + Sync {
+    fn exec(&self, program: &str, args: &[&str]) -> Result<Output>;
+}
+
+/*
+ * Runner spawning actual processes on the host
+ */
+pub struct Process;
+
+impl Runner for Process {
+    fn exec(&self, program: &str, args: &[&str]) -> Result<Output> {
+        let out = match Command::new(program).args(args).output() {
+            Ok(out) => out,
+            Err(e) => return Err(Error::new(format!("{}: exec: {}", program, e)))
+        };
+
+        let stdout = try!(String::from_utf8(out.stdout).ok().ok_or(Error::new(format!("{}: failed to read stdout", program))));
+        let stderr = try!(String::from_utf8(out.stderr).ok().ok_or(Error::new(format!("{}: failed to read stderr", program))));
+
+        Ok(Output {
+            success: out.status.success(),
+            stdout: stdout,
+            stderr: stderr
+        })
+    }
+}
@@ -0,0 +1,244 @@
+/*
+ * Frame - Reliable commands over UDP
+ *
+ * Requests are prefixed with an identifier chosen by the client:
+ *
+ *     #<id> <command> <argument>
+ *
+ * Responses are split in chunks small enough to fit in a single datagram:
+ *
+ *     #<id> <seq>/<total> <data>
+ *
+ * Chunks are numbered from 1, an empty response is sent as a single empty chunk.
+ * When a response is incomplete after a timeout, the client sends the same request again:
+ * the server answers requests it has already seen from a cache instead of running them twice.
+ *
+ * This module only depends on the standard library, so that clients can include it.
+ */
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/*
+ * Maximum size of the data carried by a response chunk
+ */
+pub const CHUNK_SIZE: usize = 1200;
+
+/*
+ * Maximum size of a UDP datagram
+ */
+pub const MAX_DATAGRAM: usize = 65507;
+
+/*
+ * Maximum length of a request identifier
+ */
+const MAX_ID: usize = 64;
+
+/*
+ * Maximum number of chunks in a response
+ */
+const MAX_CHUNKS: usize = 4096;
+
+static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/*
+ * Generate a request identifier, unique for this process
+ */
+pub fn request_id() -> String {
+    let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() * 1000000000 + d.subsec_nanos() as u64,
+        Err(_) => 0
+    };
+
+    format!("{:x}{:x}{:x}", process::id(), nanos, COUNTER.fetch_add(1, Ordering::SeqCst))
+}
+
+fn is_valid_id(id: &[u8]) -> bool {
+    id.len() > 0 && id.len() <= MAX_ID && id.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'-' || *c == b'_')
+}
+
+/*
+ * Build a request datagram
+ */
+pub fn request(id: &str, payload: &str) -> Vec<u8> {
+    format!("#{} {}", id, payload).into_bytes()
+}
+
+/*
+ * Split a request datagram into its identifier and payload
+ * Return None for datagrams without an identifier
+ */
+pub fn parse_request(data: &[u8]) -> Option<(String, &[u8])> {
+    if data.len() == 0 || data[0] != b'#' {
+        return None;
+    }
+
+    let (id, payload) = match data.iter().position(|c| *c == b' ') {
+        Some(i) => (&data[1..i], &data[i + 1..]),
+        None => (&data[1..], &data[data.len()..])
+    };
+
+    if !is_valid_id(id) {
+        return None;
+    }
+
+    Some((String::from_utf8_lossy(id).into_owned(), payload))
+}
+
+/*
+ * Split a response into chunk datagrams
+ */
+pub fn chunks(id: &str, data: &[u8]) -> Vec<Vec<u8>> {
+    let parts: Vec<&[u8]> = match data.len() {
+        0 => vec![data],
+        _ => data.chunks(CHUNK_SIZE).collect()
+    };
+
+    let total = parts.len();
+
+    parts.iter().enumerate().map(|(i, part)| {
+        let mut chunk = format!("#{} {}/{} ", id, i + 1, total).into_bytes();
+        chunk.extend_from_slice(part);
+        chunk
+    }).collect()
+}
+
+/*
+ * A chunk of response
+ */
+pub struct Chunk<'a> {
+    pub id: String,
+    pub seq: usize,
+    pub total: usize,
+    pub data: &'a [u8]
+}
+
+pub fn parse_chunk(data: &[u8]) -> Option<Chunk> {
+    let (id, rest) = match parse_request(data) {
+        Some(req) => req,
+        None => return None
+    };
+
+    let (counter, data) = match rest.iter().position(|c| *c == b' ') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, &rest[rest.len()..])
+    };
+
+    let counter = String::from_utf8_lossy(counter);
+    let (seq, total) = match counter.find('/') {
+        Some(i) => (counter[..i].parse::<usize>(), counter[i + 1..].parse::<usize>()),
+        None => return None
+    };
+
+    match (seq, total) {
+        (Ok(seq), Ok(total)) if seq >= 1 && seq <= total && total <= MAX_CHUNKS => Some(Chunk {
+            id: id,
+            seq: seq,
+            total: total,
+            data: data
+        }),
+        _ => None
+    }
+}
+
+/*
+ * Reassemble the chunks of a response, in any order
+ */
+pub struct Assembler {
+    parts: Vec<Option<Vec<u8>>>
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler {
+            parts: Vec::new()
+        }
+    }
+
+    /*
+     * Add a chunk, return true when the response is complete
+     */
+    pub fn add(&mut self, chunk: &Chunk) -> bool {
+        if self.parts.len() == 0 {
+            self.parts = vec![None; chunk.total];
+        }
+
+        // Ignore chunks not matching the first ones
+        if chunk.total == self.parts.len() {
+            self.parts[chunk.seq - 1] = Some(chunk.data.to_vec());
+        }
+
+        self.is_complete()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.parts.len() > 0 && self.parts.iter().all(|p| p.is_some())
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for p in self.parts.into_iter().filter_map(|p| p) {
+            data.extend(p);
+        }
+
+        data
+    }
+}
+
+/*
+ * Bind a client socket of the same address family as the destination, return it with the resolved destination
+ */
+pub fn bind<A: ToSocketAddrs>(dst: A) -> io::Result<(UdpSocket, SocketAddr)> {
+    let dst = match try!(dst.to_socket_addrs()).next() {
+        Some(addr) => addr,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))
+    };
+
+    let local = match dst {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0"
+    };
+
+    Ok((try!(UdpSocket::bind(local)), dst))
+}
+
+/*
+ * Send a request and wait for the complete response, the identifier comes from request_id()
+ * The request is sent again when no complete response arrived before the timeout
+ */
+pub fn exchange<A: ToSocketAddrs>(socket: &UdpSocket, dst: A, id: &str, payload: &str, timeout: Duration, retries: u32) -> io::Result<Vec<u8>> {
+    let req = request(id, payload);
+
+    try!(socket.set_read_timeout(Some(timeout)));
+
+    let mut assembler = Assembler::new();
+    let mut buf = vec![0; MAX_DATAGRAM];
+
+    for _ in 0..retries + 1 {
+        try!(socket.send_to(&req, &dst));
+
+        loop {
+            let len = match socket.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+                Err(e) => return Err(e)
+            };
+
+            // Ignore datagrams belonging to other requests
+            let complete = match parse_chunk(&buf[..len]) {
+                Some(ref chunk) if chunk.id == id => assembler.add(chunk),
+                _ => false
+            };
+
+            if complete {
+                return Ok(assembler.into_bytes());
+            }
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no response"))
+}
@@ -3,3 +3,12 @@
  */
 
 pub mod system;
+pub mod exec;
+pub mod frame;
+pub mod pool;
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
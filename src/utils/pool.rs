@@ -0,0 +1,68 @@
+/*
+ * Pool - Run tasks on a fixed number of threads
+ * Tasks wait in a bounded queue, new tasks are rejected when it is full
+ */
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+
+/*
+ * Boxed closures can not be called directly, they are run through this trait instead
+ */
+trait Task: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F: FnOnce() + Send> Task for F {
+    fn run(self: Box<F>) {
+        (*self)()
+    }
+}
+
+pub struct Pool {
+    sender: SyncSender<Box<Task>>
+}
+
+impl Pool {
+    /*
+     * Start the threads, 'queue' tasks can wait for a free thread
+     */
+    pub fn new(threads: usize, queue: usize) -> Pool {
+        let (sender, receiver) = mpsc::sync_channel::<Box<Task>>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..threads.max(1) {
+            let receiver = receiver.clone();
+
+            thread::spawn(move || {
+                loop {
+                    let task = match receiver.lock().unwrap().recv() {
+                        Ok(task) => task,
+                        Err(_) => return // The pool was dropped
+                    };
+
+                    // Keep the thread alive if the task panics
+                    if let Err(_) = panic::catch_unwind(AssertUnwindSafe(|| task.run())) {
+                        error!("pool", "a task panicked");
+                    }
+                }
+            });
+        }
+
+        Pool {
+            sender: sender
+        }
+    }
+
+    /*
+     * Queue a task, return false if the queue is full
+     */
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, f: F) -> bool {
+        match self.sender.try_send(Box::new(f)) {
+            Ok(_) => true,
+            Err(_) => false
+        }
+    }
+}
@@ -2,78 +2,164 @@
  * OS Utilities
  */
 
-use std::fs::File;
-use std::io::Read;
-use std::time::Duration;
-use std::thread;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::mem;
+use std::ptr;
+
+use libc;
 
 use common::{Error, Result};
 
-struct Stats {
-    user: f32,
-    nice: f32,
-    system: f32,
-    idle: f32
+/*
+ * Time spent by a CPU in each mode since boot, in clock ticks
+ * Guest time is also counted in user and nice time
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CpuTimes {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: u64,
+    pub irq: u64,
+    pub softirq: u64,
+    pub steal: u64, // Taken by the hypervisor when the host is itself a VM
+    pub guest: u64,
+    pub guest_nice: u64
+}
+
+impl CpuTimes {
+    fn total(&self) -> u64 {
+        self.user + self.nice + self.system + self.idle + self.iowait + self.irq + self.softirq + self.steal
+    }
+}
+
+/*
+ * Counters of all the CPUs, and of each online core
+ */
+#[derive(Clone, Debug, Default)]
+pub struct CpuStats {
+    pub all: CpuTimes,
+    pub cores: Vec<(u32, CpuTimes)> // By core number, offline cores are missing
 }
 
 /*
- * Given two stats separated by n number of milliseconds,
- * calculate the current CPU usage
+ * Share of the time spent in each mode between two readings, in percent
  */
-fn cpu_usage_percent(s1: Stats, s2: Stats) -> f32 {
-    let user = s2.user - s1.user;
-    let nice = s2.nice - s1.nice;
-    let system = s2.system - s1.system;
-    let idle = s2.idle - s1.idle;
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct CpuUsage {
+    pub user: f32, // With nice time
+    pub system: f32, // With interrupts
+    pub iowait: f32,
+    pub steal: f32,
+    pub idle: f32
+}
 
-    return ((user + nice + system) / idle) * 100.0;
+impl CpuUsage {
+    /*
+     * Time the CPU was not available for new work, waiting for I/O counts as idle
+     */
+    pub fn busy(&self) -> f32 {
+        self.user + self.system + self.steal
+    }
 }
 
 /*
- * Read /proc/stat into a Stats struct
+ * Compute the usage of a CPU from two readings of its counters
  */
-fn global_cpu_stats() -> Result<Stats> {
-    let mut f = try!(File::open("/proc/stat"));
-    let mut s = String::new();
+pub fn cpu_usage(before: &CpuTimes, after: &CpuTimes) -> CpuUsage {
+    let total = after.total().saturating_sub(before.total());
 
-    try!(f.read_to_string(&mut s));
+    // Nothing was accounted in between
+    if total == 0 {
+        return CpuUsage {
+            idle: 100.0,
+            ..CpuUsage::default()
+        };
+    }
 
-    let line = try!(s.lines().next().ok_or(Error::new("Invalid /proc/stat: no lines")));
-    let mut parts = line.split_whitespace();
+    let percent = |ticks: u64| -> f32 { ticks as f32 * 100.0 / total as f32 };
+    let delta = |b: u64, a: u64| -> u64 { a.saturating_sub(b) };
 
-    try!(parts.next().ok_or(Error::new("Invalid /proc/stat: mising cpu value")));
+    CpuUsage {
+        user: percent(delta(before.user, after.user) + delta(before.nice, after.nice)),
+        system: percent(delta(before.system, after.system) + delta(before.irq, after.irq) + delta(before.softirq, after.softirq)),
+        iowait: percent(delta(before.iowait, after.iowait)),
+        steal: percent(delta(before.steal, after.steal)),
+        idle: percent(delta(before.idle, after.idle))
+    }
+}
 
-    let cpu_user = try!(try!(parts.next().ok_or(Error::new("Invalid /proc/stat: mising user value")))
-        .parse::<f32>().ok().ok_or(Error::new("Invalid /proc/stat number")));
+/*
+ * Parse a cpu line of /proc/stat
+ * Kernels older than 2.6.33 have fewer fields, the missing ones are left at 0
+ */
+fn parse_cpu_line(line: &str) -> Result<CpuTimes> {
+    let mut values = Vec::new();
 
-    let cpu_nice = try!(try!(parts.next().ok_or(Error::new("Invalid /proc/stat: mising nice value")))
-        .parse::<f32>().ok().ok_or(Error::new("Invalid /proc/stat number")));
+    for field in line.split_whitespace().skip(1) {
+        match field.parse::<u64>() {
+            Ok(v) => values.push(v),
+            Err(_) => return Err(Error::new(format!("Invalid /proc/stat number '{}'", field)))
+        };
+    }
 
-    let cpu_system = try!(try!(parts.next().ok_or(Error::new("Invalid /proc/stat: mising system value")))
-        .parse::<f32>().ok().ok_or(Error::new("Invalid /proc/stat number")));
+    if values.len() < 4 {
+        return Err(Error::new("Invalid /proc/stat: missing user, nice, system or idle value"));
+    }
 
-    let cpu_idle = try!(try!(parts.next().ok_or(Error::new("Invalid /proc/stat: mising idle value")))
-        .parse::<f32>().ok().ok_or(Error::new("Invalid /proc/stat number")));
+    let value = |i: usize| -> u64 { values.get(i).cloned().unwrap_or(0) };
 
-    Ok(Stats {
-        user: cpu_user,
-        nice: cpu_nice,
-        system: cpu_system,
-        idle: cpu_idle
+    Ok(CpuTimes {
+        user: value(0),
+        nice: value(1),
+        system: value(2),
+        idle: value(3),
+        iowait: value(4),
+        irq: value(5),
+        softirq: value(6),
+        steal: value(7),
+        guest: value(8),
+        guest_nice: value(9)
     })
 }
 
 /*
- * Return the system's global CPU usage in percent
+ * Parse /proc/stat, the line of all the CPUs comes first
  */
-pub fn global_cpu_usage() -> Result<f32> {
-    let s1 = try!(global_cpu_stats());
-    thread::sleep(Duration::from_millis(250));
-    let s2 = try!(global_cpu_stats());
+pub fn parse_cpu_stats(s: &str) -> Result<CpuStats> {
+    let mut lines = s.lines().filter(|l| l.starts_with("cpu"));
+
+    let all = try!(lines.next().ok_or(Error::new("Invalid /proc/stat: no cpu lines")));
+    if !all.starts_with("cpu ") {
+        return Err(Error::new("Invalid /proc/stat: the first cpu line is not the one of all the CPUs"));
+    }
+
+    let mut stats = CpuStats {
+        all: try!(parse_cpu_line(all)),
+        cores: Vec::new()
+    };
+
+    for line in lines {
+        let core = match line.split_whitespace().next().and_then(|name| name[3..].parse::<u32>().ok()) {
+            Some(core) => core,
+            None => return Err(Error::new(format!("Invalid /proc/stat line '{}'", line)))
+        };
 
-    Ok(cpu_usage_percent(s1, s2))
+        stats.cores.push((core, try!(parse_cpu_line(line))));
+    }
+
+    Ok(stats)
 }
 
+/*
+ * Read /proc/stat, see parse_cpu_stats
+ */
+pub fn cpu_stats() -> Result<CpuStats> {
+    parse_cpu_stats(try!(read("/proc/stat")).as_str())
+}
 
 pub fn global_memory_info() -> Result<(f32, f32)> {
     let mut mem_total = 0.0;
@@ -109,3 +195,269 @@ pub fn global_memory_info() -> Result<(f32, f32)> {
 
     Ok(((mem_total - (mem_free + mem_buffers + mem_cached)) / 1024.0, mem_total / 1024.0))
 }
+
+/*
+ * Return the used and total swap space, in MiB
+ */
+pub fn global_swap_info() -> Result<(f32, f32)> {
+    let mut swap_total = 0.0;
+    let mut swap_free = 0.0;
+
+    let s = try!(read("/proc/meminfo"));
+
+    for line in s.lines() {
+        let mut parts = line.split_whitespace();
+        let key = try!(parts.next().ok_or(Error::new("Invalid /proc/meminfo")));
+        let val = try!(parts.next().ok_or(Error::new("Invalid /proc/meminfo")));
+
+        if key == "SwapTotal:" {
+            swap_total = try!(val.parse::<f32>().ok().ok_or(Error::new("Invalid /proc/meminfo number")));
+        }
+        if key == "SwapFree:" {
+            swap_free = try!(val.parse::<f32>().ok().ok_or(Error::new("Invalid /proc/meminfo number")));
+        }
+    }
+
+    Ok(((swap_total - swap_free) / 1024.0, swap_total / 1024.0))
+}
+
+/*
+ * Read a whole file from /proc or /sys
+ */
+fn read(path: &str) -> Result<String> {
+    let mut f = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(Error::new(format!("{}: {}", path, e)))
+    };
+
+    let mut s = String::new();
+    try!(f.read_to_string(&mut s));
+
+    Ok(s)
+}
+
+/*
+ * Resource usage of a process
+ */
+#[derive(Clone)]
+pub struct ProcessStats {
+    pub cpu_time: f64, // Seconds spent in user and kernel mode
+    pub rss: u64, // Resident memory, in bytes
+    pub uptime: f64 // Seconds since the process started
+}
+
+/*
+ * Parse /proc/<pid>/stat, return the CPU time of the process and its start time since boot, in clock ticks
+ * The command name can contain spaces and parentheses, the fields are counted from the last one
+ */
+pub fn parse_process_stat(s: &str) -> Result<(u64, u64)> {
+    let i = try!(s.rfind(')').ok_or(Error::new("Invalid /proc/<pid>/stat: missing command name")));
+    let fields: Vec<&str> = s[i + 1..].split_whitespace().collect();
+
+    // The state is the third field, user and system times are the 14th and 15th, start time the 22nd
+    let field = |n: usize| -> Result<u64> {
+        match fields.get(n - 3).and_then(|f| f.parse::<u64>().ok()) {
+            Some(v) => Ok(v),
+            None => Err(Error::new(format!("Invalid /proc/<pid>/stat: invalid field {}", n)))
+        }
+    };
+
+    Ok((try!(field(14)) + try!(field(15)), try!(field(22))))
+}
+
+/*
+ * Parse /proc/<pid>/statm, return the number of resident pages
+ */
+pub fn parse_statm(s: &str) -> Result<u64> {
+    match s.split_whitespace().nth(1).and_then(|f| f.parse::<u64>().ok()) {
+        Some(pages) => Ok(pages),
+        None => Err(Error::new("Invalid /proc/<pid>/statm"))
+    }
+}
+
+/*
+ * Return the number of seconds since the system booted
+ */
+pub fn uptime() -> Result<f64> {
+    let s = try!(read("/proc/uptime"));
+
+    match s.split_whitespace().next().and_then(|f| f.parse::<f64>().ok()) {
+        Some(uptime) => Ok(uptime),
+        None => Err(Error::new("Invalid /proc/uptime"))
+    }
+}
+
+/*
+ * Return the resource usage of a running process
+ */
+pub fn process_stats(pid: u32) -> Result<ProcessStats> {
+    let (cpu, start) = try!(parse_process_stat(try!(read(format!("/proc/{}/stat", pid).as_str())).as_str()));
+    let pages = try!(parse_statm(try!(read(format!("/proc/{}/statm", pid).as_str())).as_str()));
+
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+    Ok(ProcessStats {
+        cpu_time: cpu as f64 / ticks,
+        rss: pages * page_size,
+        uptime: (try!(uptime()) - start as f64 / ticks).max(0.0)
+    })
+}
+
+/*
+ * Parse /proc/<pid>/io, return the bytes read from and written to the storage layer
+ */
+pub fn parse_process_io(s: &str) -> Result<(u64, u64)> {
+    let mut read = None;
+    let mut written = None;
+
+    for line in s.lines() {
+        let mut parts = line.split_whitespace();
+
+        match (parts.next(), parts.next().and_then(|v| v.parse::<u64>().ok())) {
+            (Some("read_bytes:"), Some(v)) => read = Some(v),
+            (Some("write_bytes:"), Some(v)) => written = Some(v),
+            _ => {}
+        };
+    }
+
+    match (read, written) {
+        (Some(r), Some(w)) => Ok((r, w)),
+        _ => Err(Error::new("Invalid /proc/<pid>/io: missing read_bytes or write_bytes"))
+    }
+}
+
+/*
+ * Return the bytes read and written by a process, only readable by its owner and by root
+ */
+pub fn process_io(pid: u32) -> Result<(u64, u64)> {
+    parse_process_io(try!(read(format!("/proc/{}/io", pid).as_str())).as_str())
+}
+
+/*
+ * Return the groups of a user, primary and supplementary, from the user and group databases
+ */
+pub fn user_groups(uid: u32) -> Result<Vec<u32>> {
+    let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut found: *mut libc::passwd = ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16384];
+
+    let ret = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if ret != 0 {
+        return Err(Error::new(format!("Failed to look up user {}: {}", uid, io::Error::from_raw_os_error(ret))));
+    }
+
+    if found.is_null() {
+        return Err(Error::not_found(format!("Unknown user {}", uid)));
+    }
+
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+
+        if unsafe { libc::getgrouplist(pwd.pw_name, pwd.pw_gid, groups.as_mut_ptr(), &mut count) } >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+
+        // The list was too small, count is the number of groups of the user
+        if count as usize <= groups.len() {
+            return Err(Error::new(format!("Failed to list the groups of user {}", uid)));
+        }
+
+        groups.resize(count as usize, 0);
+    }
+}
+
+/*
+ * Counters of a network interface, as seen from the host
+ */
+#[derive(Clone)]
+pub struct InterfaceStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64
+}
+
+/*
+ * Return the counters of a network interface
+ */
+pub fn interface_stats(dev: &str) -> Result<InterfaceStats> {
+    let counter = |name: &str| -> Result<u64> {
+        let path = format!("/sys/class/net/{}/statistics/{}", dev, name);
+
+        match try!(read(path.as_str())).trim().parse::<u64>() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(Error::new(format!("Invalid {}", path)))
+        }
+    };
+
+    Ok(InterfaceStats {
+        rx_bytes: try!(counter("rx_bytes")),
+        tx_bytes: try!(counter("tx_bytes")),
+        rx_packets: try!(counter("rx_packets")),
+        tx_packets: try!(counter("tx_packets"))
+    })
+}
+
+/*
+ * Parse /proc/loadavg, return the load averages over 1, 5 and 15 minutes
+ */
+pub fn parse_loadavg(s: &str) -> Result<(f64, f64, f64)> {
+    let loads: Vec<f64> = s.split_whitespace().take(3).filter_map(|f| f.parse::<f64>().ok()).collect();
+
+    if loads.len() != 3 {
+        return Err(Error::new("Invalid /proc/loadavg"));
+    }
+
+    Ok((loads[0], loads[1], loads[2]))
+}
+
+pub fn loadavg() -> Result<(f64, f64, f64)> {
+    parse_loadavg(try!(read("/proc/loadavg")).as_str())
+}
+
+/*
+ * Return the release of the running kernel
+ */
+pub fn kernel_version() -> Result<String> {
+    Ok(try!(read("/proc/sys/kernel/osrelease")).trim().to_string())
+}
+
+/*
+ * Check if KVM can be used, the device is missing without hardware virtualization
+ */
+pub fn kvm_available() -> bool {
+    OpenOptions::new().read(true).write(true).open("/dev/kvm").is_ok()
+}
+
+/*
+ * Space of the filesystem holding a path, in bytes
+ */
+pub struct DiskUsage {
+    pub total: u64,
+    pub used: u64,
+    pub free: u64 // Available to unprivileged users
+}
+
+pub fn disk_usage(path: &str) -> Result<DiskUsage> {
+    let cpath = match CString::new(path) {
+        Ok(p) => p,
+        Err(_) => return Err(Error::invalid_argument(format!("Invalid path '{}'", path)))
+    };
+
+    let mut st: libc::statvfs = unsafe { mem::zeroed() };
+
+    if unsafe { libc::statvfs(cpath.as_ptr(), &mut st) } != 0 {
+        return Err(Error::new(format!("{}: {}", path, io::Error::last_os_error())));
+    }
+
+    let block = st.f_frsize as u64;
+
+    Ok(DiskUsage {
+        total: st.f_blocks as u64 * block,
+        used: (st.f_blocks as u64 - st.f_bfree as u64) * block,
+        free: st.f_bavail as u64 * block
+    })
+}
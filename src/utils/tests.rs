@@ -0,0 +1,258 @@
+use std::net::UdpSocket;
+use std::sync::{Arc, Barrier};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use super::frame::{self, Assembler};
+use super::pool::Pool;
+use super::system;
+
+/*
+ * frame
+ */
+#[test]
+fn frame_request() {
+    let req = frame::request("abc", "getvm test");
+    assert_eq!(req.as_slice(), b"#abc getvm test");
+
+    let (id, payload) = frame::parse_request(&req).unwrap();
+    assert_eq!(id.as_str(), "abc");
+    assert_eq!(payload, b"getvm test");
+
+    let (id, payload) = frame::parse_request(b"#abc").unwrap();
+    assert_eq!(id.as_str(), "abc");
+    assert_eq!(payload, b"");
+
+    // Datagrams without a valid identifier
+    assert!(frame::parse_request(b"getvm test").is_none());
+    assert!(frame::parse_request(b"# getvm").is_none());
+    assert!(frame::parse_request(b"#a/b getvm").is_none());
+
+    assert!(frame::request_id() != frame::request_id());
+}
+
+#[test]
+fn frame_chunks() {
+    let data: Vec<u8> = (0..frame::CHUNK_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+    let chunks = frame::chunks("abc", &data);
+
+    assert_eq!(chunks.len(), 3);
+    assert!(chunks[0].starts_with(b"#abc 1/3 "));
+    assert!(chunks[2].starts_with(b"#abc 3/3 "));
+
+    // Chunks can arrive in any order, and more than once
+    let mut assembler = Assembler::new();
+    assert!(!assembler.add(&frame::parse_chunk(&chunks[2]).unwrap()));
+    assert!(!assembler.add(&frame::parse_chunk(&chunks[0]).unwrap()));
+    assert!(!assembler.add(&frame::parse_chunk(&chunks[0]).unwrap()));
+    assert!(assembler.add(&frame::parse_chunk(&chunks[1]).unwrap()));
+    assert_eq!(assembler.into_bytes(), data);
+}
+
+#[test]
+fn frame_empty_response() {
+    let chunks = frame::chunks("abc", b"");
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].as_slice(), b"#abc 1/1 ");
+
+    let chunk = frame::parse_chunk(&chunks[0]).unwrap();
+    assert_eq!((chunk.seq, chunk.total, chunk.data), (1, 1, &b""[..]));
+}
+
+#[test]
+fn frame_invalid_chunks() {
+    assert!(frame::parse_chunk(b"#abc 0/1 data").is_none());
+    assert!(frame::parse_chunk(b"#abc 2/1 data").is_none());
+    assert!(frame::parse_chunk(b"#abc 1 data").is_none());
+    assert!(frame::parse_chunk(b"#abc 1/999999 data").is_none());
+    assert!(frame::parse_chunk(b"abc 1/1 data").is_none());
+}
+
+#[test]
+fn frame_exchange_retry() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // Drop the first request, answer the second one in two chunks
+    let t = thread::spawn(move || {
+        let mut buf = vec![0; frame::MAX_DATAGRAM];
+        server.recv_from(&mut buf).unwrap();
+
+        let (len, src) = server.recv_from(&mut buf).unwrap();
+        let (id, payload) = frame::parse_request(&buf[..len]).unwrap();
+        assert_eq!(payload, b"listvm");
+
+        let data = vec![b'x'; frame::CHUNK_SIZE + 1];
+        for chunk in frame::chunks(id.as_str(), &data).iter().rev() {
+            server.send_to(chunk, src).unwrap();
+        }
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let res = frame::exchange(&client, addr, frame::request_id().as_str(), "listvm", Duration::from_millis(200), 2).unwrap();
+    assert_eq!(res.len(), frame::CHUNK_SIZE + 1);
+
+    t.join().unwrap();
+}
+
+#[test]
+fn frame_exchange_timeout() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    assert!(frame::exchange(&client, server.local_addr().unwrap(), "abc", "listvm", Duration::from_millis(50), 1).is_err());
+}
+
+/*
+ * pool
+ */
+#[test]
+fn frame_bind() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (socket, dst) = frame::bind(addr).unwrap();
+    assert_eq!(dst, addr);
+    assert!(socket.local_addr().unwrap().is_ipv4());
+
+    // IPv6 may be missing from the host, only check the family when it is available
+    if let Ok(server) = UdpSocket::bind("[::1]:0") {
+        let (socket, _) = frame::bind(server.local_addr().unwrap()).unwrap();
+        assert!(socket.local_addr().unwrap().is_ipv6());
+    }
+}
+
+#[test]
+fn pool_runs_tasks_in_parallel() {
+    let pool = Pool::new(2, 4);
+    let barrier = Arc::new(Barrier::new(3));
+
+    // Both tasks must be running at the same time to pass the barrier
+    for _ in 0..2 {
+        let barrier = barrier.clone();
+        assert!(pool.execute(move || { barrier.wait(); }));
+    }
+
+    barrier.wait();
+}
+
+#[test]
+fn pool_rejects_when_full() {
+    let pool = Pool::new(1, 1);
+    let (tx, rx) = mpsc::channel::<()>();
+    let (started_tx, started_rx) = mpsc::channel();
+
+    // Keep the only thread busy
+    assert!(pool.execute(move || {
+        started_tx.send(()).unwrap();
+        let _ = rx.recv();
+    }));
+    started_rx.recv().unwrap();
+
+    assert!(pool.execute(|| {}));
+    assert!(!pool.execute(|| {}));
+
+    drop(tx);
+}
+
+#[test]
+fn pool_survives_panics() {
+    let pool = Pool::new(1, 2);
+    let (tx, rx) = mpsc::channel();
+
+    assert!(pool.execute(|| panic!("task failure")));
+    assert!(pool.execute(move || tx.send(()).unwrap()));
+
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+}
+
+/*
+ * system
+ */
+#[test]
+fn process_stat() {
+    // The command name of a QEMU process can contain spaces and parentheses
+    let stat = "4242 (qemu (vm web)) S 1 4242 4242 0 -1 4194560 51234 0 12 0 1500 320 0 0 20 0 3 0 987654 2147483648 65536 18446744073709551615 0 0 0 0 0 4096 0 0 0 0 17 2 0 0 0 0 0\n";
+    assert_eq!(system::parse_process_stat(stat).unwrap(), (1820, 987654));
+
+    assert!(system::parse_process_stat("4242 (qemu) S 1 4242").is_err());
+    assert!(system::parse_process_stat("garbage").is_err());
+
+    assert_eq!(system::parse_statm("524288 65536 1024 512 0 131072 0\n").unwrap(), 65536);
+    assert!(system::parse_statm("").is_err());
+}
+
+#[test]
+fn process_io() {
+    let io = "rchar: 323934931\nwchar: 323929600\nsyscr: 632687\nsyscw: 632675\nread_bytes: 4096000\nwrite_bytes: 323932160\ncancelled_write_bytes: 0\n";
+    assert_eq!(system::parse_process_io(io).unwrap(), (4096000, 323932160));
+
+    assert!(system::parse_process_io("rchar: 323934931\nwchar: 323929600\n").is_err());
+    assert!(system::parse_process_io("read_bytes: many\nwrite_bytes: 0\n").is_err());
+}
+
+#[test]
+fn user_groups() {
+    // Root always belongs to its primary group
+    assert!(system::user_groups(0).unwrap().contains(&0));
+}
+
+#[test]
+fn cpu_stats() {
+    let stats = system::parse_cpu_stats(include_str!("fixtures/proc_stat")).unwrap();
+    assert_eq!(stats.all.user, 1000);
+    assert_eq!(stats.all.iowait, 200);
+    assert_eq!(stats.all.steal, 300);
+    assert_eq!(stats.cores.len(), 2);
+    assert_eq!(stats.cores[1].0, 1);
+    assert_eq!(stats.cores[1].1.idle, 4000);
+
+    // Offline cores are missing
+    let stats = system::parse_cpu_stats(include_str!("fixtures/proc_stat_offline")).unwrap();
+    let cores: Vec<u32> = stats.cores.iter().map(|&(core, _)| core).collect();
+    assert_eq!(cores, vec![0, 2, 3]);
+
+    // Older kernels have no steal and guest time
+    let stats = system::parse_cpu_stats(include_str!("fixtures/proc_stat_2.6.0")).unwrap();
+    assert_eq!(stats.all.softirq, 100);
+    assert_eq!((stats.all.steal, stats.all.guest, stats.all.guest_nice), (0, 0, 0));
+
+    assert!(system::parse_cpu_stats("intr 114930548\n").is_err());
+    assert!(system::parse_cpu_stats("cpu0 1393 280 260 1863\n").is_err());
+    assert!(system::parse_cpu_stats("cpu  4705 356\n").is_err());
+    assert!(system::parse_cpu_stats("cpu  4705 356 584 3699\ncpux 1 2 3 4\n").is_err());
+}
+
+#[test]
+fn cpu_usage() {
+    let before = system::parse_cpu_stats(include_str!("fixtures/proc_stat")).unwrap();
+    let after = system::parse_cpu_stats(include_str!("fixtures/proc_stat_loaded")).unwrap();
+
+    // Busy and idle times are shares of the total, stolen time is busy
+    let usage = system::cpu_usage(&before.all, &after.all);
+    assert_eq!(usage, system::CpuUsage {
+        user: 65.0,
+        system: 15.0,
+        iowait: 5.0,
+        steal: 5.0,
+        idle: 10.0
+    });
+    assert_eq!(usage.busy(), 85.0);
+
+    assert_eq!(system::cpu_usage(&before.cores[0].1, &after.cores[0].1).busy(), 100.0);
+    assert_eq!(system::cpu_usage(&before.cores[1].1, &after.cores[1].1).busy(), 70.0);
+
+    // Since boot
+    let usage = system::cpu_usage(&system::CpuTimes::default(), &before.all);
+    assert_eq!((usage.busy(), usage.idle), (18.0, 80.0));
+
+    // No time elapsed
+    assert_eq!(system::cpu_usage(&after.all, &after.all).idle, 100.0);
+}
+
+#[test]
+fn loadavg() {
+    assert_eq!(system::parse_loadavg("0.52 0.58 0.59 2/611 12345\n").unwrap(), (0.52, 0.58, 0.59));
+    assert!(system::parse_loadavg("0.52 0.58\n").is_err());
+}
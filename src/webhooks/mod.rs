@@ -0,0 +1,391 @@
+/*
+ * Webhooks - Post the events to the HTTP endpoints declared in the configuration
+ *
+ * Each target gets its own subscription to the events, drained into a bounded queue so that a slow
+ * target never holds the bus, and its own thread posting them. Payloads are the JSON representation
+ * of the events, sent with the following headers:
+ *
+ *     X-OLVM-Event: <event type>
+ *     X-OLVM-Delivery: <unique ID of the delivery, kept across retries>
+ *     X-OLVM-Signature: sha256=<hex-encoded HMAC-SHA256 of the body, keyed with the target secret>
+ *
+ * Any 2xx status is a success. Failed deliveries are queued and retried with an exponential backoff,
+ * the ones still failing after the last retry are appended to the dead-letter log as JSON lines,
+ * along with the events dropped when a queue is full.
+ */
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, Condvar};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use openssl::ssl::{SslConnector, SslMethod};
+use serde_json;
+use uuid::{Uuid, UuidVersion};
+
+use common::{Context, Result, Error};
+use config::{Webhook, Webhooks};
+use events::Event;
+use auth;
+
+/*
+ * Maximum delay between two retries, in seconds
+ */
+const MAX_BACKOFF: u64 = 3600;
+
+/*
+ * Delay to wait for events when no retry is pending, in seconds
+ */
+const IDLE: u64 = 60;
+
+/*
+ * An event to be sent to a target
+ */
+struct Delivery {
+    id: String,
+    event: Event,
+    body: String,
+    attempts: u32,
+    next: Instant // Time of the next retry
+}
+
+impl Delivery {
+    fn new(event: Event) -> Result<Delivery> {
+        let body = try!(serde_json::to_string(&event));
+
+        Ok(Delivery {
+            id: Uuid::new(UuidVersion::Random).unwrap().hyphenated().to_string(),
+            event: event,
+            body: body,
+            attempts: 0,
+            next: Instant::now()
+        })
+    }
+}
+
+/*
+ * Deliveries received for a target, waiting to be posted
+ */
+struct Pending {
+    deliveries: Mutex<VecDeque<Delivery>>,
+    ready: Condvar
+}
+
+impl Pending {
+    fn new() -> Pending {
+        Pending {
+            deliveries: Mutex::new(VecDeque::new()),
+            ready: Condvar::new()
+        }
+    }
+
+    /*
+     * Queue a delivery, or give it back when 'max' deliveries are already waiting
+     */
+    fn push(&self, delivery: Delivery, max: usize) -> ::std::result::Result<(), Delivery> {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        if deliveries.len() >= max {
+            return Err(delivery);
+        }
+
+        deliveries.push_back(delivery);
+        self.ready.notify_one();
+
+        Ok(())
+    }
+
+    /*
+     * Take the waiting deliveries, waiting at most 'timeout' for one to come
+     */
+    fn take(&self, timeout: Duration) -> Vec<Delivery> {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        if deliveries.is_empty() {
+            deliveries = self.ready.wait_timeout(deliveries, timeout).unwrap().0;
+        }
+
+        deliveries.drain(..).collect()
+    }
+}
+
+/*
+ * Give up on a delivery, and keep a trace of it in the dead-letter log
+ */
+fn dead_letter(conf: &Webhooks, target: &Webhook, delivery: &Delivery, e: &Error) {
+    error!("webhooks", "giving up on delivery after {} attempts: {}", delivery.attempts, e; target = target.name, delivery = delivery.id, event = delivery.event.kind);
+
+    let path = match conf.dead_letter {
+        Some(ref path) => path,
+        None => return
+    };
+
+    let line = json!({
+        "target": target.name,
+        "delivery": delivery.id,
+        "attempts": delivery.attempts,
+        "error": e.to_string(),
+        "event": delivery.event
+    });
+
+    let res = OpenOptions::new().create(true).append(true).open(path.as_str())
+        .and_then(|mut f| f.write_all(format!("{}\n", line).as_bytes()));
+
+    if let Err(e) = res {
+        error!("webhooks", "failed to write to the dead-letter log: {}", e; path = path);
+    }
+}
+
+/*
+ * Sends the events to a target, and keeps the failed deliveries until they are retried
+ */
+struct Worker<'a> {
+    conf: &'a Webhooks,
+    target: &'a Webhook,
+    retries: VecDeque<Delivery>
+}
+
+/*
+ * Split an URL into its scheme (true for HTTPS), host, port and path
+ */
+fn parse_url(url: &str) -> Result<(bool, String, u16, String)> {
+    let (https, rest) = if url.starts_with("https://") {
+        (true, &url[8..])
+    }
+    else if url.starts_with("http://") {
+        (false, &url[7..])
+    }
+    else {
+        return Err(Error::invalid_argument(format!("Invalid webhook URL '{}', expected http:// or https://", url)));
+    };
+
+    let (addr, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/")
+    };
+
+    let (host, port) = match addr.rfind(':') {
+        Some(i) => match addr[i + 1..].parse::<u16>() {
+            Ok(port) => (&addr[..i], port),
+            Err(_) => return Err(Error::invalid_argument(format!("Invalid port in webhook URL '{}'", url)))
+        },
+        None => (addr, if https { 443 } else { 80 })
+    };
+
+    if host.len() == 0 {
+        return Err(Error::invalid_argument(format!("Missing host in webhook URL '{}'", url)));
+    }
+
+    Ok((https, host.to_string(), port, path.to_string()))
+}
+
+/*
+ * Send a request and return the status code of the response
+ */
+fn exchange<S: Read + Write>(stream: &mut S, request: &str) -> Result<u32> {
+    try!(stream.write_all(request.as_bytes()));
+
+    let mut status = String::new();
+    try!(BufReader::new(stream).read_line(&mut status));
+
+    match status.split(' ').nth(1).and_then(|c| c.parse::<u32>().ok()) {
+        Some(code) => Ok(code),
+        None => Err(Error::network(format!("Invalid HTTP status line '{}'", status.trim())))
+    }
+}
+
+/*
+ * Post a delivery to a target
+ */
+fn post(target: &Webhook, timeout: Duration, delivery: &Delivery) -> Result<()> {
+    let (https, host, port, path) = try!(parse_url(target.url.as_str()));
+
+    let request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+        X-OLVM-Event: {}\r\nX-OLVM-Delivery: {}\r\nX-OLVM-Signature: sha256={}\r\nConnection: close\r\n\r\n{}",
+        path, host, delivery.body.len(), delivery.event.kind, delivery.id, auth::signature::hmac(target.secret.as_str(), delivery.body.as_bytes()), delivery.body);
+
+    let addr = match try!((host.as_str(), port).to_socket_addrs()).next() {
+        Some(addr) => addr,
+        None => return Err(Error::network(format!("Failed to resolve '{}'", host)))
+    };
+
+    let stream = match TcpStream::connect_timeout(&addr, timeout) {
+        Ok(stream) => stream,
+        Err(e) => return Err(Error::network(format!("{}: {}", addr, e)))
+    };
+
+    try!(stream.set_read_timeout(Some(timeout)));
+    try!(stream.set_write_timeout(Some(timeout)));
+
+    let code = if https {
+        let connector = try!(SslConnector::builder(SslMethod::tls())).build();
+
+        let mut stream = match connector.connect(host.as_str(), stream) {
+            Ok(stream) => stream,
+            Err(e) => return Err(Error::network(format!("{}: TLS handshake failed: {}", host, e)))
+        };
+
+        try!(exchange(&mut stream, request.as_str()))
+    }
+    else {
+        let mut stream = stream;
+        try!(exchange(&mut stream, request.as_str()))
+    };
+
+    if code < 200 || code >= 300 {
+        return Err(Error::network(format!("HTTP error {}", code)));
+    }
+
+    Ok(())
+}
+
+impl<'a> Worker<'a> {
+    fn new(conf: &'a Webhooks, target: &'a Webhook) -> Worker<'a> {
+        Worker {
+            conf: conf,
+            target: target,
+            retries: VecDeque::new()
+        }
+    }
+
+    /*
+     * Retry the deliveries whose backoff has elapsed
+     */
+    fn retry(&mut self) {
+        let now = Instant::now();
+        let (due, waiting): (VecDeque<Delivery>, VecDeque<Delivery>) = self.retries.drain(..).partition(|d| d.next <= now);
+
+        self.retries = waiting;
+
+        for delivery in due {
+            self.attempt(delivery);
+        }
+    }
+
+    /*
+     * Delay until the next retry, if any
+     */
+    fn next_retry(&self) -> Option<Duration> {
+        let now = Instant::now();
+
+        self.retries.iter().map(|d| d.next).min().map(|next| {
+            if next > now {
+                next - now
+            }
+            else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /*
+     * Post a delivery, queue it for a retry if it fails
+     */
+    fn attempt(&mut self, mut delivery: Delivery) {
+        delivery.attempts += 1;
+
+        let e = match post(self.target, Duration::from_secs(self.conf.timeout), &delivery) {
+            Ok(_) => return,
+            Err(e) => e
+        };
+
+        if delivery.attempts > self.conf.retries {
+            return self.dead_letter(&delivery, &e);
+        }
+        if self.retries.len() >= self.conf.queue {
+            return self.dead_letter(&delivery, &Error::new(format!("Retry queue full, last error: {}", e)));
+        }
+
+        let backoff = self.conf.backoff.saturating_mul(1 << cmp::min(delivery.attempts - 1, 16));
+        delivery.next = Instant::now() + Duration::from_secs(cmp::min(backoff, MAX_BACKOFF));
+
+        warn!("webhooks", "delivery failed (attempt {}): {}", delivery.attempts, e; target = self.target.name, delivery = delivery.id, event = delivery.event.kind);
+        self.retries.push_back(delivery);
+    }
+
+    fn dead_letter(&self, delivery: &Delivery, e: &Error) {
+        dead_letter(self.conf, self.target, delivery, e)
+    }
+}
+
+/*
+ * Queue the events of a target as they are published, until the program stops
+ */
+fn receive(ctx: &Context, conf: &Webhooks, target: &Webhook, pending: &Pending) {
+    loop {
+        let receiver = ctx.events.subscribe(target.events.clone());
+
+        for event in receiver.iter() {
+            let delivery = match Delivery::new(event) {
+                Ok(delivery) => delivery,
+                Err(e) => {
+                    error!("webhooks", "failed to encode event: {}", e; target = target.name);
+                    continue;
+                }
+            };
+
+            if let Err(delivery) = pending.push(delivery, conf.queue) {
+                dead_letter(conf, target, &delivery, &Error::new("Delivery queue full, the target is too slow or unreachable"));
+            }
+        }
+
+        warn!("webhooks", "events were dropped by the bus, subscribing again"; target = target.name);
+    }
+}
+
+/*
+ * Post the queued events to a target, until the program stops
+ */
+fn send(conf: &Webhooks, target: &Webhook, pending: &Pending) {
+    let mut worker = Worker::new(conf, target);
+
+    loop {
+        let wait = worker.next_retry().unwrap_or(Duration::from_secs(IDLE));
+
+        for delivery in pending.take(wait) {
+            worker.attempt(delivery);
+        }
+
+        worker.retry();
+    }
+}
+
+/*
+ * Start a thread for each webhook target
+ */
+pub fn run(ctx: Arc<Context>) {
+    let conf = match ctx.conf.webhooks {
+        Some(ref conf) => conf,
+        None => return
+    };
+
+    for (i, t) in conf.target.iter().enumerate() {
+        if let Err(e) = parse_url(t.url.as_str()) {
+            error!("webhooks", "{}, ignoring this target", e; target = t.name);
+            continue;
+        }
+
+        let pending = Arc::new(Pending::new());
+
+        let rctx = ctx.clone();
+        let rpending = pending.clone();
+        thread::spawn(move || {
+            let conf = rctx.conf.webhooks.as_ref().unwrap();
+            receive(rctx.as_ref(), conf, &conf.target[i], rpending.as_ref());
+        });
+
+        let rctx = ctx.clone();
+        thread::spawn(move || {
+            let conf = rctx.conf.webhooks.as_ref().unwrap();
+            send(conf, &conf.target[i], pending.as_ref());
+        });
+    }
+}
+
+/*
+ * Tests
+ */
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,213 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use serde_json;
+use serde_json::value::Value;
+
+use auth;
+use config::{Webhook, Webhooks};
+use events::Event;
+
+use super::{Delivery, Pending, Worker, parse_url};
+
+/*
+ * Request received by the stub
+ */
+struct Received {
+    line: String,
+    headers: Vec<(String, String)>,
+    body: String
+}
+
+impl Received {
+    fn header(&self, name: &str) -> &str {
+        self.headers.iter().find(|h| h.0.eq_ignore_ascii_case(name)).map(|h| h.1.as_str()).unwrap_or("")
+    }
+}
+
+/*
+ * Local HTTP server answering the requests with the specified statuses, in order
+ * Return its URL, and the requests it received
+ */
+fn stub(statuses: &[u32]) -> (String, Receiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hooks/olvm", listener.local_addr().unwrap());
+    let statuses = statuses.to_vec();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for status in statuses {
+            let mut socket = listener.accept().unwrap().0;
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+
+            let mut headers = Vec::new();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+
+                let header = header.trim();
+                if header.len() == 0 {
+                    break;
+                }
+
+                let i = header.find(':').unwrap();
+                headers.push((header[..i].to_string(), header[i + 1..].trim().to_string()));
+            }
+
+            let length = headers.iter().find(|h| h.0.eq_ignore_ascii_case("content-length")).unwrap().1.parse::<usize>().unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            socket.write_all(format!("HTTP/1.1 {} Stub\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).unwrap();
+
+            tx.send(Received {
+                line: line.trim().to_string(),
+                headers: headers,
+                body: String::from_utf8(body).unwrap()
+            }).unwrap();
+        }
+    });
+
+    (url, rx)
+}
+
+fn conf(url: &str, retries: u32, dead_letter: Option<String>) -> Webhooks {
+    Webhooks {
+        target: vec![Webhook {
+            name: String::from("test"),
+            url: url.to_string(),
+            secret: String::from("secret"),
+            events: Vec::new()
+        }],
+        retries: retries,
+        backoff: 0,
+        timeout: 5,
+        queue: 10,
+        dead_letter: dead_letter
+    }
+}
+
+fn event() -> Event {
+    Event::new("vm.created", "test", json!({"backend": "kvm"}))
+}
+
+fn delivery() -> Delivery {
+    Delivery::new(event()).unwrap()
+}
+
+fn recv(rx: &Receiver<Received>) -> Received {
+    rx.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+#[test]
+fn urls() {
+    assert_eq!(parse_url("http://localhost:8080/hooks").unwrap(), (false, String::from("localhost"), 8080, String::from("/hooks")));
+    assert_eq!(parse_url("https://example.com").unwrap(), (true, String::from("example.com"), 443, String::from("/")));
+    assert_eq!(parse_url("http://example.com/a/b").unwrap(), (false, String::from("example.com"), 80, String::from("/a/b")));
+
+    assert!(parse_url("ftp://example.com").is_err());
+    assert!(parse_url("http://example.com:port/").is_err());
+    assert!(parse_url("http:///hooks").is_err());
+}
+
+#[test]
+fn signed_delivery() {
+    let (url, rx) = stub(&[200]);
+    let conf = conf(url.as_str(), 0, None);
+    let mut worker = Worker::new(&conf, &conf.target[0]);
+
+    worker.attempt(delivery());
+    assert_eq!(worker.retries.len(), 0);
+
+    let req = recv(&rx);
+    assert_eq!(req.line.as_str(), "POST /hooks/olvm HTTP/1.1");
+    assert_eq!(req.header("X-OLVM-Event"), "vm.created");
+    assert_eq!(req.header("X-OLVM-Delivery").len(), 36);
+
+    // The signature lets the target check the payload comes from us
+    let signature = format!("sha256={}", auth::signature::hmac("secret", req.body.as_bytes()));
+    assert_eq!(req.header("X-OLVM-Signature"), signature.as_str());
+
+    let body: Value = serde_json::from_str(req.body.as_str()).unwrap();
+    assert_eq!(body["kind"].as_str(), Some("vm.created"));
+    assert_eq!(body["object"].as_str(), Some("test"));
+    assert_eq!(body["data"]["backend"].as_str(), Some("kvm"));
+}
+
+#[test]
+fn retry_failed_delivery() {
+    let (url, rx) = stub(&[503, 200]);
+    let conf = conf(url.as_str(), 3, None);
+    let mut worker = Worker::new(&conf, &conf.target[0]);
+
+    worker.attempt(delivery());
+    assert_eq!(worker.retries.len(), 1);
+    assert_eq!(worker.next_retry(), Some(Duration::from_secs(0)));
+
+    worker.retry();
+    assert_eq!(worker.retries.len(), 0);
+    assert_eq!(worker.next_retry(), None);
+
+    // Retries keep the delivery ID
+    let first = recv(&rx);
+    let second = recv(&rx);
+    assert_eq!(first.header("X-OLVM-Delivery"), second.header("X-OLVM-Delivery"));
+    assert_eq!(first.body, second.body);
+}
+
+#[test]
+fn dead_letter() {
+    let path = env::temp_dir().join(format!("olvm-test-{}-webhooks.log", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let (url, rx) = stub(&[500, 500]);
+    let conf = conf(url.as_str(), 1, Some(path.to_str().unwrap().to_string()));
+    let mut worker = Worker::new(&conf, &conf.target[0]);
+
+    worker.attempt(delivery());
+    worker.retry();
+    assert_eq!(worker.retries.len(), 0);
+
+    recv(&rx);
+    recv(&rx);
+
+    let mut log = String::new();
+    File::open(&path).unwrap().read_to_string(&mut log).unwrap();
+    let _ = fs::remove_file(&path);
+
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 1);
+
+    let line: Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(line["target"].as_str(), Some("test"));
+    assert_eq!(line["attempts"].as_u64(), Some(2));
+    assert_eq!(line["event"]["kind"].as_str(), Some("vm.created"));
+    assert!(line["error"].as_str().unwrap().contains("500"));
+}
+
+#[test]
+fn pending_queue() {
+    let pending = Pending::new();
+
+    pending.push(delivery(), 2).ok().unwrap();
+    pending.push(delivery(), 2).ok().unwrap();
+
+    // Full, the delivery is given back to be dead-lettered
+    let dropped = pending.push(delivery(), 2).unwrap_err();
+    assert_eq!(dropped.attempts, 0);
+
+    assert_eq!(pending.take(Duration::from_secs(5)).len(), 2);
+    assert_eq!(pending.take(Duration::from_millis(10)).len(), 0);
+
+    pending.push(delivery(), 2).ok().unwrap();
+    assert_eq!(pending.take(Duration::from_millis(10)).len(), 1);
+}